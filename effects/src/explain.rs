@@ -0,0 +1,183 @@
+use anyhow::anyhow;
+
+use crate::{interpreter::EvalError, parser::ParseError, scanner::ScanError, typer::TypeError};
+
+/// The long-form explanation of an error code, for `--explain` and `:explain`.
+pub struct Entry {
+    pub code: &'static str,
+    pub title: &'static str,
+    pub explanation: &'static str,
+    /// REPL lines, one per line, the last of which fails with this code; `None` if no line
+    /// fails with it every time
+    pub example: Option<&'static str>,
+}
+
+/// Every error code, in order. E10xx are scanner errors, E11xx parser errors, E12xx type
+/// errors and E13xx runtime errors.
+pub const CATALOG: &[Entry] = &[
+    Entry {
+        code: "E1001",
+        title: "unexpected symbol",
+        explanation: "The line has a character that can't start a token. Names are letters, digits and `_`, and start with a letter or `_`; the only symbols are `\\`, `.`, `=`, `==`, `+`, `(`, `)`, `{`, `}`, `[`, `]`, `;`, `,`, `|`, `::` and `->`, and `#` starts a comment. Integers can be added with `+` but have no other arithmetic.",
+        example: Some("return 2 * 3"),
+    },
+    Entry {
+        code: "E1002",
+        title: "integer literal out of range",
+        explanation: "Integers are 64-bit, so a literal can be at most 9223372036854775807. Adding past that is a runtime error, E1304.",
+        example: Some("return 99999999999999999999"),
+    },
+    Entry {
+        code: "E1101",
+        title: "unexpected token",
+        explanation: "The parser expected one kind of token and found another. The message says what it expected. Values and computations are separate: `return v` makes a value a computation, `let x = c1 in c2` runs a computation to get a value, and only values can be arguments.",
+        example: Some("if true then return 1 return 2"),
+    },
+    Entry {
+        code: "E1102",
+        title: "unexpected end of line",
+        explanation: "The line ended in the middle of a program, as after `let x = return 1 in` with no body or `(1, 2` with no `)`.",
+        example: Some("return (1, 2"),
+    },
+    Entry {
+        code: "E1103",
+        title: "list match without one case of each kind",
+        explanation: "`match v with [] -> c1 | x :: xs -> c2` needs exactly one `[]` case and one `::` case, in either order.",
+        example: Some("match [1] with [] -> return 0 | [] -> return 1"),
+    },
+    Entry {
+        code: "E1201",
+        title: "undeclared variable",
+        explanation: "The variable isn't bound by an enclosing `\\`, `let`, `match` or handler case, or defined by an earlier top-level `let`. Operations are declared with `effect op` instead.",
+        example: Some("return x"),
+    },
+    Entry {
+        code: "E1202",
+        title: "infinite type",
+        explanation: "Inference needed a type to contain itself, as when a variable is applied to itself in `\\x. x x`. Types are finite, so no type works.",
+        example: Some("return \\x. x x"),
+    },
+    Entry {
+        code: "E1203",
+        title: "mismatched types",
+        explanation: "Two types that have to be equal aren't, such as an `if` condition that isn't a `Bool`, an operand of `+` that isn't an `Int` or two uses of an operation with different types. An operation's signature is fixed by its first use.",
+        example: Some("1 + true"),
+    },
+    Entry {
+        code: "E1204",
+        title: "unhandled operation",
+        explanation: "An operation is called where no handler deals with it. Every operation except `print`, which the host handles, has to run inside `with h handle c` for a handler `h` with a case for it; `yield` is also handled by `for` and by `:list`, `:sum` and `:take`.",
+        example: Some("effect get\nget ()"),
+    },
+    Entry {
+        code: "E1301",
+        title: "interrupted",
+        explanation: "Ctrl-C stopped the computation before it finished. A top-level `let` that was interrupted doesn't define anything.",
+        example: None,
+    },
+    Entry {
+        code: "E1302",
+        title: "operation reached the host",
+        explanation: "An operation that the host doesn't handle reached the top level while the program ran. The typechecker rules this out, so it means there's a bug in the typechecker.",
+        example: None,
+    },
+    Entry {
+        code: "E1303",
+        title: "wrong kind of value",
+        explanation: "A value of one kind was used where another was needed. Within a program the typechecker rules this out, but `:sum` only finds out that a generator yields something other than integers as it runs.",
+        example: Some(":sum yield true"),
+    },
+    Entry {
+        code: "E1304",
+        title: "integer overflow",
+        explanation: "Integers are 64-bit, and an addition, or the running total of `:sum`, went past 9223372036854775807. The computation stops there rather than wrapping around.",
+        example: Some("9223372036854775807 + 1"),
+    },
+    Entry {
+        code: "E1305",
+        title: "computations nested too deeply",
+        explanation: "A computation had to finish another first, as the first computation of a `let` or the one a handler handles, more than 10000 times over, usually through recursion that never stops. A call in tail position, such as the body of `let rec f x = f x`, doesn't nest, so it runs until Ctrl-C stops it. Recursion that does nest needs a case that ends it.",
+        example: Some("let rec f x = let y = f x in return y in f 1"),
+    },
+];
+
+/// The code of `err`, if it's one of the errors that has one.
+pub fn code(err: &anyhow::Error) -> Option<&'static str> {
+    if let Some(e) = err.downcast_ref::<ScanError>() {
+        Some(e.code())
+    } else if let Some(e) = err.downcast_ref::<ParseError>() {
+        Some(e.code())
+    } else if let Some(e) = err.downcast_ref::<TypeError>() {
+        Some(e.code())
+    } else {
+        err.downcast_ref::<EvalError>().map(EvalError::code)
+    }
+}
+
+/// The entry for `code`, written out for the terminal.
+pub fn explain(code: &str) -> anyhow::Result<String> {
+    let entry = CATALOG
+        .iter()
+        .find(|entry| entry.code.eq_ignore_ascii_case(code.trim()))
+        .ok_or_else(|| anyhow!("no error has the code `{}`", code.trim()))?;
+    let mut text = format!(
+        "{}: {}\n\n{}\n",
+        entry.code,
+        entry.title,
+        fill(entry.explanation, 80)
+    );
+    if let Some(example) = entry.example {
+        text.push_str("\nFor example:\n\n");
+        for line in example.lines() {
+            text.push_str(&format!("    λ {line}\n"));
+        }
+    }
+    Ok(text)
+}
+
+/// `text` broken at spaces into lines of at most `width` characters, where the words allow.
+fn fill(text: &str, width: usize) -> String {
+    let mut filled = String::new();
+    let mut column = 0;
+    for word in text.split(' ') {
+        let len = word.chars().count();
+        if column > 0 && column + 1 + len > width {
+            filled.push('\n');
+            column = 0;
+        } else if column > 0 {
+            filled.push(' ');
+            column += 1;
+        }
+        filled.push_str(word);
+        column += len;
+    }
+    filled
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{run_line, session::Session};
+
+    #[test]
+    fn examples_fail_with_their_codes() {
+        for (i, entry) in CATALOG.iter().enumerate() {
+            assert!(
+                CATALOG[..i].iter().all(|other| other.code < entry.code),
+                "{} is out of order",
+                entry.code
+            );
+            let Some(example) = entry.example else {
+                continue;
+            };
+            let mut session = Session::with_prelude();
+            let lines: Vec<_> = example.lines().collect();
+            let (last, setup) = lines.split_last().unwrap();
+            for line in setup {
+                run_line(line.to_string(), &mut session).unwrap();
+            }
+            let err = run_line(last.to_string(), &mut session).expect_err(entry.code);
+            assert_eq!(code(&err), Some(entry.code), "{}", err);
+        }
+    }
+}
@@ -0,0 +1,108 @@
+use std::io;
+
+use crate::{
+    host,
+    interpreter::{add, expected, interpret, substitute_comp},
+    parser::{Computation, Constant, Value},
+};
+
+/// Runs the generator `comp`, passing every value it yields to `consume`, which decides
+/// whether to resume it. Returns the generator's final value, or `None` if it was cut short.
+pub fn drive(
+    comp: &Computation,
+    mut consume: impl FnMut(Value) -> anyhow::Result<bool>,
+) -> anyhow::Result<Option<Value>> {
+    let mut comp = comp.clone();
+    loop {
+        match interpret(&comp)? {
+            Computation::Return(v) => return Ok(Some(v)),
+            Computation::OpCall { op, param, y, c } if op == "yield" => {
+                if !consume(param)? {
+                    return Ok(None);
+                }
+                comp = substitute_comp(&c, &y, &Value::Constant(Constant::Unit));
+            }
+            Computation::OpCall { op, param, y, c } => {
+                let v = host::perform(&op, &param, &mut io::stdout())?;
+                comp = substitute_comp(&c, &y, &v);
+            }
+            _ => unreachable!("interpret returns terminal computations"),
+        }
+    }
+}
+
+/// Every value `comp` yields, in order.
+pub fn to_list(comp: &Computation) -> anyhow::Result<Vec<Value>> {
+    take(comp, usize::MAX)
+}
+
+/// The first `n` values `comp` yields; the rest of the generator is never run.
+pub fn take(comp: &Computation, n: usize) -> anyhow::Result<Vec<Value>> {
+    let mut values = Vec::new();
+    if n > 0 {
+        drive(comp, |v| {
+            values.push(v);
+            Ok(values.len() < n)
+        })?;
+    }
+    Ok(values)
+}
+
+/// The sum of the integers `comp` yields, or an error if it overflows.
+pub fn sum(comp: &Computation) -> anyhow::Result<i64> {
+    let mut total: i64 = 0;
+    drive(comp, |v| match v {
+        Value::Constant(Constant::Int(n)) => {
+            total = add(total, n)?;
+            Ok(true)
+        }
+        v => expected("an integer", &v),
+    })?;
+    Ok(total)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashSet;
+
+    use super::*;
+    use crate::{interpreter::EvalError, parser::Parser, prelude::OPERATIONS, scanner::Scanner};
+
+    fn parse(s: &str) -> Computation {
+        let ops: HashSet<String> = OPERATIONS.iter().map(|op| op.to_string()).collect();
+        Parser::parse(Scanner::scan(s.to_string()).unwrap(), &ops).unwrap()
+    }
+
+    #[test]
+    fn folds() {
+        let gen = parse("let _ = yield 1 in let _ = yield 2 in yield 3");
+        assert_eq!(sum(&gen).unwrap(), 6);
+        assert_eq!(
+            take(&gen, 2).unwrap(),
+            vec![
+                Value::Constant(Constant::Int(1)),
+                Value::Constant(Constant::Int(2))
+            ]
+        );
+        let err = sum(&parse("let _ = yield 9223372036854775807 in yield 1")).unwrap_err();
+        assert_eq!(
+            err.downcast_ref::<EvalError>(),
+            Some(&EvalError::Overflow { a: i64::MAX, b: 1 })
+        );
+    }
+
+    #[test]
+    fn for_loop() {
+        let gen = parse(
+            "for b in (let _ = yield true in let _ = yield false in yield true) \
+             do if b then yield b else return ()",
+        );
+        assert_eq!(
+            to_list(&gen).unwrap(),
+            vec![
+                Value::Constant(Constant::True),
+                Value::Constant(Constant::True)
+            ]
+        );
+    }
+}
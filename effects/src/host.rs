@@ -0,0 +1,37 @@
+use std::io::Write;
+
+use anyhow::bail;
+
+use crate::{
+    interpreter::{interpret, substitute_comp, EvalError},
+    parser::{Computation, Constant, Value},
+};
+
+/// Operations handled by the host once nothing in the program handles them.
+pub const OPERATIONS: &[&str] = &["print"];
+
+/// Runs `comp`, handling host operations as they reach the top level.
+pub fn run(comp: &Computation, out: &mut dyn Write) -> anyhow::Result<Value> {
+    let mut comp = comp.clone();
+    loop {
+        match interpret(&comp)? {
+            Computation::Return(v) => return Ok(v),
+            Computation::OpCall { op, param, y, c } => {
+                let v = perform(&op, &param, out)?;
+                comp = substitute_comp(&c, &y, &v);
+            }
+            _ => unreachable!("interpret returns terminal computations"),
+        }
+    }
+}
+
+/// Handles the host operation `op`, returning the value to resume with.
+pub fn perform(op: &str, param: &Value, out: &mut dyn Write) -> anyhow::Result<Value> {
+    match op {
+        "print" => {
+            writeln!(out, "{}", param)?;
+            Ok(Value::Constant(Constant::Unit))
+        }
+        _ => bail!(EvalError::Unhandled { op: op.to_string() }),
+    }
+}
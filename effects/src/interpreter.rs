@@ -1,155 +1,633 @@
-use std::collections::HashSet;
+use std::{
+    collections::HashSet,
+    fmt,
+    sync::atomic::{AtomicBool, Ordering},
+    thread,
+};
 
-use crate::parser::{Computation, Constant, Value};
+use anyhow::bail;
 
-pub fn interpret(comp: &Computation) -> Value {
+use crate::parser::{Computation, Constant, Handler, Prim, Value};
+
+/// Set by Ctrl-C in the REPL. Evaluation gives up at its next step while it's set.
+pub static INTERRUPTED: AtomicBool = AtomicBool::new(false);
+
+/// Why a computation stopped before returning.
+#[derive(Debug, Clone, PartialEq)]
+pub enum EvalError {
+    /// the computation was interrupted by Ctrl-C
+    Interrupted,
+    /// `op` reached the top level, and the host doesn't handle it either
+    Unhandled { op: String },
+    /// `found` is where a value of another kind, like `a list`, was needed
+    Expected {
+        expected: &'static str,
+        found: Value,
+    },
+    /// `a + b` doesn't fit in 64 bits
+    Overflow { a: i64, b: i64 },
+    /// computations nested more than `depth` deep, as in recursion that isn't a tail call
+    TooDeep { depth: usize },
+}
+
+impl EvalError {
+    /// The stable code that `--explain` looks up.
+    pub fn code(&self) -> &'static str {
+        match self {
+            EvalError::Interrupted => "E1301",
+            EvalError::Unhandled { .. } => "E1302",
+            EvalError::Expected { .. } => "E1303",
+            EvalError::Overflow { .. } => "E1304",
+            EvalError::TooDeep { .. } => "E1305",
+        }
+    }
+}
+
+impl fmt::Display for EvalError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            EvalError::Interrupted => write!(f, "interrupted"),
+            EvalError::Unhandled { op } => write!(f, "operation `{}` is not handled", op),
+            EvalError::Expected { expected, found } => {
+                write!(f, "expected {}, got `{}`", expected, found)
+            }
+            EvalError::Overflow { a, b } => {
+                write!(f, "`{} + {}` is too big for a 64-bit integer", a, b)
+            }
+            EvalError::TooDeep { depth } => write!(
+                f,
+                "computations nested more than {} deep; only calls in tail position can recurse without limit",
+                depth
+            ),
+        }
+    }
+}
+
+impl std::error::Error for EvalError {}
+
+/// The error for `found` where a value of the kind `expected` was needed.
+pub fn expected<T>(expected: &'static str, found: &Value) -> anyhow::Result<T> {
+    bail!(EvalError::Expected {
+        expected,
+        found: found.clone(),
+    })
+}
+
+/// `a + b`, or the error if it overflows.
+pub fn add(a: i64, b: i64) -> anyhow::Result<i64> {
+    a.checked_add(b)
+        .ok_or_else(|| EvalError::Overflow { a, b }.into())
+}
+
+/// How deeply computations may nest, as in the first computation of a `let` or the one a
+/// handler handles. Calls in tail position don't nest, so they can loop forever.
+pub const MAX_DEPTH: usize = 10_000;
+/// Stack for each level of nesting, generous enough for debug builds.
+const STACK_PER_LEVEL: usize = 16 * 1024;
+
+/// Evaluates `comp` to a terminal computation: either `return v` or an operation call
+/// `op(v; y.c)` that no enclosing handler deals with. Runs on a thread with enough stack for
+/// `MAX_DEPTH`, so deep recursion is an error rather than an overflow.
+pub fn interpret(comp: &Computation) -> anyhow::Result<Computation> {
+    thread::scope(|scope| {
+        thread::Builder::new()
+            .stack_size(MAX_DEPTH * STACK_PER_LEVEL)
+            .spawn_scoped(scope, || eval(comp, 0))?
+            .join()
+            .unwrap_or_else(|panic| std::panic::resume_unwind(panic))
+    })
+}
+
+/// What's left after a step of evaluation.
+enum Step {
+    /// a terminal computation
+    Done(Computation),
+    /// a computation in tail position, which `eval` loops on rather than recursing into
+    Next(Computation),
+}
+
+/// `interpret`, `depth` computations deep.
+fn eval(comp: &Computation, depth: usize) -> anyhow::Result<Computation> {
+    if depth > MAX_DEPTH {
+        bail!(EvalError::TooDeep { depth: MAX_DEPTH });
+    }
+    let mut next = step(comp, depth)?;
+    loop {
+        match next {
+            Step::Done(comp) => return Ok(comp),
+            Step::Next(comp) => next = step(&comp, depth)?,
+        }
+    }
+}
+
+fn step(comp: &Computation, depth: usize) -> anyhow::Result<Step> {
+    if INTERRUPTED.load(Ordering::Relaxed) {
+        bail!(EvalError::Interrupted);
+    }
     match comp {
-        Computation::Return(_) => todo!(),
-        Computation::OpCall { op, param } => todo!(),
-        Computation::Seq { x, c1, c2 } => seq(x, c1, c2),
+        Computation::Return(_) | Computation::OpCall { .. } => Ok(Step::Done(comp.clone())),
+        Computation::Seq { x, c1, c2 } => seq(x, c1, c2, depth),
         Computation::If { v, c1, c2 } => r#if(v, c1, c2),
         Computation::App { v1, v2 } => app(v1, v2),
-        Computation::Handling { with, handle } => todo!(),
+        Computation::Handling { with, handle } => handling(with, handle, depth),
+        Computation::LetRec { f, x, c1, c2 } => {
+            Ok(Step::Next(substitute_comp(c2, f, &unfold(f, x, c1))))
+        }
+        Computation::MatchList { v, c1, x, xs, c2 } => match_list(v, c1, x, xs, c2),
+        Computation::MatchPair { v, x, y, c } => match_pair(v, x, y, c),
+        Computation::Prim { op, v1, v2 } => prim(*op, v1, v2).map(Step::Done),
+    }
+}
+
+/// let rec f x = c === \x. let rec f x = c in c
+fn unfold(f: &String, x: &String, c: &Computation) -> Value {
+    Value::Fun {
+        x: x.to_string(),
+        c: Box::new(Computation::LetRec {
+            f: f.to_string(),
+            x: x.to_string(),
+            c1: Box::new(c.clone()),
+            c2: Box::new(c.clone()),
+        }),
     }
 }
 
-fn app(v1: &Value, v2: &Value) -> Value {
+fn match_list(
+    v: &Value,
+    c1: &Computation,
+    x: &String,
+    xs: &String,
+    c2: &Computation,
+) -> anyhow::Result<Step> {
+    match v {
+        Value::Nil => Ok(Step::Next(c1.clone())),
+        Value::Cons(head, tail) => {
+            let (binders, c2) = rename_binders(&[x, xs], c2, &fv_val(head));
+            let c2 = substitute_comp(&c2, &binders[0], head);
+            Ok(Step::Next(substitute_comp(&c2, &binders[1], tail)))
+        }
+        _ => expected("a list", v),
+    }
+}
+
+fn match_pair(v: &Value, x: &String, y: &String, c: &Computation) -> anyhow::Result<Step> {
+    if let Value::Pair(v1, v2) = v {
+        let (binders, c) = rename_binders(&[x, y], c, &fv_val(v1));
+        let c = substitute_comp(&c, &binders[0], v1);
+        Ok(Step::Next(substitute_comp(&c, &binders[1], v2)))
+    } else {
+        expected("a pair", v)
+    }
+}
+
+fn prim(op: Prim, v1: &Value, v2: &Value) -> anyhow::Result<Computation> {
+    let v = match (op, v1, v2) {
+        (Prim::Add, Value::Constant(Constant::Int(a)), Value::Constant(Constant::Int(b))) => {
+            Constant::Int(add(*a, *b)?)
+        }
+        (Prim::Add, Value::Constant(Constant::Int(_)), v) | (Prim::Add, v, _) => {
+            return expected("an integer", v)
+        }
+        (Prim::Eq, _, _) if v1 == v2 => Constant::True,
+        (Prim::Eq, _, _) => Constant::False,
+    };
+    Ok(Computation::Return(Value::Constant(v)))
+}
+
+fn app(v1: &Value, v2: &Value) -> anyhow::Result<Step> {
     if let Value::Fun { x, c } = v1 {
-        interpret(&substitute_comp(c, x, v2))
+        Ok(Step::Next(substitute_comp(c, x, v2)))
     } else {
-        unreachable!()
+        expected("a function", v1)
     }
 }
 
-fn seq(x: &String, c1: &Computation, c2: &Computation) -> Value {
-    match c1 {
-        Computation::Return(v) => interpret(&substitute_comp(c2, x, v)),
-        Computation::OpCall { op, param } => {
-            // op(x;y.c) === do y <- op v in c
-            // Maybe leave it in here??
-            todo!()
+fn seq(x: &String, c1: &Computation, c2: &Computation, depth: usize) -> anyhow::Result<Step> {
+    match eval(c1, depth + 1)? {
+        Computation::Return(v) => Ok(Step::Next(substitute_comp(c2, x, &v))),
+        // do x <- op(v; y.c) in c2 === op(v; y.do x <- c in c2)
+        Computation::OpCall { op, param, y, c } => {
+            let (y, c) = avoid_capture(&y, &c, &fv_comp(c2));
+            Ok(Step::Done(Computation::OpCall {
+                op,
+                param,
+                y,
+                c: Box::new(Computation::Seq {
+                    x: x.to_string(),
+                    c1: Box::new(c),
+                    c2: Box::new(c2.clone()),
+                }),
+            }))
         }
-        _ => unreachable!("uh"),
+        _ => unreachable!("interpret returns terminal computations"),
     }
 }
 
-fn r#if(v: &Value, c1: &Computation, c2: &Computation) -> Value {
+fn r#if(v: &Value, c1: &Computation, c2: &Computation) -> anyhow::Result<Step> {
     match v {
-        Value::Constant(Constant::True) => interpret(&c1),
-        Value::Constant(Constant::False) => interpret(&c2),
-        _ => unreachable!("uh... :|"),
+        Value::Constant(Constant::True) => Ok(Step::Next(c1.clone())),
+        Value::Constant(Constant::False) => Ok(Step::Next(c2.clone())),
+        _ => expected("a boolean", v),
+    }
+}
+
+fn handling(with: &Value, handle: &Computation, depth: usize) -> anyhow::Result<Step> {
+    let Value::Handler(h) = with else {
+        return expected("a handler", with);
+    };
+    match eval(handle, depth + 1)? {
+        Computation::Return(v) => match &h.ret {
+            Some((x, c)) => Ok(Step::Next(substitute_comp(c, x, &v))),
+            None => Ok(Step::Done(Computation::Return(v))),
+        },
+        // with h handle op(v; y.c) === c_op[x := v, k := \y.with h handle c]
+        Computation::OpCall { op, param, y, c } => match h.ops.get(&op) {
+            Some((x, k, c_op)) => {
+                let cont = Value::Fun {
+                    x: y,
+                    c: Box::new(Computation::Handling {
+                        with: with.clone(),
+                        handle: c,
+                    }),
+                };
+                // rename `k` first so substituting `param` can't capture it
+                let (k, c_op) = avoid_capture(k, c_op, &fv_val(&param));
+                let c_op = substitute_comp(&c_op, x, &param);
+                Ok(Step::Next(substitute_comp(&c_op, &k, &cont)))
+            }
+            // with h handle op(v; y.c) === op(v; y.with h handle c) when h doesn't handle op
+            None => {
+                let (y, c) = avoid_capture(&y, &c, &fv_val(with));
+                Ok(Step::Done(Computation::OpCall {
+                    op,
+                    param,
+                    y,
+                    c: Box::new(Computation::Handling {
+                        with: with.clone(),
+                        handle: Box::new(c),
+                    }),
+                }))
+            }
+        },
+        _ => unreachable!("interpret returns terminal computations"),
     }
 }
 
-fn substitute_comp(comp: &Computation, old: &String, new: &Value) -> Computation {
+pub fn substitute_comp(comp: &Computation, old: &String, new: &Value) -> Computation {
+    Substitution::new(old, new).comp(comp)
+}
+
+/// [old := new], renaming binders that would capture free variables of `new`
+struct Substitution<'a> {
+    old: &'a String,
+    new: &'a Value,
+    fv_new: HashSet<&'a String>,
+}
+
+impl<'a> Substitution<'a> {
+    fn new(old: &'a String, new: &'a Value) -> Self {
+        Self {
+            old,
+            new,
+            fv_new: fv_val(new),
+        }
+    }
+
+    fn comp(&self, comp: &Computation) -> Computation {
+        match comp {
+            Computation::Return(v) => Computation::Return(self.val(v)),
+            Computation::OpCall { op, param, y, c } => {
+                let ([y], c) = self.under([y], c);
+                Computation::OpCall {
+                    op: op.to_string(),
+                    param: self.val(param),
+                    y,
+                    c: Box::new(c),
+                }
+            }
+            Computation::Seq { x, c1, c2 } => {
+                let ([x], c2) = self.under([x], c2);
+                Computation::Seq {
+                    x,
+                    c1: Box::new(self.comp(c1)),
+                    c2: Box::new(c2),
+                }
+            }
+            Computation::If { v, c1, c2 } => Computation::If {
+                v: self.val(v),
+                c1: Box::new(self.comp(c1)),
+                c2: Box::new(self.comp(c2)),
+            },
+            Computation::App { v1, v2 } => Computation::App {
+                v1: self.val(v1),
+                v2: self.val(v2),
+            },
+            Computation::Handling { with, handle } => Computation::Handling {
+                with: self.val(with),
+                handle: Box::new(self.comp(handle)),
+            },
+            Computation::LetRec { f, .. } if f == self.old => comp.clone(),
+            Computation::LetRec { f, x, c1, c2 } => {
+                // `f` is bound in both bodies, so rename it in both at once
+                let (f, c1, c2) = if self.fv_new.contains(f) {
+                    let mut avoid = self.fv_new.clone();
+                    avoid.extend(fv_comp(c1));
+                    avoid.extend(fv_comp(c2));
+                    avoid.insert(x);
+                    let fresh = Value::Var(fresh_name(f, &avoid));
+                    (
+                        fresh.to_string(),
+                        substitute_comp(c1, f, &fresh),
+                        substitute_comp(c2, f, &fresh),
+                    )
+                } else {
+                    (f.to_string(), *c1.clone(), *c2.clone())
+                };
+                let ([x], c1) = self.under([x], &c1);
+                Computation::LetRec {
+                    f,
+                    x,
+                    c1: Box::new(c1),
+                    c2: Box::new(self.comp(&c2)),
+                }
+            }
+            Computation::MatchList { v, c1, x, xs, c2 } => {
+                let ([x, xs], c2) = self.under([x, xs], c2);
+                Computation::MatchList {
+                    v: self.val(v),
+                    c1: Box::new(self.comp(c1)),
+                    x,
+                    xs,
+                    c2: Box::new(c2),
+                }
+            }
+            Computation::MatchPair { v, x, y, c } => {
+                let ([x, y], c) = self.under([x, y], c);
+                Computation::MatchPair {
+                    v: self.val(v),
+                    x,
+                    y,
+                    c: Box::new(c),
+                }
+            }
+            Computation::Prim { op, v1, v2 } => Computation::Prim {
+                op: *op,
+                v1: self.val(v1),
+                v2: self.val(v2),
+            },
+        }
+    }
+
+    fn val(&self, v: &Value) -> Value {
+        match v {
+            Value::Var(x) if x == self.old => self.new.clone(),
+            Value::Var(_) | Value::Constant(_) | Value::Nil => v.clone(),
+            Value::Cons(v1, v2) => Value::Cons(Box::new(self.val(v1)), Box::new(self.val(v2))),
+            Value::Pair(v1, v2) => Value::Pair(Box::new(self.val(v1)), Box::new(self.val(v2))),
+            Value::Fun { x, c } => {
+                let ([x], c) = self.under([x], c);
+                Value::Fun { x, c: Box::new(c) }
+            }
+            Value::Handler(h) => {
+                let ret = h.ret.as_ref().map(|(x, c)| {
+                    let ([x], c) = self.under([x], c);
+                    (x, c)
+                });
+                let ops = h
+                    .ops
+                    .iter()
+                    .map(|(op, (x, k, c))| {
+                        let ([x, k], c) = self.under([x, k], c);
+                        (op.to_string(), (x, k, c))
+                    })
+                    .collect();
+                Value::Handler(Box::new(Handler { ret, ops }))
+            }
+        }
+    }
+
+    /// Substitutes into `c`, which sits under all of `binders`.
+    fn under<const N: usize>(
+        &self,
+        binders: [&String; N],
+        c: &Computation,
+    ) -> ([String; N], Computation) {
+        if binders.contains(&self.old) {
+            (binders.map(|x| x.to_string()), c.clone())
+        } else if binders.iter().any(|x| self.fv_new.contains(x)) {
+            let (binders, c) = rename_binders(&binders, c, &self.fv_new);
+            (binders, self.comp(&c))
+        } else {
+            (binders.map(|x| x.to_string()), self.comp(c))
+        }
+    }
+}
+
+/// Renames whichever of `binders` are in `avoid`, keeping them distinct from each other.
+fn rename_binders<const N: usize>(
+    binders: &[&String; N],
+    c: &Computation,
+    avoid: &HashSet<&String>,
+) -> ([String; N], Computation) {
+    let mut c = c.clone();
+    let mut renamed = binders.map(|x| x.to_string());
+    for i in 0..N {
+        let mut avoid = avoid.clone();
+        let others: Vec<String> = renamed
+            .iter()
+            .enumerate()
+            .filter(|(j, _)| *j != i)
+            .map(|(_, x)| x.to_string())
+            .collect();
+        avoid.extend(others.iter());
+        let (x, c_new) = avoid_capture(&renamed[i], &c, &avoid);
+        renamed[i] = x;
+        c = c_new;
+    }
+    (renamed, c)
+}
+
+/// Renames the binder `x` of `c` if it is in `avoid`.
+fn avoid_capture(x: &String, c: &Computation, avoid: &HashSet<&String>) -> (String, Computation) {
+    if avoid.contains(x) {
+        let mut avoid = avoid.clone();
+        let fv_body = fv_comp(c);
+        avoid.extend(fv_body);
+        let new_name = fresh_name(x, &avoid);
+        let c = substitute_comp(c, x, &Value::Var(new_name.clone()));
+        (new_name, c)
+    } else {
+        (x.to_string(), c.clone())
+    }
+}
+
+fn fresh_name(x: &String, avoid: &HashSet<&String>) -> String {
+    // Just add 1s until we have a new variable name!
+    let mut new_name = format!("{x}1");
+    while avoid.contains(&new_name) {
+        new_name = format!("{new_name}1");
+    }
+    new_name
+}
+
+fn fv_comp(comp: &Computation) -> HashSet<&String> {
     match comp {
-        Computation::Return(v) => Computation::Return(substitute_val(v, old, new)),
-        Computation::OpCall { op, param } => todo!(),
-        Computation::Seq { x, c1, c2 } => todo!(),
-        Computation::If { v, c1, c2 } => todo!(),
-        Computation::App { v1, v2 } => todo!(),
-        Computation::Handling { with, handle } => todo!(),
-        // Expr::Var(x) if x == old => new.clone(),
-        // Expr::Var(_) | Expr::Constant(_) => comp.clone(),
-        // Expr::App { e1, e2 } => Expr::App {
-        //     e1: Box::new(substitute(e1, old, new)),
-        //     e2: Box::new(substitute(e2, old, new)),
-        // },
-        // Expr::Abs { x, t: _, e: _ } if x == old => comp.clone(),
-        // Expr::Abs { x, t, e } => {
-        //     let fv_body = fv(new);
-        //     if fv_body.contains(x) {
-        //         // Just add 1s until we have a new variable name!
-        //         let mut new_name = format!("{x}1");
-        //         while fv_body.contains(&new_name) {
-        //             new_name = format!("{new_name}1");
-        //         }
-        //         // TODO: what if substitution fails here too
-        //         let e = substitute(e, x, &Expr::Var(new_name.clone()));
-        //         Expr::Abs {
-        //             x: new_name,
-        //             t: t.clone(),
-        //             e: Box::new(substitute(&e, old, new)),
-        //         }
-        //     } else {
-        //         Expr::Abs {
-        //             x: x.to_string(),
-        //             t: t.clone(),
-        //             e: Box::new(substitute(e, old, new)),
-        //         }
-        //     }
-        // }
-        // Expr::If { e1, e2, e3 } => Expr::If {
-        //     e1: Box::new(substitute(e1, old, new)),
-        //     e2: Box::new(substitute(e2, old, new)),
-        //     e3: Box::new(substitute(e3, old, new)),
-        // },
-    }
-}
-
-fn substitute_val(v: &Value, old: &String, new: &Value) -> Value {
+        Computation::Return(v) => fv_val(v),
+        Computation::OpCall { op: _, param, y, c } => {
+            let mut set = fv_comp(c);
+            set.remove(y);
+            set.union(&fv_val(param)).cloned().collect()
+        }
+        Computation::Seq { x, c1, c2 } => {
+            let mut set = fv_comp(c2);
+            set.remove(x);
+            set.union(&fv_comp(c1)).cloned().collect()
+        }
+        Computation::If { v, c1, c2 } => {
+            let fv1 = fv_comp(c1);
+            let fv2 = fv_comp(c2);
+            fv1.union(&fv2)
+                .cloned()
+                .collect::<HashSet<_>>()
+                .union(&fv_val(v))
+                .cloned()
+                .collect()
+        }
+        Computation::App { v1, v2 } => {
+            let fv1 = fv_val(v1);
+            let fv2 = fv_val(v2);
+            fv1.union(&fv2).cloned().collect()
+        }
+        Computation::Handling { with, handle } => {
+            let fv1 = fv_val(with);
+            let fv2 = fv_comp(handle);
+            fv1.union(&fv2).cloned().collect()
+        }
+        Computation::LetRec { f, x, c1, c2 } => {
+            let mut fv1 = fv_comp(c1);
+            fv1.remove(f);
+            fv1.remove(x);
+            let mut fv2 = fv_comp(c2);
+            fv2.remove(f);
+            fv1.union(&fv2).cloned().collect()
+        }
+        Computation::MatchList { v, c1, x, xs, c2 } => {
+            let mut set = fv_comp(c2);
+            set.remove(x);
+            set.remove(xs);
+            set.extend(fv_comp(c1));
+            set.extend(fv_val(v));
+            set
+        }
+        Computation::MatchPair { v, x, y, c } => {
+            let mut set = fv_comp(c);
+            set.remove(x);
+            set.remove(y);
+            set.extend(fv_val(v));
+            set
+        }
+        Computation::Prim { op: _, v1, v2 } => {
+            let fv1 = fv_val(v1);
+            let fv2 = fv_val(v2);
+            fv1.union(&fv2).cloned().collect()
+        }
+    }
+}
+
+fn fv_val(v: &Value) -> HashSet<&String> {
     match v {
-        Value::Var(_) => todo!(),
-        Value::Constant(_) => todo!(),
-        Value::Fun { x, c } => todo!(),
-        Value::Handler(_) => todo!(),
-    }
-}
-
-// fn fv(e: &Expr) -> HashSet<&String> {
-//     match e {
-//         Expr::Var(x) => HashSet::from([x]),
-//         Expr::Constant(_) => HashSet::new(),
-//         Expr::App { e1, e2 } => {
-//             let fv1 = fv(e1);
-//             let fv2 = fv(e2);
-//             fv1.union(&fv2).cloned().collect()
-//         }
-//         Expr::Abs { x, t: _, e } => {
-//             let mut set = fv(e);
-//             set.remove(x);
-//             set
-//         }
-//         Expr::If { e1, e2, e3 } => {
-//             let fv1 = fv(e1);
-//             let fv2 = fv(e2);
-//             let fv3 = fv(e3);
-//             fv1.union(&fv2)
-//                 .cloned()
-//                 .collect::<HashSet<_>>()
-//                 .union(&fv3)
-//                 .cloned()
-//                 .collect()
-//         }
-//     }
-// }
-
-// #[cfg(test)]
-// mod tests {
-//     use super::*;
-
-//     #[test]
-//     fn substitution() {
-//         // (\x.x y)[y := x] => (\x1.x1 x)
-//         let e = Expr::Abs {
-//             x: "x".into(),
-//             t: crate::parser::Type::Bool,
-//             e: Box::new(Expr::App {
-//                 e1: Box::new(Expr::Var("x".into())),
-//                 e2: Box::new(Expr::Var("y".into())),
-//             }),
-//         };
-//         let e2 = substitute(&e, &"y".into(), &Expr::Var("x".into()));
-//         assert_eq!(
-//             e2,
-//             Expr::Abs {
-//                 x: "x1".into(),
-//                 t: crate::parser::Type::Bool,
-//                 e: Box::new(Expr::App {
-//                     e1: Box::new(Expr::Var("x1".into())),
-//                     e2: Box::new(Expr::Var("x".into()))
-//                 })
-//             }
-//         );
-//     }
-// }
+        Value::Var(x) => HashSet::from([x]),
+        Value::Constant(_) | Value::Nil => HashSet::new(),
+        Value::Cons(v1, v2) | Value::Pair(v1, v2) => {
+            let fv1 = fv_val(v1);
+            let fv2 = fv_val(v2);
+            fv1.union(&fv2).cloned().collect()
+        }
+        Value::Fun { x, c } => {
+            let mut set = fv_comp(c);
+            set.remove(x);
+            set
+        }
+        Value::Handler(h) => {
+            let mut set = HashSet::new();
+            if let Some((x, c)) = &h.ret {
+                let mut fv = fv_comp(c);
+                fv.remove(x);
+                set.extend(fv);
+            }
+            for (x, k, c) in h.ops.values() {
+                let mut fv = fv_comp(c);
+                fv.remove(x);
+                fv.remove(k);
+                set.extend(fv);
+            }
+            set
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use super::*;
+
+    #[test]
+    fn substitution() {
+        // (\x.x y)[y := x] => (\x1.x1 x)
+        let e = Value::Fun {
+            x: "x".into(),
+            c: Box::new(Computation::App {
+                v1: Value::Var("x".into()),
+                v2: Value::Var("y".into()),
+            }),
+        };
+        let e2 = Substitution::new(&"y".into(), &Value::Var("x".into())).val(&e);
+        assert_eq!(
+            e2,
+            Value::Fun {
+                x: "x1".into(),
+                c: Box::new(Computation::App {
+                    v1: Value::Var("x1".into()),
+                    v2: Value::Var("x".into())
+                })
+            }
+        );
+    }
+
+    #[test]
+    fn handles_op() {
+        // with handler { get(x; k) -> k true } handle (let a = get () in return a)
+        let h = Handler {
+            ret: None,
+            ops: HashMap::from([(
+                "get".to_string(),
+                (
+                    "x".to_string(),
+                    "k".to_string(),
+                    Computation::App {
+                        v1: Value::Var("k".into()),
+                        v2: Value::Constant(Constant::True),
+                    },
+                ),
+            )]),
+        };
+        let c = Computation::Handling {
+            with: Value::Handler(Box::new(h)),
+            handle: Box::new(Computation::Seq {
+                x: "a".into(),
+                c1: Box::new(Computation::OpCall {
+                    op: "get".into(),
+                    param: Value::Constant(Constant::Unit),
+                    y: "y".into(),
+                    c: Box::new(Computation::Return(Value::Var("y".into()))),
+                }),
+                c2: Box::new(Computation::Return(Value::Var("a".into()))),
+            }),
+        };
+        assert_eq!(
+            interpret(&c).unwrap(),
+            Computation::Return(Value::Constant(Constant::True))
+        );
+    }
+}
@@ -1,36 +1,90 @@
-use parser::Parser;
-use scanner::Scanner;
+use std::{io, sync::atomic::Ordering};
 
+use anyhow::anyhow;
+use interpreter::INTERRUPTED;
+use parser::Value;
+use rustyline::error::ReadlineError;
+use session::Session;
+
+mod explain;
+mod generator;
+mod host;
 mod interpreter;
 mod parser;
+mod prelude;
+mod pretty;
 mod scanner;
-// mod typer;
+mod session;
+mod typer;
 
 fn main() {
+    let mut args = std::env::args().skip_while(|arg| arg != "--explain");
+    if args.next().is_some() {
+        match args
+            .next()
+            .ok_or_else(|| anyhow!("`--explain` needs an error code, as in `--explain E1203`"))
+            .and_then(|code| explain::explain(&code))
+        {
+            Ok(text) => print!("{text}"),
+            Err(e) => {
+                eprintln!("{}", e);
+                std::process::exit(1);
+            }
+        }
+        return;
+    }
+
+    let mut session = Session::with_prelude();
+    ctrlc::set_handler(|| INTERRUPTED.store(true, Ordering::Relaxed))
+        .expect("failed to install the Ctrl-C handler");
     let mut rl = rustyline::DefaultEditor::new().unwrap();
     loop {
         let readline = rl.readline("λ ");
         match readline {
+            Ok(line) if line.trim() == ":quit" => break,
             Ok(line) => {
                 rl.add_history_entry(&line).unwrap();
-                if let Err(e) = type_line(line) {
-                    eprintln!("{}", e);
+                INTERRUPTED.store(false, Ordering::Relaxed);
+                if let Err(e) = run_line(line, &mut session) {
+                    match explain::code(&e) {
+                        Some(code) => eprintln!("error[{code}]: {}", e),
+                        None => eprintln!("{}", e),
+                    }
                 }
             }
-            Err(_) => {
-                println!("Connection terminated");
+            // Ctrl-C at the prompt just clears the line
+            Err(ReadlineError::Interrupted) => continue,
+            Err(ReadlineError::Eof) => break,
+            Err(e) => {
+                eprintln!("{}", e);
                 break;
             }
         }
     }
+    println!("Connection terminated");
 }
 
-fn type_line(line: String) -> anyhow::Result<()> {
-    let tokens = Scanner::scan(line)?;
-    let expr = Parser::parse(tokens)?;
-    // let ty = Typer::default().typecheck(&expr)?;
-    let out = interpreter::interpret(&expr);
-    println!("{}", out.to_string());
-    // println!("{} : {}", out.to_string(), ty.to_string());
+fn run_line(line: String, session: &mut Session) -> anyhow::Result<()> {
+    if let Some(code) = line.strip_prefix(":explain ") {
+        print!("{}", explain::explain(code)?);
+    } else if let Some(rest) = line.strip_prefix(":list ") {
+        let values = generator::to_list(&session.parse(rest)?)?;
+        println!("{}", list_to_string(&values));
+    } else if let Some(rest) = line.strip_prefix(":sum ") {
+        println!("{}", generator::sum(&session.parse(rest)?)?);
+    } else if let Some(rest) = line.strip_prefix(":take ") {
+        let rest = rest.trim_start();
+        let (n, rest) = rest.split_once(' ').unwrap_or((rest, ""));
+        let n = n.parse()?;
+        let values = generator::take(&session.parse(rest)?, n)?;
+        println!("{}", list_to_string(&values));
+    } else if let Some((out, ty)) = session.exec(&line, &mut io::stdout())? {
+        println!("{} : {}", out, ty);
+    }
     Ok(())
 }
+
+fn list_to_string(values: &[Value]) -> String {
+    let values: Vec<_> = values.iter().map(|v| v.to_string()).collect();
+    format!("[{}]", values.join(", "))
+}
@@ -1,41 +1,50 @@
-use std::collections::HashMap;
+use std::{
+    collections::{HashMap, HashSet},
+    fmt,
+};
 
-use anyhow::{anyhow, bail, Ok};
+use anyhow::{bail, Ok};
 
 use crate::scanner::Token;
 
-#[derive(Debug)]
+#[derive(Debug, PartialEq, Clone)]
 pub enum Value {
     Var(String),
     Constant(Constant),
-    Fun { x: String, c: Box<Computation> },
+    Fun {
+        x: String,
+        c: Box<Computation>,
+    },
     Handler(Box<Handler>),
+    /// []
+    Nil,
+    /// v1 :: v2
+    Cons(Box<Value>, Box<Value>),
+    /// (v1, v2)
+    Pair(Box<Value>, Box<Value>),
 }
 
-#[derive(Debug)]
+#[derive(Debug, PartialEq, Clone)]
 pub struct Handler {
     /// optional tuple (var, body)
-    ret: Option<(String, Computation)>,
+    pub ret: Option<(String, Computation)>,
 
     /// map from op name to (var, Continuation, body)
-    ops: HashMap<String, (String, String, Computation)>,
+    pub ops: HashMap<String, (String, String, Computation)>,
 }
 
-#[derive(Debug)]
+#[derive(Debug, PartialEq, Clone)]
 pub enum Computation {
     /// return v
     Return(Value),
 
     /// op(v;y.c)
-    // OpCall {
-    //     name: String,
-    //     param: Value,
-    //     ret_name: String,
-    //     continuation: Box<Computation>,
-    // },
-
-    /// op ()
-    OpCall { op: String, param: Option<Value> },
+    OpCall {
+        op: String,
+        param: Value,
+        y: String,
+        c: Box<Computation>,
+    },
 
     /// let x = c1 in c2
     Seq {
@@ -59,140 +68,433 @@ pub enum Computation {
         with: Value,
         handle: Box<Computation>,
     },
+
+    /// let rec f x = c1 in c2
+    LetRec {
+        f: String,
+        x: String,
+        c1: Box<Computation>,
+        c2: Box<Computation>,
+    },
+
+    /// match v with [] -> c1 | x :: xs -> c2
+    MatchList {
+        v: Value,
+        c1: Box<Computation>,
+        x: String,
+        xs: String,
+        c2: Box<Computation>,
+    },
+
+    /// match v with (x, y) -> c
+    MatchPair {
+        v: Value,
+        x: String,
+        y: String,
+        c: Box<Computation>,
+    },
+
+    /// v1 + v2, v1 == v2
+    Prim { op: Prim, v1: Value, v2: Value },
+}
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Prim {
+    Add,
+    Eq,
+}
+
+/// A line of the REPL or the prelude
+#[derive(Debug, PartialEq)]
+pub enum Statement {
+    /// effect op
+    Effect(String),
+    /// let x = c
+    Let { x: String, c: Computation },
+    /// c
+    Computation(Computation),
 }
 
 #[derive(Debug, PartialEq, Clone)]
 pub enum Constant {
     True,
     False,
+    Unit,
+    Int(i64),
 }
 
-// impl ToString for Expr {
-//     fn to_string(&self) -> String {
-//         let maybe_parenthesize = |e: &Expr| {
-//             if matches!(*e, Expr::Var(_) | Expr::Constant(_)) {
-//                 e.to_string()
-//             } else {
-//                 format!("({})", e.to_string())
-//             }
-//         };
-
-//         match self {
-//             Expr::Var(x) => x.to_string(),
-//             Expr::Constant(c) => c.to_string(),
-//             Expr::App { e1, e2 } => {
-//                 format!("{} {}", maybe_parenthesize(e1), maybe_parenthesize(e2))
-//             }
-//             Expr::Abs { x, t, e } => format!("\\{}: {}.{}", x, t.to_string(), e.to_string()),
-//             Expr::If { e1, e2, e3 } => format!(
-//                 "if {} then {} else {}",
-//                 e1.to_string(),
-//                 e2.to_string(),
-//                 e3.to_string()
-//             ),
-//         }
-//     }
-// }
-
-// impl ToString for Constant {
-//     fn to_string(&self) -> String {
-//         match self {
-//             Constant::True => "true".to_string(),
-//             Constant::False => "false".to_string(),
-//         }
-//     }
-// }
+/// Why a line of tokens isn't a program.
+#[derive(Debug, Clone, PartialEq)]
+pub enum ParseError {
+    /// `found` is where `expected` should be, or the line ended there if it's `None`
+    Unexpected {
+        expected: String,
+        found: Option<Token>,
+    },
+    /// a list `match` without exactly one `[]` case and one `::` case
+    MatchCases,
+}
+
+impl ParseError {
+    /// The stable code that `--explain` looks up.
+    pub fn code(&self) -> &'static str {
+        match self {
+            ParseError::Unexpected { found: Some(_), .. } => "E1101",
+            ParseError::Unexpected { found: None, .. } => "E1102",
+            ParseError::MatchCases => "E1103",
+        }
+    }
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ParseError::Unexpected {
+                expected,
+                found: Some(found),
+            } => write!(f, "expected {expected}, found `{found}`"),
+            ParseError::Unexpected {
+                expected,
+                found: None,
+            } => write!(f, "expected {expected}, but the line ended"),
+            ParseError::MatchCases => write!(f, "expected one `[]` case and one `::` case"),
+        }
+    }
+}
+
+impl std::error::Error for ParseError {}
+
+/// A parenthesised group, which is a value unless it turned out to hold a computation.
+enum Group {
+    Value(Value),
+    Computation(Computation),
+}
 
 pub struct Parser {
     stream: Vec<Token>,
     pos: usize,
+    /// names that parse as operations rather than variables
+    ops: HashSet<String>,
 }
 
 impl Parser {
-    pub fn parse(stream: Vec<Token>) -> anyhow::Result<Computation> {
-        let mut parser = Self { stream, pos: 0 };
-        parser.computation()
+    pub fn parse(stream: Vec<Token>, ops: &HashSet<String>) -> anyhow::Result<Computation> {
+        let mut parser = Self::new(stream, ops);
+        let c = parser.computation()?;
+        if parser.peek().is_some() {
+            bail!(parser.unexpected("the end of the line"));
+        }
+        Ok(c)
+    }
+
+    /// Parses a sequence of statements. Operations declared along the way are added to `ops`.
+    pub fn parse_program(
+        stream: Vec<Token>,
+        ops: &mut HashSet<String>,
+    ) -> anyhow::Result<Vec<Statement>> {
+        let mut parser = Self::new(stream, ops);
+        let mut statements = Vec::new();
+        while parser.peek().is_some() {
+            let statement = parser.statement()?;
+            if let Statement::Effect(op) = &statement {
+                parser.ops.insert(op.to_string());
+            }
+            statements.push(statement);
+        }
+        *ops = parser.ops;
+        Ok(statements)
+    }
+
+    fn new(stream: Vec<Token>, ops: &HashSet<String>) -> Self {
+        Self {
+            stream,
+            pos: 0,
+            ops: ops.clone(),
+        }
+    }
+
+    fn statement(&mut self) -> anyhow::Result<Statement> {
+        match self.peek() {
+            Some(Token::Effect) => {
+                self.consume(&Token::Effect)?;
+                Ok(Statement::Effect(self.consume_ident()?))
+            }
+            Some(Token::Let) => self.r#let(),
+            _ => Ok(Statement::Computation(self.computation()?)),
+        }
     }
 
     fn computation(&mut self) -> anyhow::Result<Computation> {
-        match self.peek().ok_or_else(|| anyhow!("empty stream"))? {
+        let Some(t) = self.peek() else {
+            bail!(self.unexpected("a computation"));
+        };
+        match t {
             Token::Return => self.ret(),
-            Token::Identifier(_) => {
-                // let e1 = self.var()?;
-                // if recurse_app {
-                //     self.maybe_app(e1)
-                // } else {
-                //     Ok(e1)
-                // }
-                self.op()
-            }
-            Token::Let => self.r#let(),
+            Token::Identifier(ident) if self.ops.contains(ident) => self.op(),
+            Token::Identifier(_) => self.app(),
+            Token::Let => match self.r#let()? {
+                Statement::Computation(c) => Ok(c),
+                _ => bail!(self.unexpected("`in`")),
+            },
+            Token::Match => self.r#match(),
             Token::If => self.if_then_else(),
             Token::With => self.with(),
-            Token::LeftParen => {
-                self.consume(&Token::LeftParen)?;
-                let e = self.computation()?;
-                self.consume(&Token::RightParen)?;
-                Ok(e)
-                // if recurse_app {
-                //     self.maybe_app(e)
-                // } else {
-                //     Ok(e)
-                // }
-            }
+            Token::For => self.r#for(),
+            Token::LeftParen => self.group(),
+            Token::Lambda
+            | Token::Handler
+            | Token::True
+            | Token::False
+            | Token::Int(_)
+            | Token::LeftBracket => self.app(),
 
-            Token::Then
+            t @ (Token::Then
             | Token::Else
             | Token::Equal
             | Token::Dot
             | Token::Arrow
-            | Token::Lambda
             | Token::LeftCurly
             | Token::RightCurly
             | Token::RightParen
             | Token::Comma
             | Token::Semicolon
-            | Token::True
-            | Token::False
-            | Token::Handler
             | Token::In
-            | Token::Handle => {
-                let v1 = self.value().unwrap()?;
-                let v2 = self.value().unwrap()?;
-                Ok(Computation::App { v1, v2 })
-                // if let Some(v1) = self.value() {
-                //     if let Some(v2) = self.value() {
-                //         return Ok(Computation::App { v1: v1?, v2: v2? });
-                //     }
-                // }
-                // bail!("unexpected token {:?}", t)
+            | Token::Do
+            | Token::Effect
+            | Token::RightBracket
+            | Token::Bar
+            | Token::ColonColon
+            | Token::Plus
+            | Token::EqualEqual
+            | Token::Rec
+            | Token::Handle) => bail!(ParseError::Unexpected {
+                expected: "a computation".to_string(),
+                found: Some(t.clone()),
+            }),
+        }
+    }
+
+    /// (c), or an application whose head is a parenthesised value like (\x. c) v
+    fn group(&mut self) -> anyhow::Result<Computation> {
+        self.consume(&Token::LeftParen)?;
+        match self.group_rest()? {
+            Group::Computation(c) => Ok(c),
+            Group::Value(v) => self.app_from(v),
+        }
+    }
+
+    /// The rest of a group after its `(`, through its `)`. Whether it's a value or a
+    /// computation is settled by the token after its first value, so nothing is parsed twice.
+    fn group_rest(&mut self) -> anyhow::Result<Group> {
+        if self.consume(&Token::RightParen).is_ok() {
+            return Ok(Group::Value(Value::Constant(Constant::Unit)));
+        }
+        let head = match self.peek() {
+            Some(Token::LeftParen) => {
+                self.consume(&Token::LeftParen)?;
+                self.group_rest()?
             }
+            Some(Token::Identifier(ident)) if self.ops.contains(ident) => {
+                Group::Computation(self.computation()?)
+            }
+            Some(
+                Token::Identifier(_)
+                | Token::True
+                | Token::False
+                | Token::Int(_)
+                | Token::LeftBracket
+                | Token::Lambda
+                | Token::Handler,
+            ) => Group::Value(
+                self.atom()
+                    .unwrap_or_else(|| bail!(self.unexpected("a value")))?,
+            ),
+            _ => Group::Computation(self.computation()?),
+        };
+        let mut v1 = match head {
+            Group::Computation(c) => {
+                self.consume(&Token::RightParen)?;
+                return Ok(Group::Computation(c));
+            }
+            Group::Value(v) => v,
+        };
+        // a cons can't be applied, so it's the whole value
+        let cons = self.consume(&Token::ColonColon).is_ok();
+        if cons {
+            let vs = self
+                .value()
+                .unwrap_or_else(|| bail!(self.unexpected("a value")))?;
+            v1 = Value::Cons(Box::new(v1), Box::new(vs));
+        }
+        if self.consume(&Token::Comma).is_ok() {
+            let v2 = self
+                .value()
+                .unwrap_or_else(|| bail!(self.unexpected("a value")))?;
+            self.consume(&Token::RightParen)?;
+            Ok(Group::Value(Value::Pair(Box::new(v1), Box::new(v2))))
+        } else if self.consume(&Token::RightParen).is_ok() {
+            Ok(Group::Value(v1))
+        } else if cons {
+            bail!(self.unexpected("`)`"))
+        } else {
+            let c = self.app_from(v1)?;
+            self.consume(&Token::RightParen)?;
+            Ok(Group::Computation(c))
         }
     }
 
     fn ret(&mut self) -> anyhow::Result<Computation> {
         self.consume(&Token::Return)?;
-        let v = self.value().unwrap_or_else(|| bail!("expected value"))?;
+        let v = self
+            .value()
+            .unwrap_or_else(|| bail!(self.unexpected("a value")))?;
         Ok(Computation::Return(v))
     }
 
-    fn r#let(&mut self) -> anyhow::Result<Computation> {
+    /// let x = c1 in c2 or let rec f x = c1 in c2; at the top level, `in c2` may be left off
+    fn r#let(&mut self) -> anyhow::Result<Statement> {
         self.consume(&Token::Let)?;
-        let x = self.consume_ident()?;
-        self.consume(&Token::Equal)?;
-        let c1 = self.computation()?;
-        self.consume(&Token::In)?;
-        let c2 = self.computation()?;
-        Ok(Computation::Seq {
-            x,
-            c1: Box::new(c1),
-            c2: Box::new(c2),
-        })
+        if self.consume(&Token::Rec).is_ok() {
+            let f = self.consume_ident()?;
+            let x = self.consume_ident()?;
+            self.consume(&Token::Equal)?;
+            let c1 = self.computation()?;
+            let c2 = if self.consume(&Token::In).is_ok() {
+                self.computation()?
+            } else {
+                return Ok(Statement::Let {
+                    x: f.clone(),
+                    c: Computation::LetRec {
+                        f: f.clone(),
+                        x,
+                        c1: Box::new(c1),
+                        c2: Box::new(Computation::Return(Value::Var(f))),
+                    },
+                });
+            };
+            Ok(Statement::Computation(Computation::LetRec {
+                f,
+                x,
+                c1: Box::new(c1),
+                c2: Box::new(c2),
+            }))
+        } else {
+            let x = self.consume_ident()?;
+            self.consume(&Token::Equal)?;
+            let c1 = self.computation()?;
+            if self.consume(&Token::In).is_err() {
+                return Ok(Statement::Let { x, c: c1 });
+            }
+            let c2 = self.computation()?;
+            Ok(Statement::Computation(Computation::Seq {
+                x,
+                c1: Box::new(c1),
+                c2: Box::new(c2),
+            }))
+        }
+    }
+
+    /// match v with [] -> c1 | x :: xs -> c2 or match v with (x, y) -> c
+    fn r#match(&mut self) -> anyhow::Result<Computation> {
+        self.consume(&Token::Match)?;
+        let v = self
+            .value()
+            .unwrap_or_else(|| bail!(self.unexpected("a value")))?;
+        self.consume(&Token::With)?;
+        let _ = self.consume(&Token::Bar);
+        if self.consume(&Token::LeftParen).is_ok() {
+            let x = self.consume_ident()?;
+            self.consume(&Token::Comma)?;
+            let y = self.consume_ident()?;
+            self.consume(&Token::RightParen)?;
+            self.consume(&Token::Arrow)?;
+            let c = self.computation()?;
+            return Ok(Computation::MatchPair {
+                v,
+                x,
+                y,
+                c: Box::new(c),
+            });
+        }
+
+        let mut nil = None;
+        let mut cons = None;
+        for i in 0..2 {
+            if i > 0 {
+                self.consume(&Token::Bar)?;
+            }
+            if self.consume(&Token::LeftBracket).is_ok() {
+                self.consume(&Token::RightBracket)?;
+                self.consume(&Token::Arrow)?;
+                nil = Some(self.computation()?);
+            } else {
+                let x = self.consume_ident()?;
+                self.consume(&Token::ColonColon)?;
+                let xs = self.consume_ident()?;
+                self.consume(&Token::Arrow)?;
+                cons = Some((x, xs, self.computation()?));
+            }
+        }
+        match (nil, cons) {
+            (Some(c1), Some((x, xs, c2))) => Ok(Computation::MatchList {
+                v,
+                c1: Box::new(c1),
+                x,
+                xs,
+                c2: Box::new(c2),
+            }),
+            _ => bail!(ParseError::MatchCases),
+        }
+    }
+
+    /// v1 v2 v3 === let f = v1 v2 in f v3, v1 + v2 or v1 == v2
+    fn app(&mut self) -> anyhow::Result<Computation> {
+        let v1 = self
+            .atom()
+            .unwrap_or_else(|| bail!(self.unexpected("a value")))?;
+        self.app_from(v1)
+    }
+
+    /// The rest of an application or a primitive whose first value `v1` is already parsed.
+    fn app_from(&mut self, v1: Value) -> anyhow::Result<Computation> {
+        let prim = match self.peek() {
+            Some(Token::Plus) => Some(Prim::Add),
+            Some(Token::EqualEqual) => Some(Prim::Eq),
+            _ => None,
+        };
+        if let Some(op) = prim {
+            self.pos += 1;
+            let v2 = self
+                .atom()
+                .unwrap_or_else(|| bail!(self.unexpected("a value")))?;
+            return Ok(Computation::Prim { op, v1, v2 });
+        }
+
+        let v2 = self
+            .atom()
+            .unwrap_or_else(|| bail!(self.unexpected("a value")))?;
+        let mut c = Computation::App { v1, v2 };
+        let mut i = 0;
+        while let Some(v) = self.atom() {
+            let f = self.fresh(&format!("f{i}"));
+            c = Computation::Seq {
+                x: f.clone(),
+                c1: Box::new(c),
+                c2: Box::new(Computation::App {
+                    v1: Value::Var(f),
+                    v2: v?,
+                }),
+            };
+            i += 1;
+        }
+        Ok(c)
     }
 
     fn with(&mut self) -> anyhow::Result<Computation> {
         self.consume(&Token::With)?;
-        let v = self.value().unwrap_or_else(|| bail!("expected value"))?;
+        let v = self
+            .value()
+            .unwrap_or_else(|| bail!(self.unexpected("a value")))?;
         self.consume(&Token::Handle)?;
         let c = self.computation()?;
         Ok(Computation::Handling {
@@ -201,25 +503,88 @@ impl Parser {
         })
     }
 
+    /// for x in c1 do c2 === with handler { yield(x; k) -> let _ = c2 in k () } handle c1
+    fn r#for(&mut self) -> anyhow::Result<Computation> {
+        self.consume(&Token::For)?;
+        let x = self.consume_ident()?;
+        self.consume(&Token::In)?;
+        let c1 = self.computation()?;
+        self.consume(&Token::Do)?;
+        let c2 = self.computation()?;
+
+        let k = self.fresh("k");
+        let body = Computation::Seq {
+            x: self.fresh("_"),
+            c1: Box::new(c2),
+            c2: Box::new(Computation::App {
+                v1: Value::Var(k.clone()),
+                v2: Value::Constant(Constant::Unit),
+            }),
+        };
+        let handler = Handler {
+            ret: None,
+            ops: HashMap::from([("yield".to_string(), (x, k, body))]),
+        };
+        Ok(Computation::Handling {
+            with: Value::Handler(Box::new(handler)),
+            handle: Box::new(c1),
+        })
+    }
+
+    /// op v === op(v; y.return y)
     fn op(&mut self) -> anyhow::Result<Computation> {
         let op = self.consume_ident()?;
         if let Some(Token::LeftParen) = self.peek() {
             self.consume(&Token::LeftParen)?;
-            self.consume(&Token::RightParen)?;
-            Ok(Computation::OpCall { op, param: None })
+            if self.consume(&Token::RightParen).is_ok() {
+                return Ok(Self::generic_op(op, Value::Constant(Constant::Unit)));
+            }
+            let param = self
+                .value()
+                .unwrap_or_else(|| bail!(self.unexpected("a value")))?;
+            if self.consume(&Token::Comma).is_ok() {
+                let v2 = self
+                    .value()
+                    .unwrap_or_else(|| bail!(self.unexpected("a value")))?;
+                self.consume(&Token::RightParen)?;
+                let param = Value::Pair(Box::new(param), Box::new(v2));
+                Ok(Self::generic_op(op, param))
+            } else if self.consume(&Token::Semicolon).is_ok() {
+                let y = self.consume_ident()?;
+                self.consume(&Token::Dot)?;
+                let c = self.computation()?;
+                self.consume(&Token::RightParen)?;
+                Ok(Computation::OpCall {
+                    op,
+                    param,
+                    y,
+                    c: Box::new(c),
+                })
+            } else {
+                self.consume(&Token::RightParen)?;
+                Ok(Self::generic_op(op, param))
+            }
         } else if let Some(v) = self.value() {
-            Ok(Computation::OpCall {
-                op,
-                param: Some(v?),
-            })
+            Ok(Self::generic_op(op, v?))
         } else {
-            bail!("invalid parameter to {op}")
+            bail!(self.unexpected(format!("a parameter to `{op}`")))
+        }
+    }
+
+    fn generic_op(op: String, param: Value) -> Computation {
+        Computation::OpCall {
+            op,
+            param,
+            y: "y".to_string(),
+            c: Box::new(Computation::Return(Value::Var("y".to_string()))),
         }
     }
 
     fn if_then_else(&mut self) -> anyhow::Result<Computation> {
         self.consume(&Token::If)?;
-        let v = self.value().unwrap_or_else(|| bail!("expected value"))?;
+        let v = self
+            .value()
+            .unwrap_or_else(|| bail!(self.unexpected("a value")))?;
         self.consume(&Token::Then)?;
         let c1 = self.computation()?;
         self.consume(&Token::Else)?;
@@ -232,6 +597,19 @@ impl Parser {
     }
 
     fn value(&mut self) -> Option<anyhow::Result<Value>> {
+        let v = self.atom()?;
+        if self.consume(&Token::ColonColon).is_err() {
+            return Some(v);
+        }
+        Some(v.and_then(|v| {
+            let vs = self
+                .value()
+                .unwrap_or_else(|| bail!(self.unexpected("a value")))?;
+            Ok(Value::Cons(Box::new(v), Box::new(vs)))
+        }))
+    }
+
+    fn atom(&mut self) -> Option<anyhow::Result<Value>> {
         match self.peek()? {
             Token::Identifier(_) => Some(self.var()),
             Token::True => {
@@ -242,27 +620,86 @@ impl Parser {
                 let _ = self.consume(&Token::False);
                 Some(Ok(Value::Constant(Constant::False)))
             }
+            Token::Int(n) => {
+                let n = *n;
+                let _ = self.consume(&Token::Int(n));
+                Some(Ok(Value::Constant(Constant::Int(n))))
+            }
+            Token::LeftParen => Some(self.tuple()),
+            Token::LeftBracket => Some(self.list()),
             Token::Lambda => Some(self.fun()),
             Token::Handler => Some(self.handler()),
 
             Token::Equal
             | Token::Dot
             | Token::Comma
-            | Token::LeftParen
             | Token::RightParen
             | Token::LeftCurly
             | Token::RightCurly
+            | Token::RightBracket
             | Token::Semicolon
+            | Token::Bar
+            | Token::ColonColon
+            | Token::Plus
+            | Token::EqualEqual
             | Token::If
             | Token::Then
             | Token::Else
             | Token::Arrow
             | Token::Return
             | Token::Let
+            | Token::Rec
             | Token::In
             | Token::With
-            | Token::Handle => None,
+            | Token::Handle
+            | Token::For
+            | Token::Do
+            | Token::Effect
+            | Token::Match => None,
+        }
+    }
+
+    /// (), (v) or (v1, v2)
+    fn tuple(&mut self) -> anyhow::Result<Value> {
+        self.consume(&Token::LeftParen)?;
+        if self.consume(&Token::RightParen).is_ok() {
+            return Ok(Value::Constant(Constant::Unit));
+        }
+        let v1 = self
+            .value()
+            .unwrap_or_else(|| bail!(self.unexpected("a value")))?;
+        if self.consume(&Token::Comma).is_ok() {
+            let v2 = self
+                .value()
+                .unwrap_or_else(|| bail!(self.unexpected("a value")))?;
+            self.consume(&Token::RightParen)?;
+            Ok(Value::Pair(Box::new(v1), Box::new(v2)))
+        } else {
+            self.consume(&Token::RightParen)?;
+            Ok(v1)
+        }
+    }
+
+    /// [v1, v2, ...] === v1 :: v2 :: ... :: []
+    fn list(&mut self) -> anyhow::Result<Value> {
+        self.consume(&Token::LeftBracket)?;
+        let mut items = Vec::new();
+        if self.consume(&Token::RightBracket).is_err() {
+            loop {
+                items.push(
+                    self.value()
+                        .unwrap_or_else(|| bail!(self.unexpected("a value")))?,
+                );
+                if self.consume(&Token::Comma).is_err() {
+                    break;
+                }
+            }
+            self.consume(&Token::RightBracket)?;
         }
+        Ok(items
+            .into_iter()
+            .rev()
+            .fold(Value::Nil, |vs, v| Value::Cons(Box::new(v), Box::new(vs))))
     }
 
     fn var(&mut self) -> anyhow::Result<Value> {
@@ -274,30 +711,29 @@ impl Parser {
         self.consume(&Token::Handler)?;
         self.consume(&Token::LeftCurly)?;
 
-        let ret = if self.consume(&Token::Return).is_ok() {
-            let x = self.consume_ident()?;
-            self.consume(&Token::Arrow)?;
-            let c = self.computation()?;
-            Some((x, c))
-        } else {
-            None
-        };
-
+        let mut ret = None;
         let mut ops = HashMap::new();
         loop {
             if let Some(Token::RightCurly) = self.peek() {
                 break;
             }
 
-            let op = self.consume_ident()?;
-            self.consume(&Token::LeftParen)?;
-            let x = self.consume_ident()?;
-            self.consume(&Token::Semicolon)?;
-            let k = self.consume_ident()?;
-            self.consume(&Token::RightParen)?;
-            self.consume(&Token::Arrow)?;
-            let c = self.computation()?;
-            ops.insert(op, (x, k, c));
+            if self.consume(&Token::Return).is_ok() {
+                let x = self.consume_ident()?;
+                self.consume(&Token::Arrow)?;
+                let c = self.computation()?;
+                ret = Some((x, c));
+            } else {
+                let op = self.consume_ident()?;
+                self.consume(&Token::LeftParen)?;
+                let x = self.consume_ident()?;
+                self.consume(&Token::Semicolon)?;
+                let k = self.consume_ident()?;
+                self.consume(&Token::RightParen)?;
+                self.consume(&Token::Arrow)?;
+                let c = self.computation()?;
+                ops.insert(op, (x, k, c));
+            }
 
             if self.consume(&Token::Comma).is_ok() {
                 continue;
@@ -321,7 +757,20 @@ impl Parser {
         }
     }
 
-    fn peek(&mut self) -> Option<&Token> {
+    /// A variable name that appears nowhere in the token stream.
+    fn fresh(&self, base: &str) -> String {
+        let mut name = base.to_string();
+        while self
+            .stream
+            .iter()
+            .any(|t| matches!(t, Token::Identifier(x) if x == &name))
+        {
+            name = format!("{name}1");
+        }
+        name
+    }
+
+    fn peek(&self) -> Option<&Token> {
         if self.pos < self.stream.len() {
             Some(&self.stream[self.pos])
         } else {
@@ -330,56 +779,291 @@ impl Parser {
     }
 
     fn consume(&mut self, tok: &Token) -> anyhow::Result<Option<&Token>> {
-        let peek = self.peek();
-        if peek == Some(tok) {
+        if self.peek() == Some(tok) {
             self.pos += 1;
             Ok(self.peek())
         } else {
-            bail!("expected {:?}, found {:?}", tok, peek)
+            bail!(self.unexpected(format!("`{tok}`")))
         }
     }
 
     fn consume_ident(&mut self) -> anyhow::Result<String> {
-        let peek = self.peek();
-        if let Some(Token::Identifier(ident)) = peek {
+        if let Some(Token::Identifier(ident)) = self.peek() {
             let ident = ident.clone();
             self.pos += 1;
             Ok(ident)
         } else {
-            bail!("exptected identifier, found {:?}", peek)
+            bail!(self.unexpected("an identifier"))
+        }
+    }
+
+    /// The error for a next token that isn't `expected`.
+    fn unexpected(&self, expected: impl Into<String>) -> ParseError {
+        ParseError::Unexpected {
+            expected: expected.into(),
+            found: self.peek().cloned(),
         }
     }
 }
 
-// source: just trust me bro
-// #[cfg(test)]
-// mod tests {
-//     use super::*;
-
-//     #[test]
-//     fn works() {
-//         let stream = vec![
-//             Token::LeftParen,
-//             Token::Lambda,
-//             Token::Identifier("x".into()),
-//             Token::Colon,
-//             Token::Bool,
-//             Token::Dot,
-//             Token::Identifier("x".into()),
-//             Token::RightParen,
-//             Token::True,
-//         ];
-//         let e = Parser::parse(stream).unwrap();
-//         assert_eq!(
-//             e,
-//             Expr::App {
-//                 e1: Box::new(Expr::Abs {
-//                     x: "x".into(),
-//                     t: Type::Bool,
-//                     e: Box::new(Expr::Var("x".into()))
-//                 }),
-//                 e2: Box::new(Expr::Constant(Constant::True))
-//             }
-//         );
-//     }
-// }
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::scanner::Scanner;
+
+    fn parse(s: &str) -> anyhow::Result<Computation> {
+        let ops = HashSet::from(["get".to_string()]);
+        Parser::parse(Scanner::scan(s.to_string())?, &ops)
+    }
+
+    fn var(x: &str) -> Value {
+        Value::Var(x.to_string())
+    }
+
+    fn int(n: i64) -> Value {
+        Value::Constant(Constant::Int(n))
+    }
+
+    fn ret(v: Value) -> Computation {
+        Computation::Return(v)
+    }
+
+    fn app(v1: Value, v2: Value) -> Computation {
+        Computation::App { v1, v2 }
+    }
+
+    fn fun(x: &str, c: Computation) -> Value {
+        Value::Fun {
+            x: x.to_string(),
+            c: Box::new(c),
+        }
+    }
+
+    #[test]
+    fn values() {
+        let cons = |v, vs| Value::Cons(Box::new(v), Box::new(vs));
+        let pair = |v1, v2| Value::Pair(Box::new(v1), Box::new(v2));
+        let cases = [
+            ("return x", var("x")),
+            ("return true", Value::Constant(Constant::True)),
+            ("return false", Value::Constant(Constant::False)),
+            ("return 1", int(1)),
+            ("return ()", Value::Constant(Constant::Unit)),
+            ("return (1)", int(1)),
+            (
+                "return (1, (x, ()))",
+                pair(int(1), pair(var("x"), Value::Constant(Constant::Unit))),
+            ),
+            ("return []", Value::Nil),
+            ("return [1, 2]", cons(int(1), cons(int(2), Value::Nil))),
+            (
+                "return 1 :: (2 :: [])",
+                cons(int(1), cons(int(2), Value::Nil)),
+            ),
+            ("return \\x. return x", fun("x", ret(var("x")))),
+        ];
+        for (src, v) in cases {
+            assert_eq!(parse(src).unwrap(), ret(v), "{src}");
+        }
+    }
+
+    #[test]
+    fn applications() {
+        assert_eq!(parse("f x").unwrap(), app(var("f"), var("x")));
+        assert_eq!(
+            parse("(\\x. return x) true").unwrap(),
+            app(fun("x", ret(var("x"))), Value::Constant(Constant::True))
+        );
+        assert_eq!(parse("(f x)").unwrap(), app(var("f"), var("x")));
+        assert_eq!(
+            parse("f x y").unwrap(),
+            Computation::Seq {
+                x: "f0".into(),
+                c1: Box::new(app(var("f"), var("x"))),
+                c2: Box::new(app(var("f0"), var("y"))),
+            }
+        );
+        assert_eq!(
+            parse("1 + x").unwrap(),
+            Computation::Prim {
+                op: Prim::Add,
+                v1: int(1),
+                v2: var("x"),
+            }
+        );
+        assert_eq!(
+            parse("x == 1").unwrap(),
+            Computation::Prim {
+                op: Prim::Eq,
+                v1: var("x"),
+                v2: int(1),
+            }
+        );
+    }
+
+    #[test]
+    fn groups() {
+        assert_eq!(
+            parse("((\\x. return x)) (1, 2)").unwrap(),
+            app(
+                fun("x", ret(var("x"))),
+                Value::Pair(Box::new(int(1)), Box::new(int(2)))
+            )
+        );
+        assert_eq!(parse("((f) x)").unwrap(), app(var("f"), var("x")));
+        assert_eq!(parse("(x :: xs, y) z").unwrap(), {
+            let xs = Value::Cons(Box::new(var("x")), Box::new(var("xs")));
+            app(Value::Pair(Box::new(xs), Box::new(var("y"))), var("z"))
+        });
+        assert_eq!(parse("((get x))").unwrap(), parse("get x").unwrap());
+        // each group is parsed once, so deep nesting stays fast
+        let deep = |inner: &str| format!("{}{inner}{}", "(".repeat(200), ")".repeat(200));
+        assert_eq!(parse(&deep("return 1")).unwrap(), ret(int(1)));
+        assert_eq!(parse(&deep("f 1")).unwrap(), app(var("f"), int(1)));
+        assert_eq!(
+            parse(&format!("{} 1", deep("f"))).unwrap(),
+            app(var("f"), int(1))
+        );
+    }
+
+    #[test]
+    fn operations() {
+        let op = |param, y: &str, c| Computation::OpCall {
+            op: "get".into(),
+            param,
+            y: y.into(),
+            c: Box::new(c),
+        };
+        let unit = Value::Constant(Constant::Unit);
+        assert_eq!(parse("get x").unwrap(), op(var("x"), "y", ret(var("y"))));
+        assert_eq!(parse("get()").unwrap(), op(unit, "y", ret(var("y"))));
+        assert_eq!(parse("get(x)").unwrap(), op(var("x"), "y", ret(var("y"))));
+        assert_eq!(
+            parse("get(1, 2)").unwrap(),
+            op(
+                Value::Pair(Box::new(int(1)), Box::new(int(2))),
+                "y",
+                ret(var("y"))
+            )
+        );
+        assert_eq!(
+            parse("get(x; z. f z)").unwrap(),
+            op(var("x"), "z", app(var("f"), var("z")))
+        );
+    }
+
+    #[test]
+    fn binders() {
+        assert_eq!(
+            parse("let x = f () in return x").unwrap(),
+            Computation::Seq {
+                x: "x".into(),
+                c1: Box::new(app(var("f"), Value::Constant(Constant::Unit))),
+                c2: Box::new(ret(var("x"))),
+            }
+        );
+        assert_eq!(
+            parse("let rec f x = f x in f 1").unwrap(),
+            Computation::LetRec {
+                f: "f".into(),
+                x: "x".into(),
+                c1: Box::new(app(var("f"), var("x"))),
+                c2: Box::new(app(var("f"), int(1))),
+            }
+        );
+        assert_eq!(
+            parse("if b then return 1 else (return 2)").unwrap(),
+            Computation::If {
+                v: var("b"),
+                c1: Box::new(ret(int(1))),
+                c2: Box::new(ret(int(2))),
+            }
+        );
+        assert_eq!(
+            parse("match xs with | [] -> return 0 | y :: ys -> return y").unwrap(),
+            Computation::MatchList {
+                v: var("xs"),
+                c1: Box::new(ret(int(0))),
+                x: "y".into(),
+                xs: "ys".into(),
+                c2: Box::new(ret(var("y"))),
+            }
+        );
+        assert_eq!(
+            parse("match p with (a, b) -> return b").unwrap(),
+            Computation::MatchPair {
+                v: var("p"),
+                x: "a".into(),
+                y: "b".into(),
+                c: Box::new(ret(var("b"))),
+            }
+        );
+    }
+
+    #[test]
+    fn handlers() {
+        let h = Handler {
+            ret: Some(("x".into(), ret(var("x")))),
+            ops: HashMap::from([(
+                "get".to_string(),
+                ("u".to_string(), "k".to_string(), app(var("k"), int(1))),
+            )]),
+        };
+        assert_eq!(
+            parse("with handler { return x -> return x, get(u; k) -> k 1 } handle get ()").unwrap(),
+            Computation::Handling {
+                with: Value::Handler(Box::new(h)),
+                handle: Box::new(parse("get ()").unwrap()),
+            }
+        );
+        assert_eq!(
+            parse("with h handle (f x)").unwrap(),
+            Computation::Handling {
+                with: var("h"),
+                handle: Box::new(app(var("f"), var("x"))),
+            }
+        );
+        let Computation::Handling { with, handle } = parse("for x in g () do f x").unwrap() else {
+            panic!("expected handling");
+        };
+        assert_eq!(*handle, app(var("g"), Value::Constant(Constant::Unit)));
+        let Value::Handler(h) = with else {
+            panic!("expected handler");
+        };
+        assert!(h.ops.contains_key("yield"));
+    }
+
+    #[test]
+    fn statements() {
+        let mut ops = HashSet::new();
+        let tokens =
+            Scanner::scan("effect get\nlet x = get ()\nlet rec f y = f y\nreturn x".into());
+        let statements = Parser::parse_program(tokens.unwrap(), &mut ops).unwrap();
+        assert!(ops.contains("get"));
+        assert_eq!(statements.len(), 4);
+        assert_eq!(statements[0], Statement::Effect("get".into()));
+        assert!(matches!(&statements[1], Statement::Let { x, .. } if x == "x"));
+        assert!(
+            matches!(&statements[2], Statement::Let { x, c: Computation::LetRec { .. } } if x == "f")
+        );
+        assert_eq!(statements[3], Statement::Computation(ret(var("x"))));
+    }
+
+    #[test]
+    fn rejects_malformed() {
+        for src in [
+            "then",
+            "1",
+            "(x)",
+            "(1, 2)",
+            "(x :: xs y)",
+            "((return 1) 2)",
+            "f",
+            "return",
+            "let x = in f x",
+            ")",
+        ] {
+            assert!(parse(src).is_err(), "{src}");
+        }
+    }
+}
@@ -0,0 +1,17 @@
+/// Operations every program can call without declaring them.
+pub const OPERATIONS: &[&str] = &[
+    "yield",
+    "pause",
+    "fork",
+    "async",
+    "await",
+    // the scheduler's own, which only the prelude can name
+    "$enqueue",
+    "$dequeue",
+    "$new_promise",
+    "$resolve",
+    "$poll",
+];
+
+/// Definitions loaded into every session.
+pub const PRELUDE: &str = include_str!("prelude.eff");
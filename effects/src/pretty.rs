@@ -0,0 +1,552 @@
+use std::fmt;
+
+use crate::{
+    parser::{Computation, Constant, Handler, Prim, Statement, Value},
+    scanner::Token,
+};
+
+/// A document in the style of Wadler's "A prettier printer": text with optional line breaks,
+/// where each group is laid out on one line if it fits and broken at every `Line` otherwise.
+#[derive(Debug, Clone)]
+pub enum Doc {
+    Text(String),
+    /// a space, or a newline if the enclosing group doesn't fit
+    Line,
+    Nest(usize, Box<Doc>),
+    Concat(Vec<Doc>),
+    Group(Box<Doc>),
+}
+
+impl Doc {
+    pub fn render(&self, width: usize) -> String {
+        let mut out = String::new();
+        let mut col = 0;
+        let mut stack = vec![(0, false, self)];
+        while let Some((indent, flat, doc)) = stack.pop() {
+            match doc {
+                Doc::Text(s) => {
+                    out.push_str(s);
+                    col += s.chars().count();
+                }
+                Doc::Line if flat => {
+                    out.push(' ');
+                    col += 1;
+                }
+                Doc::Line => {
+                    out.push('\n');
+                    out.push_str(&" ".repeat(indent));
+                    col = indent;
+                }
+                Doc::Nest(i, doc) => stack.push((indent + i, flat, doc)),
+                Doc::Concat(docs) => stack.extend(docs.iter().rev().map(|d| (indent, flat, d))),
+                Doc::Group(doc) => {
+                    let flat = flat || fits(width as isize - col as isize, doc, &stack);
+                    stack.push((indent, flat, doc));
+                }
+            }
+        }
+        out
+    }
+}
+
+/// Whether `doc` laid out flat, and whatever follows it up to the next line break, fits in
+/// `width` columns.
+fn fits(mut width: isize, doc: &Doc, rest: &[(usize, bool, &Doc)]) -> bool {
+    let mut stack = vec![(true, doc)];
+    let mut rest = rest.iter().rev();
+    while width >= 0 {
+        let Some((flat, doc)) = stack
+            .pop()
+            .or_else(|| rest.next().map(|(_, f, d)| (*f, *d)))
+        else {
+            return true;
+        };
+        match doc {
+            Doc::Text(s) => width -= s.chars().count() as isize,
+            Doc::Line if flat => width -= 1,
+            Doc::Line => return true,
+            Doc::Nest(_, doc) | Doc::Group(doc) => stack.push((flat, doc)),
+            Doc::Concat(docs) => stack.extend(docs.iter().rev().map(|d| (flat, d))),
+        }
+    }
+    false
+}
+
+fn text(s: impl Into<String>) -> Doc {
+    Doc::Text(s.into())
+}
+
+fn concat(docs: impl IntoIterator<Item = Doc>) -> Doc {
+    Doc::Concat(docs.into_iter().collect())
+}
+
+fn nest(doc: Doc) -> Doc {
+    Doc::Nest(2, Box::new(doc))
+}
+
+fn group(doc: Doc) -> Doc {
+    Doc::Group(Box::new(doc))
+}
+
+/// `head` followed by `body` on the same line, or indented on the next one.
+fn hang(head: Doc, body: Doc) -> Doc {
+    group(concat([head, nest(concat([Doc::Line, body]))]))
+}
+
+pub fn computation(c: &Computation) -> Doc {
+    match c {
+        Computation::Return(v) => concat([text("return "), value(v)]),
+        Computation::OpCall { op, param, y, c } => match c.as_ref() {
+            Computation::Return(Value::Var(z)) if z == y => {
+                concat([text(op), text(" "), atom(param)])
+            }
+            _ => concat([
+                hang(
+                    concat([text(op), text("("), value(param), text(format!("; {y}."))]),
+                    computation(c),
+                ),
+                text(")"),
+            ]),
+        },
+        Computation::Seq { x, c1, c2 } => r#let(text(format!("let {x} =")), c1, c2),
+        Computation::LetRec { f, x, c1, c2 } => r#let(text(format!("let rec {f} {x} =")), c1, c2),
+        Computation::If { v, c1, c2 } => group(concat([
+            text("if "),
+            value(v),
+            text(" then"),
+            nest(concat([Doc::Line, computation(c1)])),
+            Doc::Line,
+            text("else"),
+            nest(concat([Doc::Line, computation(c2)])),
+        ])),
+        Computation::App { v1, v2 } => concat([atom(v1), text(" "), atom(v2)]),
+        Computation::Handling { with, handle } => hang(
+            concat([text("with "), value(with), text(" handle")]),
+            computation(handle),
+        ),
+        Computation::MatchList { v, c1, x, xs, c2 } => group(concat([
+            text("match "),
+            value(v),
+            text(" with"),
+            Doc::Line,
+            hang(text("| [] ->"), computation(c1)),
+            Doc::Line,
+            hang(text(format!("| {x} :: {xs} ->")), computation(c2)),
+        ])),
+        Computation::MatchPair { v, x, y, c } => hang(
+            concat([
+                text("match "),
+                value(v),
+                text(format!(" with ({x}, {y}) ->")),
+            ]),
+            computation(c),
+        ),
+        Computation::Prim { op, v1, v2 } => {
+            let op = match op {
+                Prim::Add => " + ",
+                Prim::Eq => " == ",
+            };
+            concat([atom(v1), text(op), atom(v2)])
+        }
+    }
+}
+
+/// let x = c1 in c2, with `c2` on the next line if it doesn't all fit
+fn r#let(head: Doc, c1: &Computation, c2: &Computation) -> Doc {
+    group(concat([
+        hang(head, computation(c1)),
+        text(" in"),
+        Doc::Line,
+        computation(c2),
+    ]))
+}
+
+pub fn value(v: &Value) -> Doc {
+    match v {
+        Value::Fun { x, c } => hang(text(format!("\\{x}.")), computation(c)),
+        Value::Cons(v, vs) if list(vs).is_none() => concat([atom(v), text(" :: "), value(vs)]),
+        _ => atom(v),
+    }
+}
+
+/// A value that can be an argument, parenthesised if need be.
+fn atom(v: &Value) -> Doc {
+    match v {
+        Value::Var(x) => text(x),
+        Value::Constant(c) => text(c.to_string()),
+        Value::Handler(h) => handler(h),
+        Value::Nil | Value::Cons(..) => match list(v) {
+            Some(items) => items_doc("[", items.into_iter().map(value), "]"),
+            None => concat([text("("), value(v), text(")")]),
+        },
+        Value::Pair(v1, v2) => items_doc("(", [value(v1), value(v2)], ")"),
+        Value::Fun { .. } => concat([text("("), value(v), text(")")]),
+    }
+}
+
+/// The items of `v` if it's a list literal.
+fn list(v: &Value) -> Option<Vec<&Value>> {
+    let mut items = Vec::new();
+    let mut rest = v;
+    loop {
+        match rest {
+            Value::Nil => return Some(items),
+            Value::Cons(v, vs) => {
+                items.push(v.as_ref());
+                rest = vs;
+            }
+            _ => return None,
+        }
+    }
+}
+
+/// open item, item, ... close, with one item per line if they don't fit
+fn items_doc(open: &str, items: impl IntoIterator<Item = Doc>, close: &str) -> Doc {
+    let mut docs = Vec::new();
+    for (i, item) in items.into_iter().enumerate() {
+        if i > 0 {
+            docs.extend([text(","), Doc::Line]);
+        }
+        docs.push(item);
+    }
+    group(concat([
+        text(open),
+        Doc::Nest(open.len(), Box::new(concat(docs))),
+        text(close),
+    ]))
+}
+
+/// The clauses of a handler are printed `return` first, then in alphabetical order.
+pub fn handler(h: &Handler) -> Doc {
+    let mut clauses = Vec::new();
+    if let Some((x, c)) = &h.ret {
+        clauses.push(hang(text(format!("return {x} ->")), computation(c)));
+    }
+    let mut ops: Vec<_> = h.ops.iter().collect();
+    ops.sort_by_key(|(op, _)| *op);
+    for (op, (x, k, c)) in ops {
+        clauses.push(hang(text(format!("{op}({x}; {k}) ->")), computation(c)));
+    }
+
+    let mut body = Vec::new();
+    for (i, clause) in clauses.into_iter().enumerate() {
+        if i > 0 {
+            body.push(text(","));
+        }
+        body.extend([Doc::Line, clause]);
+    }
+    group(concat([
+        text("handler {"),
+        nest(concat(body)),
+        Doc::Line,
+        text("}"),
+    ]))
+}
+
+pub fn statement(s: &Statement) -> Doc {
+    match s {
+        Statement::Effect(op) => text(format!("effect {op}")),
+        Statement::Let {
+            x,
+            c: Computation::LetRec { f, x: y, c1, c2 },
+        } if f == x && c2.as_ref() == &Computation::Return(Value::Var(f.clone())) => {
+            hang(text(format!("let rec {f} {y} =")), computation(c1))
+        }
+        Statement::Let { x, c } => hang(text(format!("let {x} =")), computation(c)),
+        Statement::Computation(c) => computation(c),
+    }
+}
+
+/// Formats `doc` to the formatter's width, 80 columns by default: `format!("{:40}", c)`
+/// prints `c` to fit in 40 columns.
+fn render(f: &mut fmt::Formatter<'_>, doc: Doc) -> fmt::Result {
+    write!(f, "{}", doc.render(f.width().unwrap_or(80)))
+}
+
+impl fmt::Display for Computation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        render(f, computation(self))
+    }
+}
+
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        render(f, value(self))
+    }
+}
+
+impl fmt::Display for Handler {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        render(f, handler(self))
+    }
+}
+
+impl fmt::Display for Statement {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        render(f, statement(self))
+    }
+}
+
+impl fmt::Display for Constant {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Constant::True => write!(f, "true"),
+            Constant::False => write!(f, "false"),
+            Constant::Unit => write!(f, "()"),
+            Constant::Int(n) => write!(f, "{n}"),
+        }
+    }
+}
+
+/// Tokens as they're written, for parse errors.
+impl fmt::Display for Token {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let text = match self {
+            Token::Identifier(x) => x,
+            Token::Int(n) => return write!(f, "{n}"),
+            Token::Lambda => "\\",
+            Token::Equal => "=",
+            Token::Dot => ".",
+            Token::LeftParen => "(",
+            Token::RightParen => ")",
+            Token::LeftCurly => "{",
+            Token::RightCurly => "}",
+            Token::LeftBracket => "[",
+            Token::RightBracket => "]",
+            Token::Semicolon => ";",
+            Token::Comma => ",",
+            Token::Bar => "|",
+            Token::ColonColon => "::",
+            Token::Plus => "+",
+            Token::EqualEqual => "==",
+            Token::True => "true",
+            Token::False => "false",
+            Token::If => "if",
+            Token::Then => "then",
+            Token::Else => "else",
+            Token::Arrow => "->",
+            Token::Handler => "handler",
+            Token::Return => "return",
+            Token::Let => "let",
+            Token::Rec => "rec",
+            Token::In => "in",
+            Token::With => "with",
+            Token::Handle => "handle",
+            Token::For => "for",
+            Token::Do => "do",
+            Token::Effect => "effect",
+            Token::Match => "match",
+        };
+        write!(f, "{}", text)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::{HashMap, HashSet};
+
+    use super::*;
+    use crate::{parser::Parser, prelude, scanner::Scanner};
+
+    /// Renames every binder to `_n`, numbered in order, so alpha-equivalent terms are equal.
+    struct Canon {
+        next: usize,
+    }
+
+    impl Canon {
+        fn bind(
+            &mut self,
+            env: &HashMap<String, String>,
+            xs: &[&String],
+        ) -> (HashMap<String, String>, Vec<String>) {
+            let mut env = env.clone();
+            let names = xs
+                .iter()
+                .map(|x| {
+                    self.next += 1;
+                    let name = format!("_{}", self.next);
+                    env.insert(x.to_string(), name.clone());
+                    name
+                })
+                .collect();
+            (env, names)
+        }
+
+        fn comp(&mut self, c: &Computation, env: &HashMap<String, String>) -> Computation {
+            match c {
+                Computation::Return(v) => Computation::Return(self.val(v, env)),
+                Computation::OpCall { op, param, y, c } => {
+                    let param = self.val(param, env);
+                    let (env, names) = self.bind(env, &[y]);
+                    Computation::OpCall {
+                        op: op.clone(),
+                        param,
+                        y: names[0].clone(),
+                        c: Box::new(self.comp(c, &env)),
+                    }
+                }
+                Computation::Seq { x, c1, c2 } => {
+                    let c1 = Box::new(self.comp(c1, env));
+                    let (env, names) = self.bind(env, &[x]);
+                    Computation::Seq {
+                        x: names[0].clone(),
+                        c1,
+                        c2: Box::new(self.comp(c2, &env)),
+                    }
+                }
+                Computation::If { v, c1, c2 } => Computation::If {
+                    v: self.val(v, env),
+                    c1: Box::new(self.comp(c1, env)),
+                    c2: Box::new(self.comp(c2, env)),
+                },
+                Computation::App { v1, v2 } => Computation::App {
+                    v1: self.val(v1, env),
+                    v2: self.val(v2, env),
+                },
+                Computation::Handling { with, handle } => Computation::Handling {
+                    with: self.val(with, env),
+                    handle: Box::new(self.comp(handle, env)),
+                },
+                Computation::LetRec { f, x, c1, c2 } => {
+                    let (env_f, f_names) = self.bind(env, &[f]);
+                    let (env_x, x_names) = self.bind(&env_f, &[x]);
+                    Computation::LetRec {
+                        f: f_names[0].clone(),
+                        x: x_names[0].clone(),
+                        c1: Box::new(self.comp(c1, &env_x)),
+                        c2: Box::new(self.comp(c2, &env_f)),
+                    }
+                }
+                Computation::MatchList { v, c1, x, xs, c2 } => {
+                    let v = self.val(v, env);
+                    let c1 = Box::new(self.comp(c1, env));
+                    let (env, names) = self.bind(env, &[x, xs]);
+                    Computation::MatchList {
+                        v,
+                        c1,
+                        x: names[0].clone(),
+                        xs: names[1].clone(),
+                        c2: Box::new(self.comp(c2, &env)),
+                    }
+                }
+                Computation::MatchPair { v, x, y, c } => {
+                    let v = self.val(v, env);
+                    let (env, names) = self.bind(env, &[x, y]);
+                    Computation::MatchPair {
+                        v,
+                        x: names[0].clone(),
+                        y: names[1].clone(),
+                        c: Box::new(self.comp(c, &env)),
+                    }
+                }
+                Computation::Prim { op, v1, v2 } => Computation::Prim {
+                    op: *op,
+                    v1: self.val(v1, env),
+                    v2: self.val(v2, env),
+                },
+            }
+        }
+
+        fn val(&mut self, v: &Value, env: &HashMap<String, String>) -> Value {
+            match v {
+                Value::Var(x) => Value::Var(env.get(x).unwrap_or(x).clone()),
+                Value::Constant(_) | Value::Nil => v.clone(),
+                Value::Fun { x, c } => {
+                    let (env, names) = self.bind(env, &[x]);
+                    Value::Fun {
+                        x: names[0].clone(),
+                        c: Box::new(self.comp(c, &env)),
+                    }
+                }
+                Value::Handler(h) => {
+                    let ret = h.ret.as_ref().map(|(x, c)| {
+                        let (env, names) = self.bind(env, &[x]);
+                        (names[0].clone(), self.comp(c, &env))
+                    });
+                    let mut ops: Vec<_> = h.ops.iter().collect();
+                    ops.sort_by_key(|(op, _)| *op);
+                    let ops = ops
+                        .into_iter()
+                        .map(|(op, (x, k, c))| {
+                            let (env, names) = self.bind(env, &[x, k]);
+                            let c = self.comp(c, &env);
+                            (op.clone(), (names[0].clone(), names[1].clone(), c))
+                        })
+                        .collect();
+                    Value::Handler(Box::new(Handler { ret, ops }))
+                }
+                Value::Cons(v, vs) => {
+                    Value::Cons(Box::new(self.val(v, env)), Box::new(self.val(vs, env)))
+                }
+                Value::Pair(v1, v2) => {
+                    Value::Pair(Box::new(self.val(v1, env)), Box::new(self.val(v2, env)))
+                }
+            }
+        }
+    }
+
+    fn canon(c: &Computation) -> Computation {
+        Canon { next: 0 }.comp(c, &HashMap::new())
+    }
+
+    fn ops() -> HashSet<String> {
+        prelude::OPERATIONS
+            .iter()
+            .chain(&["print", "get", "set"])
+            .map(|op| op.to_string())
+            .collect()
+    }
+
+    fn parse(s: &str) -> Computation {
+        Parser::parse(Scanner::scan(s.to_string()).unwrap(), &ops()).unwrap()
+    }
+
+    fn round_trips(c: &Computation) {
+        for width in [0, 20, 80] {
+            let printed = format!("{:width$}", c);
+            // the prelude's definitions name its internal operations
+            let reparsed = Parser::parse(Scanner::scan_prelude(printed.clone()).unwrap(), &ops())
+                .unwrap_or_else(|e| panic!("{e}:\n{printed}"));
+            assert_eq!(canon(&reparsed), canon(c), "{printed}");
+        }
+    }
+
+    #[test]
+    fn round_trip() {
+        for src in [
+            "(\\x. return x) true",
+            "let f = let g = return \\x. f x in return g in f (1 :: xs)",
+            "get(1; x. let y = set (x, 2) in if y then return [x, y] else return x :: [])",
+            "match xs with | [] -> match p with (a, b) -> a + b | y :: ys -> y == (\\z. return z)",
+            "let rec f x = with handler { return x -> return (), get(u; k) -> k u } handle f x in f 0",
+            "for x in g () do print x",
+        ] {
+            round_trips(&parse(src));
+        }
+        let mut ops = ops();
+        let tokens = Scanner::scan_prelude(prelude::PRELUDE.to_string()).unwrap();
+        for statement in Parser::parse_program(tokens, &mut ops).unwrap() {
+            if let Statement::Let { c, .. } = statement {
+                round_trips(&c);
+            }
+        }
+    }
+
+    #[test]
+    fn breaks_long_handlers() {
+        let c = parse("with handler { return x -> return x, get(u; k) -> k 1, set(s; k) -> k () } handle f ()");
+        assert_eq!(
+            format!("{:40}", c),
+            "with handler {
+  return x -> return x,
+  get(u; k) -> k 1,
+  set(s; k) -> k ()
+} handle
+  f ()"
+        );
+        assert_eq!(
+            format!("{:100}", c),
+            "with handler { return x -> return x, get(u; k) -> k 1, set(s; k) -> k () } handle f ()"
+        );
+    }
+}
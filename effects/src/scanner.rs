@@ -1,8 +1,9 @@
-use anyhow::anyhow;
+use std::fmt;
 
-#[derive(Debug, PartialEq)]
+#[derive(Debug, Clone, PartialEq)]
 pub enum Token {
     Identifier(String),
+    Int(i64),
     Lambda,
     Equal,
     Dot,
@@ -10,8 +11,14 @@ pub enum Token {
     RightParen,
     LeftCurly,
     RightCurly,
+    LeftBracket,
+    RightBracket,
     Semicolon,
     Comma,
+    Bar,
+    ColonColon,
+    Plus,
+    EqualEqual,
     True,
     False,
     If,
@@ -21,48 +28,128 @@ pub enum Token {
     Handler,
     Return,
     Let,
+    Rec,
     In,
     With,
     Handle,
+    For,
+    Do,
+    Effect,
+    Match,
 }
 
+/// Why a line couldn't be split into tokens.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ScanError {
+    /// a character that can't start a token, or a `:` or `-` that isn't `::` or `->`
+    UnexpectedSymbol(char),
+    /// digits that don't fit in an `i64`
+    IntegerOutOfRange(String),
+}
+
+impl ScanError {
+    /// The stable code that `--explain` looks up.
+    pub fn code(&self) -> &'static str {
+        match self {
+            ScanError::UnexpectedSymbol(_) => "E1001",
+            ScanError::IntegerOutOfRange(_) => "E1002",
+        }
+    }
+}
+
+impl fmt::Display for ScanError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ScanError::UnexpectedSymbol(c) => write!(f, "unexpected symbol `{}`", c),
+            ScanError::IntegerOutOfRange(n) => write!(f, "integer literal `{}` out of range", n),
+        }
+    }
+}
+
+impl std::error::Error for ScanError {}
+
 pub struct Scanner {
     stream: String,
     pos: usize,
+    /// whether names may start with `$`
+    internal: bool,
 }
 
 impl Scanner {
-    pub fn scan(stream: String) -> anyhow::Result<Vec<Token>> {
-        let mut scanner = Self { stream, pos: 0 };
+    pub fn scan(stream: String) -> Result<Vec<Token>, ScanError> {
+        Self {
+            stream,
+            pos: 0,
+            internal: false,
+        }
+        .tokens()
+    }
+
+    /// Like `scan`, but names may also start with `$`, so the prelude can have operations that
+    /// no user program can name.
+    pub fn scan_prelude(stream: String) -> Result<Vec<Token>, ScanError> {
+        Self {
+            stream,
+            pos: 0,
+            internal: true,
+        }
+        .tokens()
+    }
+
+    fn tokens(mut self) -> Result<Vec<Token>, ScanError> {
         let mut tokens = Vec::new();
-        while let Some(token) = scanner.scan_token() {
+        while let Some(token) = self.scan_token() {
             tokens.push(token?);
         }
         Ok(tokens)
     }
 
-    fn scan_token(&mut self) -> Option<anyhow::Result<Token>> {
+    fn scan_token(&mut self) -> Option<Result<Token, ScanError>> {
         match self.advance()? {
             '\\' => Some(Ok(Token::Lambda)),
             '.' => Some(Ok(Token::Dot)),
-            '=' => Some(Ok(Token::Equal)),
+            '=' => {
+                if let Some('=') = self.peek() {
+                    self.advance();
+                    Some(Ok(Token::EqualEqual))
+                } else {
+                    Some(Ok(Token::Equal))
+                }
+            }
             '(' => Some(Ok(Token::LeftParen)),
             ')' => Some(Ok(Token::RightParen)),
             '{' => Some(Ok(Token::LeftCurly)),
             '}' => Some(Ok(Token::RightCurly)),
+            '[' => Some(Ok(Token::LeftBracket)),
+            ']' => Some(Ok(Token::RightBracket)),
             ';' => Some(Ok(Token::Semicolon)),
             ',' => Some(Ok(Token::Comma)),
+            '|' => Some(Ok(Token::Bar)),
+            '#' => {
+                while matches!(self.advance(), Some(c) if c != '\n') {}
+                self.scan_token()
+            }
+            '+' => Some(Ok(Token::Plus)),
+            ':' => {
+                if let Some(':') = self.advance() {
+                    Some(Ok(Token::ColonColon))
+                } else {
+                    Some(Err(ScanError::UnexpectedSymbol(':')))
+                }
+            }
             '-' => {
                 if let Some('>') = self.advance() {
                     Some(Ok(Token::Arrow))
                 } else {
-                    Some(Err(anyhow!("unexpected symbol `-`")))
+                    Some(Err(ScanError::UnexpectedSymbol('-')))
                 }
             }
             c => {
-                if c.is_alphabetic() {
+                if c.is_alphabetic() || c == '_' || (self.internal && c == '$') {
                     let start = self.pos - 1;
-                    while matches!(self.advance(), Some(c) if !" .,\\(){}=;".contains(c)) {}
+                    while matches!(self.advance(), Some(c) if !c.is_whitespace() && !".,\\(){}[]=;:|+-".contains(c))
+                    {
+                    }
                     self.pos -= 1;
                     let ident = self.stream.get(start..self.pos)?;
                     let tok = match ident {
@@ -74,23 +161,42 @@ impl Scanner {
                         "handler" => Token::Handler,
                         "return" => Token::Return,
                         "let" => Token::Let,
+                        "rec" => Token::Rec,
                         "in" => Token::In,
                         "with" => Token::With,
                         "handle" => Token::Handle,
+                        "for" => Token::For,
+                        "do" => Token::Do,
+                        "effect" => Token::Effect,
+                        "match" => Token::Match,
                         _ => Token::Identifier(ident.to_string()),
                     };
                     Some(Ok(tok))
+                } else if c.is_ascii_digit() {
+                    let start = self.pos - 1;
+                    while matches!(self.advance(), Some(c) if c.is_ascii_digit()) {}
+                    self.pos -= 1;
+                    let n = self.stream.get(start..self.pos)?;
+                    Some(
+                        n.parse()
+                            .map(Token::Int)
+                            .map_err(|_| ScanError::IntegerOutOfRange(n.to_string())),
+                    )
                 } else if c.is_whitespace() {
                     while matches!(self.advance(), Some(c) if c.is_whitespace()) {}
                     self.pos -= 1;
                     self.scan_token()
                 } else {
-                    Some(Err(anyhow!("invalid identifier")))
+                    Some(Err(ScanError::UnexpectedSymbol(c)))
                 }
             }
         }
     }
 
+    fn peek(&self) -> Option<char> {
+        self.stream.chars().nth(self.pos)
+    }
+
     fn advance(&mut self) -> Option<char> {
         self.pos += 1;
         self.stream.chars().nth(self.pos - 1)
@@ -118,7 +224,8 @@ mod tests {
                 Token::Identifier("y".into()),
                 Token::Dot,
                 Token::Return,
-                Token::Identifier("y".into())
+                Token::Identifier("y".into()),
+                Token::RightParen,
             ]
         );
     }
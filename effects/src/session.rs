@@ -0,0 +1,247 @@
+use std::{collections::HashSet, io, io::Write};
+
+use crate::{
+    host,
+    interpreter::substitute_comp,
+    parser::{Computation, Parser, Statement, Value},
+    prelude,
+    scanner::{Scanner, Token},
+    typer::{Type, Typer},
+};
+
+/// The operations and top-level definitions in scope for a REPL session.
+#[derive(Default)]
+pub struct Session {
+    ops: HashSet<String>,
+    /// evaluated top-level definitions, oldest first
+    defs: Vec<(String, Value)>,
+    /// the types of the definitions and the signatures of the operations
+    typer: Typer,
+}
+
+impl Session {
+    /// A session with the host operations and the prelude loaded.
+    pub fn with_prelude() -> Self {
+        let mut session = Self::default();
+        session.ops.extend(
+            host::OPERATIONS
+                .iter()
+                .chain(prelude::OPERATIONS)
+                .map(|op| op.to_string()),
+        );
+        for op in host::OPERATIONS {
+            session.typer.declare(op, Type::Unit);
+        }
+        Scanner::scan_prelude(prelude::PRELUDE.to_string())
+            .map_err(anyhow::Error::from)
+            .and_then(|tokens| session.run(tokens, &mut io::sink()))
+            .expect("prelude should load");
+        session
+    }
+
+    /// Typechecks and runs every statement in `src`, returning the value and type of the last
+    /// computation.
+    pub fn exec(
+        &mut self,
+        src: &str,
+        out: &mut dyn Write,
+    ) -> anyhow::Result<Option<(Value, Type)>> {
+        self.run(Scanner::scan(src.to_string())?, out)
+    }
+
+    fn run(
+        &mut self,
+        tokens: Vec<Token>,
+        out: &mut dyn Write,
+    ) -> anyhow::Result<Option<(Value, Type)>> {
+        let mut last = None;
+        for statement in Parser::parse_program(tokens, &mut self.ops)? {
+            match statement {
+                Statement::Effect(_) => {}
+                Statement::Let { x, c } => {
+                    // `x` isn't defined unless it gets a value, even if it typechecks
+                    let snapshot = self.typer.clone();
+                    let v = self
+                        .typer
+                        .define(&x, &c, host::OPERATIONS)
+                        .and_then(|_| host::run(&self.close(&c), out));
+                    match v {
+                        Ok(v) => self.defs.push((x, v)),
+                        Err(e) => {
+                            self.typer = snapshot;
+                            return Err(e);
+                        }
+                    }
+                }
+                Statement::Computation(c) => {
+                    let ty = self.typecheck(&c, host::OPERATIONS)?;
+                    last = Some((host::run(&self.close(&c), out)?, ty));
+                }
+            }
+        }
+        Ok(last)
+    }
+
+    /// Parses and typechecks a generator, closed over the session's definitions.
+    pub fn parse(&mut self, src: &str) -> anyhow::Result<Computation> {
+        let tokens = Scanner::scan(src.to_string())?;
+        let c = Parser::parse(tokens, &self.ops)?;
+        let allowed: Vec<_> = host::OPERATIONS.iter().chain(&["yield"]).cloned().collect();
+        self.typecheck(&c, &allowed)?;
+        Ok(self.close(&c))
+    }
+
+    /// Nothing a computation does to the operations' signatures outlives it, so `print 1` and
+    /// `print true` can be separate statements.
+    fn typecheck(&mut self, c: &Computation, allowed: &[&str]) -> anyhow::Result<Type> {
+        let snapshot = self.typer.clone();
+        let ty = self.typer.typecheck(c, allowed);
+        self.typer = snapshot;
+        ty
+    }
+
+    /// Substitutes the session's definitions into `c`.
+    fn close(&self, c: &Computation) -> Computation {
+        // later definitions shadow earlier ones, and definitions are closed, so go newest first
+        self.defs
+            .iter()
+            .rev()
+            .fold(c.clone(), |c, (x, v)| substitute_comp(&c, x, v))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::interpreter::{EvalError, MAX_DEPTH};
+
+    fn run(src: &str) -> String {
+        let mut out = Vec::new();
+        Session::with_prelude().exec(src, &mut out).unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn threads_alternate() {
+        let out = run("run (\\_.
+            let _ = fork (\\_. let _ = print 1 in let _ = pause () in print 3) in
+            fork (\\_. let _ = print 2 in let _ = pause () in print 4))");
+        assert_eq!(out, "1\n2\n3\n4\n");
+    }
+
+    #[test]
+    fn awaits_promise() {
+        let out = run("run (\\_.
+            let p = async (\\_. let _ = pause () in let _ = print 1 in return 42) in
+            let _ = print 0 in
+            let v = await p in
+            print v)");
+        assert_eq!(out, "0\n1\n42\n");
+    }
+
+    #[test]
+    fn threads_run_generators() {
+        let out = run("run (\\_.
+            let _ = fork (\\_. for x in (let _ = yield 1 in yield 3) do let _ = print x in pause ()) in
+            fork (\\_. for x in (let _ = yield 2 in yield 4) do let _ = print x in pause ()))");
+        assert_eq!(out, "1\n2\n3\n4\n");
+    }
+
+    #[test]
+    fn scheduler_is_internal() {
+        let mut session = Session::with_prelude();
+        let err = session.exec("$enqueue ()", &mut io::sink()).unwrap_err();
+        assert_eq!(err.to_string(), "unexpected symbol `$`");
+        // a user's `enqueue` is their own, and the scheduler doesn't handle it
+        let err = session
+            .exec("effect enqueue\nrun (\\_. enqueue ())", &mut io::sink())
+            .unwrap_err();
+        assert_eq!(err.to_string(), "operation `enqueue` is not handled");
+    }
+
+    #[test]
+    fn handlers_nest() {
+        let mut session = Session::with_prelude();
+        let mut eval = |src: &str| {
+            let (v, ty) = session.exec(src, &mut io::sink()).unwrap().unwrap();
+            format!("{} : {}", v, ty)
+        };
+        eval(
+            "effect set
+            effect raise
+            let state = return handler {
+              return x -> return \\s. return (x, s),
+              set(s; k) -> return \\_. k () s
+            }
+            let catch = return handler {
+              return x -> return [x],
+              raise(_; k) -> return []
+            }
+            let body = return \\_. let _ = set 1 in raise ()
+            return ()",
+        );
+        // `set` passes through `catch`, and `raise` through `state`
+        assert_eq!(
+            eval("with catch handle let f = with state handle body () in f 0"),
+            "[] : List (a, Int)"
+        );
+        assert_eq!(
+            eval("let f = with state handle with catch handle body () in f 0"),
+            "([], 1) : (List a, Int)"
+        );
+    }
+
+    /// Output that always fails, so that `print` fails at runtime.
+    struct Broken;
+
+    impl Write for Broken {
+        fn write(&mut self, _: &[u8]) -> io::Result<usize> {
+            Err(io::ErrorKind::BrokenPipe.into())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn failed_definitions_stay_undefined() {
+        let mut session = Session::with_prelude();
+        assert!(session.exec("let x = print 1", &mut Broken).is_err());
+        let err = session.exec("return x", &mut io::sink()).unwrap_err();
+        assert_eq!(err.to_string(), "undeclared variable `x`");
+    }
+
+    #[test]
+    fn divergence_is_an_error() {
+        let mut session = Session::with_prelude();
+        let err = session
+            .exec(
+                "let rec f x = let y = f x in return y in f 1",
+                &mut io::sink(),
+            )
+            .unwrap_err();
+        assert_eq!(
+            err.downcast_ref(),
+            Some(&EvalError::TooDeep { depth: MAX_DEPTH })
+        );
+        // the session carries on
+        let (v, _) = session.exec("return 1", &mut io::sink()).unwrap().unwrap();
+        assert_eq!(v.to_string(), "1");
+    }
+
+    #[test]
+    fn rejects_ill_typed() {
+        let mut session = Session::with_prelude();
+        let mut out = Vec::new();
+        assert!(session
+            .exec("let x = return 1 in x + true", &mut out)
+            .is_err());
+        assert!(session.exec("yield 1", &mut out).is_err());
+        let (_, ty) = session
+            .exec("let x = return 1 in x + 2", &mut out)
+            .unwrap()
+            .unwrap();
+        assert_eq!(ty, Type::Int);
+    }
+}
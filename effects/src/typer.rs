@@ -1,86 +1,812 @@
-use std::collections::HashMap;
+use std::{
+    collections::{BTreeSet, HashMap, HashSet},
+    fmt,
+};
 
-use anyhow::{anyhow, bail, Ok};
+use anyhow::{bail, Ok};
 
-use crate::parser::{Constant, Expr, Type};
+use crate::parser::{Computation, Constant, Handler, Prim, Value};
 
-#[derive(Default)]
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub enum Type {
+    Var(usize),
+    Bool,
+    Int,
+    Unit,
+    List(Box<Type>),
+    Pair(Box<Type>, Box<Type>),
+    /// A -> B ! E
+    Fn(Box<Type>, Box<CompType>),
+    /// A ! E => B ! E'
+    Handler(Box<CompType>, Box<CompType>),
+}
+
+/// A ! E: a computation returning `A` that may call the operations in `E`
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct CompType {
+    pub ty: Type,
+    pub row: Row,
+}
+
+/// {op1, op2 | r}: the operations `op1`, `op2` and whatever the row variable `r` stands for.
+/// A row without a tail is closed.
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct Row {
+    pub ops: BTreeSet<String>,
+    pub tail: Option<usize>,
+}
+
+/// forall vars rows. ty
+#[derive(Debug, Clone)]
+struct Scheme {
+    vars: Vec<usize>,
+    rows: Vec<usize>,
+    ty: Type,
+}
+
+/// Why a computation doesn't typecheck.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TypeError {
+    UndeclaredVariable {
+        x: String,
+    },
+    /// the type variable `var` would have to stand for `ty`, which contains it
+    InfiniteType {
+        var: Type,
+        ty: Type,
+    },
+    Mismatch {
+        t1: Type,
+        t2: Type,
+    },
+    /// `op` is called where nothing handles it
+    Unhandled {
+        op: String,
+    },
+}
+
+impl TypeError {
+    /// The stable code that `--explain` looks up.
+    pub fn code(&self) -> &'static str {
+        match self {
+            TypeError::UndeclaredVariable { .. } => "E1201",
+            TypeError::InfiniteType { .. } => "E1202",
+            TypeError::Mismatch { .. } => "E1203",
+            TypeError::Unhandled { .. } => "E1204",
+        }
+    }
+}
+
+impl fmt::Display for TypeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TypeError::UndeclaredVariable { x } => write!(f, "undeclared variable `{}`", x),
+            TypeError::InfiniteType { var, ty } => {
+                write!(f, "infinite type `{}` in `{}`", var, ty)
+            }
+            TypeError::Mismatch { t1, t2 } => {
+                write!(f, "mismatched types `{}` and `{}`", t1, t2)
+            }
+            TypeError::Unhandled { op } => write!(f, "operation `{}` is not handled", op),
+        }
+    }
+}
+
+impl std::error::Error for TypeError {}
+
+/// Infers types and effect rows.
+///
+/// Operation signatures are global, as if every operation had been declared with a single
+/// type: the first use of an operation fixes its parameter and result types. Effect rows
+/// only mention operations by name, and are polymorphic in their tails, so a function that
+/// calls `get` has type `A -> B ! {get | r}` and can be used under any handler for `get`.
+#[derive(Default, Clone)]
 pub struct Typer {
-    context: HashMap<String, Type>,
+    context: HashMap<String, Scheme>,
+    /// map from op name to (parameter, result)
+    ops: HashMap<String, (Type, Type)>,
+    types: HashMap<usize, Type>,
+    rows: HashMap<usize, Row>,
+    next: usize,
 }
 
 impl Typer {
-    pub fn typecheck(&mut self, e: &Expr) -> anyhow::Result<Type> {
-        match e {
-            Expr::Var(x) => self.var(x),
-            Expr::Constant(c) => self.constant(c),
-            Expr::App { e1, e2 } => self.app(e1, e2),
-            Expr::Abs { x, t, e } => self.abs(x, t, e),
-            Expr::If { e1, e2, e3 } => self.r#if(e1, e2, e3),
+    /// Fixes the result type of `op`.
+    pub fn declare(&mut self, op: &str, result: Type) {
+        let param = self.fresh();
+        self.ops.insert(op.to_string(), (param, result));
+    }
+
+    /// Infers the type of a computation that may only call the operations in `allowed`.
+    pub fn typecheck(&mut self, c: &Computation, allowed: &[&str]) -> anyhow::Result<Type> {
+        let ct = self.comp(c)?;
+        self.allow(&ct.row, allowed)?;
+        Ok(self.zonk(&ct.ty))
+    }
+
+    /// Typechecks the top-level definition `let x = c` and adds `x` to the context.
+    pub fn define(&mut self, x: &str, c: &Computation, allowed: &[&str]) -> anyhow::Result<Type> {
+        let ct = self.comp(c)?;
+        self.allow(&ct.row, allowed)?;
+        let scheme = if is_value(c) {
+            self.generalize(&ct.ty)
+        } else {
+            Scheme::mono(ct.ty)
+        };
+        let ty = self.zonk(&scheme.ty);
+        self.context.insert(x.to_string(), scheme);
+        Ok(ty)
+    }
+
+    fn allow(&mut self, row: &Row, allowed: &[&str]) -> anyhow::Result<()> {
+        let allowed = Row {
+            ops: allowed.iter().map(|op| op.to_string()).collect(),
+            tail: None,
+        };
+        self.unify_row(row, &allowed)
+    }
+
+    fn comp(&mut self, c: &Computation) -> anyhow::Result<CompType> {
+        match c {
+            Computation::Return(v) => Ok(CompType {
+                ty: self.value(v)?,
+                row: self.fresh_row(),
+            }),
+            Computation::OpCall { op, param, y, c } => self.op_call(op, param, y, c),
+            Computation::Seq { x, c1, c2 } => self.seq(x, c1, c2),
+            Computation::If { v, c1, c2 } => self.r#if(v, c1, c2),
+            Computation::App { v1, v2 } => self.app(v1, v2),
+            Computation::Handling { with, handle } => self.handling(with, handle),
+            Computation::LetRec { f, x, c1, c2 } => self.let_rec(f, x, c1, c2),
+            Computation::MatchList { v, c1, x, xs, c2 } => self.match_list(v, c1, x, xs, c2),
+            Computation::MatchPair { v, x, y, c } => self.match_pair(v, x, y, c),
+            Computation::Prim { op, v1, v2 } => self.prim(*op, v1, v2),
         }
     }
 
-    fn var(&self, var: &String) -> anyhow::Result<Type> {
-        Ok(self
-            .context
-            .get(var)
-            .ok_or_else(|| anyhow!("undeclared variable `{}`", var))?
-            .clone())
+    fn value(&mut self, v: &Value) -> anyhow::Result<Type> {
+        match v {
+            Value::Var(x) => self.var(x),
+            Value::Constant(c) => Ok(self.constant(c)),
+            Value::Fun { x, c } => {
+                let a = self.fresh();
+                let ct = self.bind(x, Scheme::mono(a.clone()), |typer| typer.comp(c))?;
+                Ok(Type::Fn(Box::new(a), Box::new(ct)))
+            }
+            Value::Handler(h) => self.handler(h),
+            Value::Nil => Ok(Type::List(Box::new(self.fresh()))),
+            Value::Cons(v, vs) => {
+                let t = self.value(v)?;
+                let ts = self.value(vs)?;
+                self.unify(&Type::List(Box::new(t)), &ts)?;
+                Ok(ts)
+            }
+            Value::Pair(v1, v2) => Ok(Type::Pair(
+                Box::new(self.value(v1)?),
+                Box::new(self.value(v2)?),
+            )),
+        }
     }
 
-    fn constant(&self, constant: &Constant) -> anyhow::Result<Type> {
-        match constant {
-            Constant::True | Constant::False => Ok(Type::Bool),
+    fn var(&mut self, x: &str) -> anyhow::Result<Type> {
+        match self.context.get(x) {
+            Some(scheme) => {
+                let scheme = scheme.clone();
+                Ok(self.instantiate(&scheme))
+            }
+            None => bail!(TypeError::UndeclaredVariable { x: x.to_string() }),
         }
     }
 
-    fn app(&mut self, e1: &Expr, e2: &Expr) -> anyhow::Result<Type> {
-        if let Type::Fn(a, b) = self.typecheck(e1)? {
-            let t2 = self.typecheck(e2)?;
-            if a.as_ref() == &t2 {
-                Ok(*b)
-            } else {
-                bail!("invalid argument; expected type `{:?}`", a);
+    fn constant(&self, c: &Constant) -> Type {
+        match c {
+            Constant::True | Constant::False => Type::Bool,
+            Constant::Unit => Type::Unit,
+            Constant::Int(_) => Type::Int,
+        }
+    }
+
+    /// A ! {op1, ..., opn | r} => B ! {r}
+    fn handler(&mut self, h: &Handler) -> anyhow::Result<Type> {
+        let a = self.fresh();
+        let out = CompType {
+            ty: self.fresh(),
+            row: self.fresh_row(),
+        };
+        for (op, (x, k, c)) in &h.ops {
+            let (param, result) = self.op(op);
+            let k_ty = Type::Fn(Box::new(result), Box::new(out.clone()));
+            let ct = self.bind(x, Scheme::mono(param), |typer| {
+                typer.bind(k, Scheme::mono(k_ty), |typer| typer.comp(c))
+            })?;
+            self.unify_comp(&ct, &out)?;
+        }
+        match &h.ret {
+            Some((x, c)) => {
+                let ct = self.bind(x, Scheme::mono(a.clone()), |typer| typer.comp(c))?;
+                self.unify_comp(&ct, &out)?;
             }
+            None => self.unify(&a, &out.ty)?,
+        }
+        // operations the handler doesn't mention are forwarded to the outer handlers
+        let input = CompType {
+            ty: a,
+            row: Row {
+                ops: h.ops.keys().cloned().collect(),
+                tail: out.row.tail,
+            },
+        };
+        Ok(Type::Handler(Box::new(input), Box::new(out)))
+    }
+
+    fn op_call(
+        &mut self,
+        op: &str,
+        param: &Value,
+        y: &str,
+        c: &Computation,
+    ) -> anyhow::Result<CompType> {
+        let (a, b) = self.op(op);
+        let t = self.value(param)?;
+        self.unify(&a, &t)?;
+        let ct = self.bind(y, Scheme::mono(b), |typer| typer.comp(c))?;
+        let row = Row {
+            ops: BTreeSet::from([op.to_string()]),
+            tail: Some(self.next_var()),
+        };
+        self.unify_row(&ct.row, &row)?;
+        Ok(ct)
+    }
+
+    fn seq(&mut self, x: &str, c1: &Computation, c2: &Computation) -> anyhow::Result<CompType> {
+        let ct1 = self.comp(c1)?;
+        let scheme = if is_value(c1) {
+            self.generalize(&ct1.ty)
         } else {
-            bail!("expected function type, got `{:?}`", e1);
+            Scheme::mono(ct1.ty)
+        };
+        let ct2 = self.bind(x, scheme, |typer| typer.comp(c2))?;
+        self.unify_row(&ct1.row, &ct2.row)?;
+        Ok(ct2)
+    }
+
+    fn r#if(&mut self, v: &Value, c1: &Computation, c2: &Computation) -> anyhow::Result<CompType> {
+        let t = self.value(v)?;
+        self.unify(&t, &Type::Bool)?;
+        let ct1 = self.comp(c1)?;
+        let ct2 = self.comp(c2)?;
+        self.unify_comp(&ct1, &ct2)?;
+        Ok(ct1)
+    }
+
+    fn app(&mut self, v1: &Value, v2: &Value) -> anyhow::Result<CompType> {
+        let t1 = self.value(v1)?;
+        let t2 = self.value(v2)?;
+        let ct = CompType {
+            ty: self.fresh(),
+            row: self.fresh_row(),
+        };
+        self.unify(&t1, &Type::Fn(Box::new(t2), Box::new(ct.clone())))?;
+        Ok(ct)
+    }
+
+    fn handling(&mut self, with: &Value, handle: &Computation) -> anyhow::Result<CompType> {
+        let h = self.value(with)?;
+        let ct = self.comp(handle)?;
+        let out = CompType {
+            ty: self.fresh(),
+            row: self.fresh_row(),
+        };
+        self.unify(&h, &Type::Handler(Box::new(ct), Box::new(out.clone())))?;
+        Ok(out)
+    }
+
+    fn let_rec(
+        &mut self,
+        f: &str,
+        x: &str,
+        c1: &Computation,
+        c2: &Computation,
+    ) -> anyhow::Result<CompType> {
+        let a = self.fresh();
+        let body = CompType {
+            ty: self.fresh(),
+            row: self.fresh_row(),
+        };
+        let f_ty = Type::Fn(Box::new(a.clone()), Box::new(body.clone()));
+        let ct1 = self.bind(f, Scheme::mono(f_ty.clone()), |typer| {
+            typer.bind(x, Scheme::mono(a), |typer| typer.comp(c1))
+        })?;
+        self.unify_comp(&ct1, &body)?;
+        let scheme = self.generalize(&f_ty);
+        self.bind(f, scheme, |typer| typer.comp(c2))
+    }
+
+    fn match_list(
+        &mut self,
+        v: &Value,
+        c1: &Computation,
+        x: &str,
+        xs: &str,
+        c2: &Computation,
+    ) -> anyhow::Result<CompType> {
+        let a = self.fresh();
+        let list = Type::List(Box::new(a.clone()));
+        let t = self.value(v)?;
+        self.unify(&t, &list)?;
+        let ct1 = self.comp(c1)?;
+        let ct2 = self.bind(x, Scheme::mono(a), |typer| {
+            typer.bind(xs, Scheme::mono(list), |typer| typer.comp(c2))
+        })?;
+        self.unify_comp(&ct1, &ct2)?;
+        Ok(ct1)
+    }
+
+    fn match_pair(
+        &mut self,
+        v: &Value,
+        x: &str,
+        y: &str,
+        c: &Computation,
+    ) -> anyhow::Result<CompType> {
+        let (a, b) = (self.fresh(), self.fresh());
+        let t = self.value(v)?;
+        self.unify(&t, &Type::Pair(Box::new(a.clone()), Box::new(b.clone())))?;
+        self.bind(x, Scheme::mono(a), |typer| {
+            typer.bind(y, Scheme::mono(b), |typer| typer.comp(c))
+        })
+    }
+
+    fn prim(&mut self, op: Prim, v1: &Value, v2: &Value) -> anyhow::Result<CompType> {
+        let t1 = self.value(v1)?;
+        let t2 = self.value(v2)?;
+        let ty = match op {
+            Prim::Add => {
+                self.unify(&t1, &Type::Int)?;
+                self.unify(&t2, &Type::Int)?;
+                Type::Int
+            }
+            Prim::Eq => {
+                self.unify(&t1, &t2)?;
+                Type::Bool
+            }
+        };
+        Ok(CompType {
+            ty,
+            row: self.fresh_row(),
+        })
+    }
+
+    /// Runs `f` with `x` bound to `scheme`, then restores whatever `x` was bound to before.
+    fn bind<T>(
+        &mut self,
+        x: &str,
+        scheme: Scheme,
+        f: impl FnOnce(&mut Self) -> anyhow::Result<T>,
+    ) -> anyhow::Result<T> {
+        let shadowed = self.context.insert(x.to_string(), scheme);
+        let result = f(self);
+        match shadowed {
+            Some(scheme) => self.context.insert(x.to_string(), scheme),
+            None => self.context.remove(x),
+        };
+        result
+    }
+
+    fn op(&mut self, op: &str) -> (Type, Type) {
+        if !self.ops.contains_key(op) {
+            let sig = (self.fresh(), self.fresh());
+            self.ops.insert(op.to_string(), sig);
+        }
+        self.ops[op].clone()
+    }
+
+    fn next_var(&mut self) -> usize {
+        self.next += 1;
+        self.next
+    }
+
+    fn fresh(&mut self) -> Type {
+        Type::Var(self.next_var())
+    }
+
+    fn fresh_row(&mut self) -> Row {
+        Row {
+            ops: BTreeSet::new(),
+            tail: Some(self.next_var()),
+        }
+    }
+
+    fn unify(&mut self, t1: &Type, t2: &Type) -> anyhow::Result<()> {
+        let t1 = self.resolve(t1);
+        let t2 = self.resolve(t2);
+        match (&t1, &t2) {
+            (Type::Var(a), Type::Var(b)) if a == b => Ok(()),
+            (Type::Var(a), t) | (t, Type::Var(a)) => {
+                if self.occurs(*a, t) {
+                    bail!(TypeError::InfiniteType {
+                        var: self.zonk(&Type::Var(*a)),
+                        ty: self.zonk(t),
+                    });
+                }
+                self.types.insert(*a, t.clone());
+                Ok(())
+            }
+            (Type::Bool, Type::Bool) | (Type::Int, Type::Int) | (Type::Unit, Type::Unit) => Ok(()),
+            (Type::List(a), Type::List(b)) => self.unify(a, b),
+            (Type::Pair(a1, b1), Type::Pair(a2, b2)) => {
+                self.unify(a1, a2)?;
+                self.unify(b1, b2)
+            }
+            (Type::Fn(a1, c1), Type::Fn(a2, c2)) => {
+                self.unify(a1, a2)?;
+                self.unify_comp(c1, c2)
+            }
+            (Type::Handler(c1, d1), Type::Handler(c2, d2)) => {
+                self.unify_comp(c1, c2)?;
+                self.unify_comp(d1, d2)
+            }
+            _ => bail!(TypeError::Mismatch {
+                t1: self.zonk(&t1),
+                t2: self.zonk(&t2),
+            }),
         }
     }
 
-    fn abs(&mut self, x: &String, t: &Type, e: &Expr) -> anyhow::Result<Type> {
-        self.context.insert(x.to_string(), t.clone());
-        let t2 = self.typecheck(e)?;
-        self.context.remove(x);
-        Ok(Type::Fn(Box::new(t.clone()), Box::new(t2.clone())))
+    fn unify_comp(&mut self, c1: &CompType, c2: &CompType) -> anyhow::Result<()> {
+        self.unify(&c1.ty, &c2.ty)?;
+        self.unify_row(&c1.row, &c2.row)
+    }
+
+    /// Rows are sets, so `{get | r}` and `{get, get | r}` are the same row.
+    fn unify_row(&mut self, r1: &Row, r2: &Row) -> anyhow::Result<()> {
+        let r1 = self.resolve_row(r1);
+        let r2 = self.resolve_row(r2);
+        let only1: BTreeSet<_> = r1.ops.difference(&r2.ops).cloned().collect();
+        let only2: BTreeSet<_> = r2.ops.difference(&r1.ops).cloned().collect();
+        match (r1.tail, r2.tail) {
+            (None, None) => {
+                if let Some(op) = only1.iter().chain(&only2).next() {
+                    bail!(TypeError::Unhandled { op: op.clone() });
+                }
+            }
+            (Some(a), None) => {
+                if let Some(op) = only1.iter().next() {
+                    bail!(TypeError::Unhandled { op: op.clone() });
+                }
+                self.rows.insert(a, Row::closed(only2));
+            }
+            (None, Some(b)) => {
+                if let Some(op) = only2.iter().next() {
+                    bail!(TypeError::Unhandled { op: op.clone() });
+                }
+                self.rows.insert(b, Row::closed(only1));
+            }
+            (Some(a), Some(b)) if a == b => {
+                if !only1.is_empty() || !only2.is_empty() {
+                    let tail = Some(self.next_var());
+                    let ops = only1.union(&only2).cloned().collect();
+                    self.rows.insert(a, Row { ops, tail });
+                }
+            }
+            (Some(a), Some(b)) => {
+                let tail = Some(self.next_var());
+                self.rows.insert(a, Row { ops: only2, tail });
+                self.rows.insert(b, Row { ops: only1, tail });
+            }
+        }
+        Ok(())
     }
 
-    fn r#if(&mut self, e1: &Expr, e2: &Expr, e3: &Expr) -> anyhow::Result<Type> {
-        if let Type::Bool = self.typecheck(e1)? {
-            let t2 = self.typecheck(e2)?;
-            let t3 = self.typecheck(e3)?;
-            if t2 == t3 {
-                Ok(t2)
-            } else {
-                bail!("mismatched `if` branches `{:?}` and `{:?}`", t2, t3);
+    /// Follows type variables until `t` is no longer a solved variable.
+    fn resolve(&self, t: &Type) -> Type {
+        let mut t = t;
+        while let Type::Var(a) = t {
+            match self.types.get(a) {
+                Some(next) => t = next,
+                None => break,
+            }
+        }
+        t.clone()
+    }
+
+    /// Flattens solved row variables into `row`.
+    fn resolve_row(&self, row: &Row) -> Row {
+        let mut ops = row.ops.clone();
+        let mut tail = row.tail;
+        while let Some(r) = tail.and_then(|r| self.rows.get(&r)) {
+            ops.extend(r.ops.iter().cloned());
+            tail = r.tail;
+        }
+        Row { ops, tail }
+    }
+
+    fn occurs(&self, a: usize, t: &Type) -> bool {
+        let mut vars = HashSet::new();
+        self.zonk(t).free_vars(&mut vars, &mut HashSet::new());
+        vars.contains(&a)
+    }
+
+    /// Applies every solved variable in `t`.
+    fn zonk(&self, t: &Type) -> Type {
+        match self.resolve(t) {
+            t @ (Type::Var(_) | Type::Bool | Type::Int | Type::Unit) => t,
+            Type::List(a) => Type::List(Box::new(self.zonk(&a))),
+            Type::Pair(a, b) => Type::Pair(Box::new(self.zonk(&a)), Box::new(self.zonk(&b))),
+            Type::Fn(a, c) => Type::Fn(Box::new(self.zonk(&a)), Box::new(self.zonk_comp(&c))),
+            Type::Handler(c, d) => {
+                Type::Handler(Box::new(self.zonk_comp(&c)), Box::new(self.zonk_comp(&d)))
+            }
+        }
+    }
+
+    fn zonk_comp(&self, c: &CompType) -> CompType {
+        CompType {
+            ty: self.zonk(&c.ty),
+            row: self.resolve_row(&c.row),
+        }
+    }
+
+    /// Quantifies over the variables in `t` that aren't pinned down by the context or by an
+    /// operation's signature.
+    fn generalize(&self, t: &Type) -> Scheme {
+        let ty = self.zonk(t);
+        let (mut vars, mut rows) = (HashSet::new(), HashSet::new());
+        ty.free_vars(&mut vars, &mut rows);
+
+        let (mut env_vars, mut env_rows) = (HashSet::new(), HashSet::new());
+        let env = self
+            .context
+            .values()
+            .flat_map(|s| {
+                let ty = self.zonk(&s.ty);
+                let (mut vars, mut rows) = (HashSet::new(), HashSet::new());
+                ty.free_vars(&mut vars, &mut rows);
+                s.vars.iter().for_each(|a| {
+                    vars.remove(a);
+                });
+                s.rows.iter().for_each(|r| {
+                    rows.remove(r);
+                });
+                [(vars, rows)]
+            })
+            .chain(self.ops.values().flat_map(|(a, b)| {
+                let (mut vars, mut rows) = (HashSet::new(), HashSet::new());
+                self.zonk(a).free_vars(&mut vars, &mut rows);
+                self.zonk(b).free_vars(&mut vars, &mut rows);
+                [(vars, rows)]
+            }));
+        for (vars, rows) in env {
+            env_vars.extend(vars);
+            env_rows.extend(rows);
+        }
+
+        Scheme {
+            vars: vars.difference(&env_vars).cloned().collect(),
+            rows: rows.difference(&env_rows).cloned().collect(),
+            ty,
+        }
+    }
+
+    fn instantiate(&mut self, scheme: &Scheme) -> Type {
+        let vars: HashMap<_, _> = scheme.vars.iter().map(|a| (*a, self.fresh())).collect();
+        let rows: HashMap<_, _> = scheme.rows.iter().map(|r| (*r, self.next_var())).collect();
+        self.zonk(&scheme.ty).rename(&vars, &rows)
+    }
+}
+
+impl Scheme {
+    fn mono(ty: Type) -> Self {
+        Self {
+            vars: Vec::new(),
+            rows: Vec::new(),
+            ty,
+        }
+    }
+}
+
+impl Row {
+    fn closed(ops: BTreeSet<String>) -> Self {
+        Self { ops, tail: None }
+    }
+}
+
+impl Type {
+    fn free_vars(&self, vars: &mut HashSet<usize>, rows: &mut HashSet<usize>) {
+        match self {
+            Type::Var(a) => {
+                vars.insert(*a);
+            }
+            Type::Bool | Type::Int | Type::Unit => {}
+            Type::List(a) => a.free_vars(vars, rows),
+            Type::Pair(a, b) => {
+                a.free_vars(vars, rows);
+                b.free_vars(vars, rows);
+            }
+            Type::Fn(a, c) => {
+                a.free_vars(vars, rows);
+                c.free_vars(vars, rows);
+            }
+            Type::Handler(c, d) => {
+                c.free_vars(vars, rows);
+                d.free_vars(vars, rows);
             }
+        }
+    }
+
+    fn rename(&self, vars: &HashMap<usize, Type>, rows: &HashMap<usize, usize>) -> Type {
+        match self {
+            Type::Var(a) => vars.get(a).cloned().unwrap_or(Type::Var(*a)),
+            Type::Bool | Type::Int | Type::Unit => self.clone(),
+            Type::List(a) => Type::List(Box::new(a.rename(vars, rows))),
+            Type::Pair(a, b) => Type::Pair(
+                Box::new(a.rename(vars, rows)),
+                Box::new(b.rename(vars, rows)),
+            ),
+            Type::Fn(a, c) => Type::Fn(
+                Box::new(a.rename(vars, rows)),
+                Box::new(c.rename(vars, rows)),
+            ),
+            Type::Handler(c, d) => Type::Handler(
+                Box::new(c.rename(vars, rows)),
+                Box::new(d.rename(vars, rows)),
+            ),
+        }
+    }
+}
+
+impl CompType {
+    fn free_vars(&self, vars: &mut HashSet<usize>, rows: &mut HashSet<usize>) {
+        self.ty.free_vars(vars, rows);
+        if let Some(r) = self.row.tail {
+            rows.insert(r);
+        }
+    }
+
+    fn rename(&self, vars: &HashMap<usize, Type>, rows: &HashMap<usize, usize>) -> CompType {
+        CompType {
+            ty: self.ty.rename(vars, rows),
+            row: Row {
+                ops: self.row.ops.clone(),
+                tail: self.row.tail.map(|r| rows.get(&r).cloned().unwrap_or(r)),
+            },
+        }
+    }
+}
+
+/// Computations whose type can be generalized: they can't call operations.
+fn is_value(c: &Computation) -> bool {
+    match c {
+        Computation::Return(_) => true,
+        Computation::LetRec { c2, .. } => is_value(c2),
+        _ => false,
+    }
+}
+
+impl fmt::Display for Type {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut names = Names::default();
+        write!(f, "{}", names.ty(self, false))
+    }
+}
+
+/// Gives type variables the names `a`, `b`, ... and row variables `r`, `r1`, ... in order of
+/// appearance.
+#[derive(Default)]
+struct Names {
+    vars: HashMap<usize, String>,
+    rows: HashMap<usize, String>,
+}
+
+impl Names {
+    fn ty(&mut self, t: &Type, atomic: bool) -> String {
+        let s = match t {
+            Type::Var(a) => {
+                let n = self.vars.len();
+                return self
+                    .vars
+                    .entry(*a)
+                    .or_insert_with(|| {
+                        let c = (b'a' + (n % 26) as u8) as char;
+                        if n < 26 {
+                            c.to_string()
+                        } else {
+                            format!("{c}{}", n / 26)
+                        }
+                    })
+                    .clone();
+            }
+            Type::Bool => return "Bool".to_string(),
+            Type::Int => return "Int".to_string(),
+            Type::Unit => return "Unit".to_string(),
+            Type::Pair(a, b) => return format!("({}, {})", self.ty(a, false), self.ty(b, false)),
+            Type::List(a) => format!("List {}", self.ty(a, true)),
+            Type::Fn(a, c) => {
+                let a = self.ty(a, matches!(**a, Type::Fn(..) | Type::Handler(..)));
+                format!("{} -> {}", a, self.comp(c))
+            }
+            Type::Handler(c, d) => format!("{} => {}", self.comp(c), self.comp(d)),
+        };
+        if atomic {
+            format!("({s})")
         } else {
-            bail!("expected boolean");
+            s
+        }
+    }
+
+    /// Leaves off rows that don't mention any operations.
+    fn comp(&mut self, c: &CompType) -> String {
+        if c.row.ops.is_empty() {
+            let inner = matches!(c.ty, Type::Handler(..));
+            self.ty(&c.ty, inner)
+        } else {
+            let ty = self.ty(&c.ty, matches!(c.ty, Type::Fn(..) | Type::Handler(..)));
+            format!("{} ! {}", ty, self.row(&c.row))
+        }
+    }
+
+    fn row(&mut self, row: &Row) -> String {
+        let ops: Vec<_> = row.ops.iter().cloned().collect();
+        match row.tail {
+            Some(r) => {
+                let n = self.rows.len();
+                let tail = self
+                    .rows
+                    .entry(r)
+                    .or_insert_with(|| {
+                        if n == 0 {
+                            "r".to_string()
+                        } else {
+                            format!("r{n}")
+                        }
+                    })
+                    .clone();
+                format!("{{{} | {}}}", ops.join(", "), tail)
+            }
+            None => format!("{{{}}}", ops.join(", ")),
         }
     }
 }
 
 #[cfg(test)]
 mod tests {
+    use std::collections::HashSet;
+
     use super::*;
+    use crate::{parser::Parser, scanner::Scanner};
+
+    fn typecheck(s: &str, allowed: &[&str]) -> anyhow::Result<Type> {
+        let ops: HashSet<String> = ["get", "set", "print"]
+            .iter()
+            .map(|op| op.to_string())
+            .collect();
+        let c = Parser::parse(Scanner::scan(s.to_string()).unwrap(), &ops).unwrap();
+        Typer::default().typecheck(&c, allowed)
+    }
 
     #[test]
-    fn works() {
-        let e = Expr::Abs {
-            x: "x".into(),
-            t: Type::Bool,
-            e: Box::new(Expr::Var("x".into())),
-        };
-        let ty = Typer::default().typecheck(&e).unwrap();
-        assert_eq!(ty, Type::Fn(Box::new(Type::Bool), Box::new(Type::Bool)));
+    fn infers_rows() {
+        let ty = typecheck("return \\x. let y = get x in return y", &[]).unwrap();
+        assert_eq!(ty.to_string(), "a -> b ! {get | r}");
+    }
+
+    #[test]
+    fn row_polymorphism() {
+        // `f` calls `get`, and is used both where `get` is the only operation and where `print`
+        // is also allowed
+        let src = "let f = return \\u. get u in
+            let h = return handler { get(u; k) -> k 1 } in
+            let a = with h handle f () in
+            with h handle (let b = f () in let _ = print b in return a)";
+        assert_eq!(typecheck(src, &["print"]).unwrap(), Type::Int);
+        assert!(typecheck("get ()", &["print"]).is_err());
+    }
+
+    #[test]
+    fn handlers_forward() {
+        // the handler for `get` leaves `set` to the top level
+        let src = "with handler { get(u; k) -> k 1 } handle (let x = get () in set x)";
+        assert!(typecheck(src, &["set"]).is_ok());
+        assert!(typecheck(src, &[]).is_err());
     }
 }
@@ -0,0 +1,175 @@
+use crate::parser::{Expr, Type};
+
+/// `forall X. (X -> X) -> X -> X`
+pub fn numeral_type() -> Type {
+    let x = || Box::new(Type::Var("X".into()));
+    Type::Forall(
+        "X".into(),
+        Box::new(Type::Fn(
+            Box::new(Type::Fn(x(), x())),
+            Box::new(Type::Fn(x(), x())),
+        )),
+    )
+}
+
+/// The Church numeral `/\X. \f: X -> X. \x: X. f (... (f x))`, applying `f` `n` times.
+pub fn encode(n: u64) -> Expr {
+    let x = || Type::Var("X".into());
+    let body = (0..n).fold(Expr::Var("x".into()), |e, _| Expr::App {
+        e1: Box::new(Expr::Var("f".into())),
+        e2: Box::new(e),
+    });
+    Expr::TyAbs {
+        x: "X".into(),
+        e: Box::new(Expr::Abs {
+            x: "f".into(),
+            t: Some(Type::Fn(Box::new(x()), Box::new(x()))),
+            e: Box::new(Expr::Abs {
+                x: "x".into(),
+                t: Some(x()),
+                e: Box::new(body),
+            }),
+        }),
+    }
+}
+
+/// Reads a Church numeral back from a normal form, with or without the type abstraction and
+/// annotations, so `\f. \x. f (f x)` decodes to 2.
+pub fn decode(e: &Expr) -> Option<u64> {
+    let e = match e {
+        Expr::TyAbs { x: _, e } => e,
+        e => e,
+    };
+    let Expr::Abs { x: f, t: _, e } = e else {
+        return None;
+    };
+    let Expr::Abs { x, t: _, e } = e.as_ref() else {
+        return None;
+    };
+    if f == x {
+        return None;
+    }
+    let mut n = 0;
+    let mut e = e.as_ref();
+    while let Expr::App { e1, e2 } = e {
+        if e1.as_ref() != &Expr::Var(f.to_string()) {
+            return None;
+        }
+        n += 1;
+        e = e2;
+    }
+    (e == &Expr::Var(x.to_string())).then_some(n)
+}
+
+/// `mu N. forall R. R -> (N -> R) -> R`, the type of the prelude's Scott numerals
+pub fn scott_numeral_type() -> Type {
+    let var = |x: &str| Box::new(Type::Var(x.into()));
+    Type::Mu(
+        "N".into(),
+        Box::new(Type::Forall(
+            "R".into(),
+            Box::new(Type::Fn(
+                var("R"),
+                Box::new(Type::Fn(Box::new(Type::Fn(var("N"), var("R"))), var("R"))),
+            )),
+        )),
+    )
+}
+
+/// Reads a Scott numeral back from a normal form: `fold [T] (/\R. \z: R. \s: T -> R. z)` is 0,
+/// and `s n` in place of `z` is one more than `n`.
+pub fn decode_scott(e: &Expr) -> Option<u64> {
+    let mut n = 0;
+    let mut e = e;
+    loop {
+        let Expr::Fold { t: _, e: body } = e else {
+            return None;
+        };
+        let body = match body.as_ref() {
+            Expr::TyAbs { x: _, e } => e,
+            e => e,
+        };
+        let Expr::Abs {
+            x: z,
+            t: _,
+            e: body,
+        } = body
+        else {
+            return None;
+        };
+        let Expr::Abs {
+            x: s,
+            t: _,
+            e: body,
+        } = body.as_ref()
+        else {
+            return None;
+        };
+        match body.as_ref() {
+            Expr::Var(x) if x == z && z != s => return Some(n),
+            Expr::App { e1, e2 } if e1.as_ref() == &Expr::Var(s.to_string()) => {
+                n += 1;
+                e = e2;
+            }
+            _ => return None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::AtomicBool;
+
+    use super::*;
+    use crate::{
+        interpreter::{normalize, EvalLimits},
+        session::{Outcome, Session},
+        typer::{alpha_eq, Typer},
+    };
+
+    #[test]
+    fn round_trip() {
+        for n in [0, 1, 2, 10] {
+            let e = encode(n);
+            assert_eq!(Typer::default().typecheck(&e).unwrap(), numeral_type());
+            assert_eq!(decode(&e), Some(n));
+        }
+        assert_eq!(encode(2).to_string(), "/\\X. \\f: X -> X. \\x: X. f (f x)");
+    }
+
+    #[test]
+    fn decodes_normal_forms() {
+        let mut session = Session::with_prelude();
+        session.exec("two = succ (succ zero)").unwrap();
+        let Ok(Outcome::Value(e, _)) = session.exec("plus (mult two two) (pred two)") else {
+            panic!("not a value");
+        };
+        assert_eq!(
+            decode(&normalize(&e, &EvalLimits::default(), &AtomicBool::new(false)).unwrap()),
+            Some(5)
+        );
+        let Ok(Outcome::Value(e, _)) = session.exec("ctrue") else {
+            panic!("not a value");
+        };
+        assert_eq!(
+            decode(&normalize(&e, &EvalLimits::default(), &AtomicBool::new(false)).unwrap()),
+            None
+        );
+    }
+
+    #[test]
+    fn decodes_scott_numerals() {
+        let mut session = Session::with_prelude();
+        let mut decode = |line: &str| {
+            let Ok(Outcome::Value(e, ty)) = session.exec(line) else {
+                panic!("not a value");
+            };
+            assert!(alpha_eq(&ty, &scott_numeral_type()), "{ty}");
+            decode_scott(&normalize(&e, &EvalLimits::default(), &AtomicBool::new(false)).unwrap())
+        };
+        assert_eq!(decode("szero"), Some(0));
+        assert_eq!(decode("ssucc (ssucc (ssucc szero))"), Some(3));
+        assert_eq!(decode("spred (spred (ssucc szero))"), Some(0));
+        assert_eq!(decode_scott(&encode(2)), None);
+    }
+}
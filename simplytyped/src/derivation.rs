@@ -0,0 +1,445 @@
+use std::fmt;
+
+use crate::{
+    parser::{Constant, Expr, Type},
+    pretty,
+    scanner::{Scanner, Token},
+};
+
+/// A typing derivation: the rule used to conclude `context |- expr : ty` from `premises`.
+#[derive(Debug, Clone)]
+pub struct Derivation {
+    pub rule: Rule,
+    /// the variables in scope, sorted by name
+    pub context: Vec<(String, Type)>,
+    pub expr: Expr,
+    pub ty: Type,
+    pub premises: Vec<Derivation>,
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum Rule {
+    Var,
+    Abs,
+    App,
+    If,
+    True,
+    False,
+    Ann,
+    TyAbs,
+    TyApp,
+    Unit,
+    Ref,
+    Deref,
+    Assign,
+    Loc,
+    Fold,
+    Unfold,
+    Raise,
+    Try,
+    List,
+    Nil,
+    Cons,
+    IsNil,
+    Head,
+    Tail,
+    Foldr,
+    Con,
+    Match,
+    String,
+    Char,
+    Concat,
+    Length,
+    Eq,
+    CharAt,
+    Hole,
+}
+
+impl fmt::Display for Rule {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Rule::Var => "T-Var",
+            Rule::Abs => "T-Abs",
+            Rule::App => "T-App",
+            Rule::If => "T-If",
+            Rule::True => "T-True",
+            Rule::False => "T-False",
+            Rule::Ann => "T-Ann",
+            Rule::TyAbs => "T-TAbs",
+            Rule::TyApp => "T-TApp",
+            Rule::Unit => "T-Unit",
+            Rule::Ref => "T-Ref",
+            Rule::Deref => "T-Deref",
+            Rule::Assign => "T-Assign",
+            Rule::Loc => "T-Loc",
+            Rule::Fold => "T-Fold",
+            Rule::Unfold => "T-Unfold",
+            Rule::Raise => "T-Raise",
+            Rule::Try => "T-Try",
+            Rule::List => "T-List",
+            Rule::Nil => "T-Nil",
+            Rule::Cons => "T-Cons",
+            Rule::IsNil => "T-IsNil",
+            Rule::Head => "T-Head",
+            Rule::Tail => "T-Tail",
+            Rule::Foldr => "T-Foldr",
+            Rule::Con => "T-Con",
+            Rule::Match => "T-Match",
+            Rule::String => "T-String",
+            Rule::Char => "T-Char",
+            Rule::Concat => "T-Concat",
+            Rule::Length => "T-Length",
+            Rule::Eq => "T-Eq",
+            Rule::CharAt => "T-CharAt",
+            Rule::Hole => "T-Hole",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+/// Lines of text, all padded to the same width.
+struct Block {
+    lines: Vec<String>,
+    width: usize,
+}
+
+impl Derivation {
+    /// x: Bool |- e : T
+    pub fn judgement(&self) -> String {
+        let context: Vec<_> = self
+            .context
+            .iter()
+            .map(|(x, t)| format!("{}: {}", x, t))
+            .collect();
+        let expr = pretty::expr(&self.expr, true).render(usize::MAX);
+        if context.is_empty() {
+            format!("|- {} : {}", expr, self.ty)
+        } else {
+            format!("{} |- {} : {}", context.join(", "), expr, self.ty)
+        }
+    }
+
+    /// Lays the derivation out as an inference-rule tree, premises above the line and the
+    /// conclusion below.
+    pub fn ascii(&self) -> String {
+        let lines: Vec<_> = self
+            .block()
+            .lines
+            .iter()
+            .map(|line| line.trim_end().to_string())
+            .collect();
+        lines.join("\n")
+    }
+
+    fn block(&self) -> Block {
+        let premises: Vec<_> = self.premises.iter().map(|p| p.block()).collect();
+        let height = premises.iter().map(|b| b.lines.len()).max().unwrap_or(0);
+        let above_width =
+            premises.iter().map(|b| b.width).sum::<usize>() + 3 * premises.len().saturating_sub(1);
+        let above: Vec<_> = (0..height)
+            .map(|row| {
+                let cells: Vec<_> = premises
+                    .iter()
+                    .map(|b| {
+                        // premises are aligned at the bottom
+                        match (row + b.lines.len()).checked_sub(height) {
+                            Some(i) => b.lines[i].clone(),
+                            None => " ".repeat(b.width),
+                        }
+                    })
+                    .collect();
+                cells.join("   ")
+            })
+            .collect();
+
+        let conclusion = self.judgement();
+        let width = above_width.max(conclusion.len());
+        let label = format!(" {}", self.rule);
+        let center = |line: &str, len: usize| {
+            let left = (width - len) / 2;
+            format!("{}{}", " ".repeat(left), line)
+        };
+
+        let mut lines: Vec<_> = above.iter().map(|l| center(l, above_width)).collect();
+        lines.push(format!("{}{}", "-".repeat(width), label));
+        lines.push(center(&conclusion, conclusion.len()));
+        let width = width + label.len();
+        let lines = lines
+            .into_iter()
+            .map(|l| format!("{:width$}", l, width = width))
+            .collect();
+        Block { lines, width }
+    }
+
+    /// A `bussproofs` proof tree.
+    pub fn latex(&self) -> String {
+        let mut out = String::from("\\begin{prooftree}\n");
+        self.latex_steps(&mut out);
+        out.push_str("\\end{prooftree}\n");
+        out
+    }
+
+    fn latex_steps(&self, out: &mut String) {
+        // `bussproofs` has inferences for up to five premises, so each premise past the fifth
+        // is joined to the ones before it without a line or a conclusion, and they all stand
+        // over the inference as its fifth
+        for (i, premise) in self.premises.iter().enumerate() {
+            premise.latex_steps(out);
+            if i >= 5 {
+                out.push_str("\\noLine\n\\BinaryInfC{}\n");
+            }
+        }
+        if self.premises.is_empty() {
+            out.push_str("\\AxiomC{}\n");
+        }
+        out.push_str(&format!("\\RightLabel{{\\scriptsize {}}}\n", self.rule));
+        let inference = match self.premises.len() {
+            0 | 1 => "UnaryInfC",
+            2 => "BinaryInfC",
+            3 => "TrinaryInfC",
+            4 => "QuaternaryInfC",
+            _ => "QuinaryInfC",
+        };
+        let context: Vec<_> = self
+            .context
+            .iter()
+            .map(|(x, t)| format!("{} : {}", latex(x), latex(&t.to_string())))
+            .collect();
+        let expr = pretty::expr(&self.expr, true).render(usize::MAX);
+        out.push_str(&format!(
+            "\\{}{{${} \\vdash {} : {}$}}\n",
+            inference,
+            context.join(", "),
+            latex(&expr),
+            latex(&self.ty.to_string())
+        ));
+    }
+}
+
+/// Typesets printed source code in math mode.
+fn latex(src: &str) -> String {
+    let word = |t: &Token| {
+        matches!(
+            t,
+            Token::Identifier(_)
+                | Token::True
+                | Token::False
+                | Token::Bool
+                | Token::If
+                | Token::Then
+                | Token::Else
+                | Token::Ref
+                | Token::RefType
+                | Token::UnitValue
+                | Token::Unit
+                | Token::Fold
+                | Token::Unfold
+                | Token::Raise
+                | Token::Try
+                | Token::With
+                | Token::ListType
+                | Token::Nil
+                | Token::Cons
+                | Token::IsNil
+                | Token::Head
+                | Token::Tail
+                | Token::Foldr
+                | Token::Data
+                | Token::Match
+                | Token::Underscore
+                | Token::Hole(_)
+                | Token::StringType
+                | Token::CharType
+                | Token::StringLiteral(_)
+                | Token::CharLiteral(_)
+                | Token::Concat
+                | Token::Length
+                | Token::Eq
+                | Token::CharAt
+        )
+    };
+    let tokens = Scanner::scan(src.to_string()).unwrap_or_default();
+    let mut out = String::new();
+    let mut prev: Option<&Token> = None;
+    for t in &tokens {
+        let spaced = prev
+            .is_some_and(|p| word(p) || matches!(p, Token::RightParen | Token::RightBracket))
+            && (word(t) || matches!(t, Token::LeftParen | Token::Lambda));
+        if spaced {
+            out.push_str("\\ ");
+        }
+        out.push_str(&match t {
+            Token::Identifier(x) => {
+                let x: String = x
+                    .chars()
+                    .map(|c| match c {
+                        '_' | '#' | '$' | '%' | '&' | '{' | '}' => format!("\\{}", c),
+                        c => c.to_string(),
+                    })
+                    .collect();
+                format!("\\mathit{{{}}}", x)
+            }
+            Token::Lambda => "\\lambda ".to_string(),
+            Token::Equal => "=".to_string(),
+            Token::Dot => ".\\,".to_string(),
+            Token::LeftParen => "(".to_string(),
+            Token::RightParen => ")".to_string(),
+            Token::Colon => "{:}".to_string(),
+            Token::True => "\\mathsf{true}".to_string(),
+            Token::False => "\\mathsf{false}".to_string(),
+            Token::Bool => "\\mathsf{Bool}".to_string(),
+            Token::If => "\\mathsf{if}".to_string(),
+            Token::Then => "\\mathsf{then}".to_string(),
+            Token::Else => "\\mathsf{else}".to_string(),
+            Token::Arrow => "\\to ".to_string(),
+            Token::TyLambda => "\\Lambda ".to_string(),
+            Token::LeftBracket => "[".to_string(),
+            Token::RightBracket => "]".to_string(),
+            Token::Forall => "\\forall ".to_string(),
+            Token::Ref => "\\mathsf{ref}".to_string(),
+            Token::RefType => "\\mathsf{Ref}".to_string(),
+            Token::UnitValue => "\\mathsf{unit}".to_string(),
+            Token::Unit => "\\mathsf{Unit}".to_string(),
+            Token::Bang => "!".to_string(),
+            Token::ColonEqual => "\\mathrel{:=}".to_string(),
+            Token::Mu => "\\mu ".to_string(),
+            Token::Fold => "\\mathsf{fold}".to_string(),
+            Token::Unfold => "\\mathsf{unfold}".to_string(),
+            Token::Raise => "\\mathsf{raise}".to_string(),
+            Token::Try => "\\mathsf{try}".to_string(),
+            Token::With => "\\mathsf{with}".to_string(),
+            Token::Comma => ",".to_string(),
+            Token::ListType => "\\mathsf{List}".to_string(),
+            Token::Nil => "\\mathsf{nil}".to_string(),
+            Token::Cons => "\\mathsf{cons}".to_string(),
+            Token::IsNil => "\\mathsf{isnil}".to_string(),
+            Token::Head => "\\mathsf{head}".to_string(),
+            Token::Tail => "\\mathsf{tail}".to_string(),
+            Token::Foldr => "\\mathsf{foldr}".to_string(),
+            Token::Data => "\\mathsf{data}".to_string(),
+            Token::Match => "\\mathsf{match}".to_string(),
+            Token::Pipe => "\\mid ".to_string(),
+            Token::Underscore => "\\_".to_string(),
+            Token::Hole(name) => format!("?\\mathit{{{}}}", name.replace('_', "\\_")),
+            Token::StringType => "\\mathsf{String}".to_string(),
+            Token::CharType => "\\mathsf{Char}".to_string(),
+            Token::StringLiteral(text) => {
+                let quoted = Constant::String(text.clone()).to_string();
+                format!("\\texttt{{{}}}", verbatim(&quoted))
+            }
+            Token::CharLiteral(c) => {
+                let quoted = Constant::Char(*c).to_string();
+                format!("\\texttt{{{}}}", verbatim(&quoted))
+            }
+            Token::Concat => "\\mathsf{concat}".to_string(),
+            Token::Length => "\\mathsf{length}".to_string(),
+            Token::Eq => "\\mathsf{eq}".to_string(),
+            Token::CharAt => "\\mathsf{char\\_at}".to_string(),
+        });
+        prev = Some(t);
+    }
+    out
+}
+
+/// Escapes the characters that LaTeX treats specially in text, for literals.
+fn verbatim(text: &str) -> String {
+    text.chars()
+        .map(|c| match c {
+            '#' | '$' | '%' | '&' | '{' | '}' | '_' => format!("\\{}", c),
+            '\\' => "\\textbackslash{}".to_string(),
+            '~' => "\\textasciitilde{}".to_string(),
+            '^' => "\\textasciicircum{}".to_string(),
+            '"' => "\\textquotedbl{}".to_string(),
+            ' ' => "\\ ".to_string(),
+            c => c.to_string(),
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{parser::Parser, typer::Typer};
+
+    fn derive(s: &str) -> Derivation {
+        let e = Parser::parse(Scanner::scan(s.to_string()).unwrap()).unwrap();
+        Typer::default().derive(&e).unwrap()
+    }
+
+    /// Replays the `bussproofs` stack: each `\AxiomC` pushes a tree and each inference pops as
+    /// many as it has premises, so a well-formed proof ends with exactly one tree.
+    fn assert_well_formed(latex: &str) {
+        let mut trees = 0usize;
+        for line in latex.lines() {
+            let arity = ["Unary", "Binary", "Trinary", "Quaternary", "Quinary"]
+                .iter()
+                .position(|n| line.starts_with(&format!("\\{n}InfC")));
+            if line.starts_with("\\AxiomC") {
+                trees += 1;
+            } else if let Some(arity) = arity {
+                trees = trees
+                    .checked_sub(arity + 1)
+                    .unwrap_or_else(|| panic!("too few premises for `{line}`"))
+                    + 1;
+            } else {
+                assert!(
+                    ["\\RightLabel", "\\noLine", "\\begin", "\\end"]
+                        .iter()
+                        .any(|c| line.starts_with(c)),
+                    "unknown command `{line}`"
+                );
+            }
+        }
+        assert_eq!(trees, 1, "{latex}");
+    }
+
+    #[test]
+    fn ascii() {
+        assert_eq!(
+            derive("(\\x: Bool. x) true").ascii(),
+            r"  ------------------- T-Var
+  x: Bool |- x : Bool
+----------------------------- T-Abs   -------------- T-True
+|- \x: Bool. x : Bool -> Bool         |- true : Bool
+----------------------------------------------------------- T-App
+               |- (\x: Bool. x) true : Bool"
+        );
+    }
+
+    #[test]
+    fn latex() {
+        assert_eq!(
+            derive("\\f: Bool -> Bool. f true").latex(),
+            "\\begin{prooftree}
+\\AxiomC{}
+\\RightLabel{\\scriptsize T-Var}
+\\UnaryInfC{$\\mathit{f} : \\mathsf{Bool}\\to \\mathsf{Bool} \\vdash \\mathit{f} : \\mathsf{Bool}\\to \\mathsf{Bool}$}
+\\AxiomC{}
+\\RightLabel{\\scriptsize T-True}
+\\UnaryInfC{$\\mathit{f} : \\mathsf{Bool}\\to \\mathsf{Bool} \\vdash \\mathsf{true} : \\mathsf{Bool}$}
+\\RightLabel{\\scriptsize T-App}
+\\BinaryInfC{$\\mathit{f} : \\mathsf{Bool}\\to \\mathsf{Bool} \\vdash \\mathit{f}\\ \\mathsf{true} : \\mathsf{Bool}$}
+\\RightLabel{\\scriptsize T-Abs}
+\\UnaryInfC{$ \\vdash \\lambda \\mathit{f}{:}\\mathsf{Bool}\\to \\mathsf{Bool}.\\,\\mathit{f}\\ \\mathsf{true} : (\\mathsf{Bool}\\to \\mathsf{Bool})\\to \\mathsf{Bool}$}
+\\end{prooftree}
+"
+        );
+    }
+
+    #[test]
+    fn latex_list_literals() {
+        // one premise per element
+        let latex = derive("[true, false, true, false]").latex();
+        assert!(latex.contains("\\QuaternaryInfC"), "{latex}");
+        assert_well_formed(&latex);
+    }
+
+    #[test]
+    fn latex_many_premises() {
+        // more premises than `bussproofs` has inferences for
+        assert_well_formed(&derive("[true, false, true, false, true, false]").latex());
+        assert_well_formed(
+            &derive("[true, true, true, true, true, true, true, true, true]").latex(),
+        );
+    }
+}
@@ -0,0 +1,413 @@
+use anyhow::anyhow;
+
+use crate::{
+    interpreter::EvalError, parser::ParseError, scanner::ScanError, session::SessionError,
+    typer::TypeError,
+};
+
+/// The long-form explanation of an error code, for `--explain` and `:explain`.
+pub struct Entry {
+    pub code: &'static str,
+    pub title: &'static str,
+    pub explanation: &'static str,
+    /// REPL lines, one per line, the last of which fails with this code; `None` if no line
+    /// fails with it every time
+    pub example: Option<&'static str>,
+    /// whether the example runs in `--untyped` mode
+    pub untyped: bool,
+}
+
+/// Every error code, in order. E00xx are scanner errors, E01xx parser errors, E02xx type
+/// errors, E03xx runtime errors and E04xx misuses of the REPL.
+pub const CATALOG: &[Entry] = &[
+    Entry {
+        code: "E0001",
+        title: "unexpected symbol",
+        explanation: "The line has a character that can't start a token. Names are letters, digits and `_`, and start with a letter; the only symbols are `\\`, `/\\`, `.`, `:`, `:=`, `=`, `(`, `)`, `[`, `]`, `,`, `|`, `!`, `->`, `?`, `_` and the quotes of string and character literals. There are no number literals: naturals are Church numerals, which `:encode 2` prints.",
+        example: Some("iszero 0"),
+        untyped: false,
+    },
+    Entry {
+        code: "E0002",
+        title: "unterminated string literal",
+        explanation: "A string literal starts with `\"` but the line ends before the closing `\"`. A `\"` inside a string has to be escaped as `\\\"`.",
+        example: Some("concat \"abc"),
+        untyped: false,
+    },
+    Entry {
+        code: "E0003",
+        title: "empty character literal",
+        explanation: "`''` has no character in it. A character literal holds exactly one character, like `'a'`, or one escape, like `'\\n'` or `'\\''`.",
+        example: Some("''"),
+        untyped: false,
+    },
+    Entry {
+        code: "E0004",
+        title: "unterminated character literal",
+        explanation: "A character literal starts with `'` but isn't closed by a `'` right after its one character. Text with more than one character is a string, written with `\"`.",
+        example: Some("'ab'"),
+        untyped: false,
+    },
+    Entry {
+        code: "E0005",
+        title: "unknown escape",
+        explanation: "A `\\` in a string or character literal starts an escape, and the character after it isn't one of `n`, `t`, `r`, `0`, `\\`, `\"` or `'`. A backslash itself is written `\\\\`.",
+        example: Some("\"C:\\new\\quiz\""),
+        untyped: false,
+    },
+    Entry {
+        code: "E0006",
+        title: "hole without a name",
+        explanation: "`?` starts a named hole, so it needs a name right after it, as in `?goal`. An unnamed hole is written `_`.",
+        example: Some("\\x: Bool. ?"),
+        untyped: false,
+    },
+    Entry {
+        code: "E0101",
+        title: "unexpected token",
+        explanation: "The parser expected one kind of token and found another. The message says what it expected; often a type annotation or a `.` after a binder is missing, or a parenthesis isn't closed.",
+        example: Some("\\x: . x"),
+        untyped: false,
+    },
+    Entry {
+        code: "E0102",
+        title: "unexpected end of line",
+        explanation: "The line ended in the middle of a term, as after `\\x: Bool.` with no body or `if b then e1` with no `else`. Every term has to fit on one line.",
+        example: Some("\\x: Bool."),
+        untyped: false,
+    },
+    Entry {
+        code: "E0103",
+        title: "capitalized variable",
+        explanation: "Names that start with a capital letter are constructors of `data` types, so variables bound by `\\` or defined with `=` have to start with a lowercase letter. Type variables, bound by `/\\` and `forall`, are the ones that are capitalized.",
+        example: Some("\\X: Bool. X"),
+        untyped: false,
+    },
+    Entry {
+        code: "E0104",
+        title: "lowercase constructor",
+        explanation: "The constructors of a `data` declaration have to start with a capital letter, so that patterns can tell them from variables.",
+        example: Some("data Color = red | Green"),
+        untyped: false,
+    },
+    Entry {
+        code: "E0105",
+        title: "tokens after the end of a term",
+        explanation: "The line has a whole term and then more tokens, such as a stray `)` or an `else` with no `if`. Arguments follow the function they're applied to, but literals like `true` aren't functions, so `true false` ends after `true`.",
+        example: Some("(\\x: Bool. x) true )"),
+        untyped: false,
+    },
+    Entry {
+        code: "E0201",
+        title: "undeclared variable",
+        explanation: "The variable isn't bound by an enclosing `\\` or `match` arm or defined earlier with `x = e`. If a name in scope is close enough to be a typo, the message suggests it.",
+        example: Some("cnto ctrue"),
+        untyped: false,
+    },
+    Entry {
+        code: "E0202",
+        title: "undeclared constructor",
+        explanation: "The constructor doesn't belong to any `data` declaration. Declare its type first with `data T = C ...`.",
+        example: Some("Nothing"),
+        untyped: false,
+    },
+    Entry {
+        code: "E0203",
+        title: "mismatched if branches",
+        explanation: "Both branches of `if e1 then e2 else e3` have to have the same type, which is the type of the whole `if`. The `then` branch decides the type, so the `else` branch is the one reported.",
+        example: Some("\\b: Bool. if b then true else unit"),
+        untyped: false,
+    },
+    Entry {
+        code: "E0204",
+        title: "unbound type variable",
+        explanation: "A type mentions a type variable that no enclosing `/\\X.` or `forall X.` binds. `/\\X. \\x: X. x` is the polymorphic identity; `\\x: X. x` on its own is an error.",
+        example: Some("\\x: X. x"),
+        untyped: false,
+    },
+    Entry {
+        code: "E0205",
+        title: "mismatched types",
+        explanation: "A term has one type where another was needed, such as an argument whose type isn't the function's parameter type. The message gives both types and the term; `:context` shows the variables in scope where it happened.",
+        example: Some("(\\x: Bool. x) unit"),
+        untyped: false,
+    },
+    Entry {
+        code: "E0206",
+        title: "applied a non-function",
+        explanation: "`e1 e2` applies `e1` as a function, but its type isn't a function type. This usually means an argument is in the wrong place or a parenthesis is missing; the hint says which when it can tell.",
+        example: Some("(\\x: Bool. x) true true"),
+        untyped: false,
+    },
+    Entry {
+        code: "E0207",
+        title: "type application of a non-polymorphic term",
+        explanation: "`e [T]` instantiates a polymorphic term, whose type is `forall X. ...`, but `e` has another type. Only terms built with `/\\X.` take type arguments.",
+        example: Some("(\\x: Bool. x) [Bool]"),
+        untyped: false,
+    },
+    Entry {
+        code: "E0208",
+        title: "not a reference",
+        explanation: "`!e` and `e := v` need `e` to be a reference, of type `Ref T`, which `ref v` creates.",
+        example: Some("!true"),
+        untyped: false,
+    },
+    Entry {
+        code: "E0209",
+        title: "mismatched handler",
+        explanation: "In `try e1 with e2`, the handler `e2` is called with the boolean an exception carries, so it has to be a function `Bool -> T`. Usually `T` is the type of `e1` and a handler of another type is a mismatched type, E0205; when `e1` is a `raise`, the handler alone decides the type.",
+        example: Some("try raise true with false"),
+        untyped: false,
+    },
+    Entry {
+        code: "E0210",
+        title: "type can't be synthesized",
+        explanation: "The typer can only check some terms against a type it already knows: abstractions without an annotation, `[]` and `raise e`. Annotate the binder, as in `\\x: Bool. x`, or ascribe the term, as in `(\\x. x : Bool -> Bool)`.",
+        example: Some("\\x. x"),
+        untyped: false,
+    },
+    Entry {
+        code: "E0211",
+        title: "abstraction or list checked against another type",
+        explanation: "An abstraction was checked against a type that isn't a function type, or a list literal against one that isn't a list type, usually because of an ascription or the parameter type of a function it's passed to.",
+        example: Some("(\\x. x : Bool)"),
+        untyped: false,
+    },
+    Entry {
+        code: "E0212",
+        title: "mismatched annotation",
+        explanation: "An abstraction annotates its binder with a type other than the parameter type of the function type it's checked against. Remove the annotation or make the two agree.",
+        example: Some("(\\x: Bool. x : Unit -> Unit)"),
+        untyped: false,
+    },
+    Entry {
+        code: "E0213",
+        title: "pattern of the wrong type",
+        explanation: "A constructor pattern in a `match` arm belongs to a different `data` type than the value it's matched against.",
+        example: Some("data A = X\ndata B = Y\nmatch X with | Y -> true"),
+        untyped: false,
+    },
+    Entry {
+        code: "E0214",
+        title: "wrong number of patterns",
+        explanation: "A constructor pattern has to have one pattern for each argument the constructor takes, no more and no fewer. Use `_` for arguments the arm doesn't need.",
+        example: Some("data Box = Box Bool\nmatch Box true with | Box -> true"),
+        untyped: false,
+    },
+    Entry {
+        code: "E0215",
+        title: "variable bound twice in a pattern",
+        explanation: "Each variable can only appear once in a pattern. To compare two parts of a value, bind them to different names and compare those.",
+        example: Some("data Pair = Pair Bool Bool\nmatch Pair true false with | Pair x x -> x"),
+        untyped: false,
+    },
+    Entry {
+        code: "E0216",
+        title: "unreachable arm",
+        explanation: "The arms above this one already match every value it matches, so it can never run. Move it above the arms that cover it, or remove it.",
+        example: Some("data Color = Red | Green\nmatch Red with | _ -> true | Red -> false"),
+        untyped: false,
+    },
+    Entry {
+        code: "E0217",
+        title: "non-exhaustive match",
+        explanation: "Some values match none of the arms, which would get stuck at runtime. The message gives a pattern for the values that are missing; add an arm for it, or a `_` arm at the end.",
+        example: Some("data Color = Red | Green\nmatch Red with | Red -> true"),
+        untyped: false,
+    },
+    Entry {
+        code: "E0218",
+        title: "not a recursive type",
+        explanation: "`fold [T] e` and `unfold [T] e` need `T` to be a recursive type `mu X. ...`.",
+        example: Some("unfold [Bool] true"),
+        untyped: false,
+    },
+    Entry {
+        code: "E0219",
+        title: "type declared twice",
+        explanation: "A `data` type with this name was already declared in the session. Declarations can't be replaced, so pick another name.",
+        example: Some("data Color = Red\ndata Color = Green"),
+        untyped: false,
+    },
+    Entry {
+        code: "E0220",
+        title: "constructor declared twice",
+        explanation: "A constructor name can only belong to one `data` type and appear once in it, so that patterns and applications know which type they mean.",
+        example: Some("data Color = Red | Red"),
+        untyped: false,
+    },
+    Entry {
+        code: "E0221",
+        title: "unknown location",
+        explanation: "A term refers to a store location that doesn't exist. Locations like `<loc 0>` only appear in values printed after `ref`, and can't be written in a line.",
+        example: None,
+        untyped: false,
+    },
+    Entry {
+        code: "E0222",
+        title: "definition with holes",
+        explanation: "`x = e` evaluates `e` to bind `x`, so `e` can't have holes in it. Fill them in first; the hole lines printed after the error give the type each one needs.",
+        example: Some("x = iszero ?n"),
+        untyped: false,
+    },
+    Entry {
+        code: "E0301",
+        title: "out of fuel",
+        explanation: "Evaluation took more reduction steps than the limit, 10,000 by default, so the term may not have a normal form. `--max-steps=N` raises the limit.",
+        example: Some("(\\x. x x) (\\x. x x)"),
+        untyped: true,
+    },
+    Entry {
+        code: "E0302",
+        title: "term too large",
+        explanation: "The term being evaluated grew past the size limit, 10,000 nodes by default, as it does on a term that makes several copies of itself at every step. `--max-size=N` raises the limit.",
+        example: Some("(\\x. \\y. y (x x) (x x) (x x) (x x)) (\\x. \\y. y (x x) (x x) (x x) (x x))"),
+        untyped: true,
+    },
+    Entry {
+        code: "E0303",
+        title: "evaluation too deep",
+        explanation: "Evaluation nested deeper than the limit, 1,000 levels by default, as it does on a term that applies itself to ever more arguments. `--max-depth=N` raises the limit.",
+        example: Some("(\\x. x x x) (\\x. x x x)"),
+        untyped: true,
+    },
+    Entry {
+        code: "E0304",
+        title: "interrupted",
+        explanation: "Ctrl-C stopped the evaluation before it finished. The session is unchanged; a definition that was interrupted isn't defined.",
+        example: None,
+        untyped: false,
+    },
+    Entry {
+        code: "E0305",
+        title: "uncaught exception",
+        explanation: "`raise e` was evaluated outside any `try ... with`, or `head`, `tail` or `char_at` had nothing to return. Wrap the term in `try e1 with \\b: Bool. e2` to handle it.",
+        example: Some("head [Bool] ([] : List Bool)"),
+        untyped: false,
+    },
+    Entry {
+        code: "E0306",
+        title: "reached a hole",
+        explanation: "A term with holes typechecks, but evaluating it needed the value of one of them. Fill in the hole, whose type is printed after the error.",
+        example: Some("iszero ?n"),
+        untyped: false,
+    },
+    Entry {
+        code: "E0307",
+        title: "not enough stack",
+        explanation: "Evaluation runs on a thread with enough stack for `--max-depth` and `--max-size`, and the system wouldn't give it that much. The REPL refuses limits that need more than 1 GiB when it starts, so this only happens when memory is short. Lower the limits.",
+        example: None,
+        untyped: false,
+    },
+    Entry {
+        code: "E0401",
+        title: "data declaration in untyped mode",
+        explanation: "`--untyped` skips the typechecker, so `data` declarations have nothing to declare: any capitalized name is a constructor.",
+        example: Some("data Color = Red"),
+        untyped: true,
+    },
+    Entry {
+        code: "E0402",
+        title: "derivation in untyped mode",
+        explanation: "Typing derivations come from the typechecker, which `--untyped` turns off. Run without `--untyped` to use `:derive`.",
+        example: Some(":derive true"),
+        untyped: true,
+    },
+];
+
+/// The code of `err`, if it's one of the errors that has one.
+pub fn code(err: &anyhow::Error) -> Option<&'static str> {
+    if let Some(e) = err.downcast_ref::<ScanError>() {
+        Some(e.code())
+    } else if let Some(e) = err.downcast_ref::<ParseError>() {
+        Some(e.code())
+    } else if let Some(e) = err.downcast_ref::<TypeError>() {
+        Some(e.code())
+    } else if let Some(e) = err.downcast_ref::<EvalError>() {
+        Some(e.code())
+    } else {
+        err.downcast_ref::<SessionError>().map(SessionError::code)
+    }
+}
+
+/// The entry for `code`, written out for the terminal.
+pub fn explain(code: &str) -> anyhow::Result<String> {
+    let entry = CATALOG
+        .iter()
+        .find(|entry| entry.code.eq_ignore_ascii_case(code.trim()))
+        .ok_or_else(|| anyhow!("no error has the code `{}`", code.trim()))?;
+    let mut text = format!(
+        "{}: {}\n\n{}\n",
+        entry.code,
+        entry.title,
+        fill(entry.explanation, 80)
+    );
+    if let Some(example) = entry.example {
+        text.push_str(if entry.untyped {
+            "\nFor example, in `simplytyped --untyped`:\n\n"
+        } else {
+            "\nFor example:\n\n"
+        });
+        for line in example.lines() {
+            text.push_str(&format!("    λ {}\n", line));
+        }
+    }
+    Ok(text)
+}
+
+/// `text` broken at spaces into lines of at most `width` characters, where the words allow.
+fn fill(text: &str, width: usize) -> String {
+    let mut filled = String::new();
+    let mut column = 0;
+    for word in text.split(' ') {
+        let len = word.chars().count();
+        if column > 0 && column + 1 + len > width {
+            filled.push('\n');
+            column = 0;
+        } else if column > 0 {
+            filled.push(' ');
+            column += 1;
+        }
+        filled.push_str(word);
+        column += len;
+    }
+    filled
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{run_line, session::Session};
+
+    #[test]
+    fn examples_fail_with_their_codes() {
+        for (i, entry) in CATALOG.iter().enumerate() {
+            assert!(
+                CATALOG[..i].iter().all(|other| other.code < entry.code),
+                "{} is out of order",
+                entry.code
+            );
+            let Some(example) = entry.example else {
+                continue;
+            };
+            let mut session = Session::with_prelude();
+            let lines: Vec<_> = example.lines().collect();
+            let (last, setup) = lines.split_last().unwrap();
+            for line in setup {
+                run_line(line, &mut session, entry.untyped, &mut false).unwrap();
+            }
+            let err =
+                run_line(last, &mut session, entry.untyped, &mut false).expect_err(entry.code);
+            assert_eq!(code(&err), Some(entry.code), "{}", err);
+        }
+    }
+
+    #[test]
+    fn lookup() {
+        assert!(explain("e0203")
+            .unwrap()
+            .starts_with("E0203: mismatched if branches"));
+        assert!(explain("E9999").is_err());
+    }
+}
@@ -1,52 +1,792 @@
-use std::collections::HashSet;
+use std::{
+    collections::HashSet,
+    fmt,
+    sync::atomic::{AtomicBool, Ordering},
+    thread,
+};
 
-use crate::parser::{Constant, Expr};
+use crate::{
+    church,
+    matching::{self, Select},
+    parser::{Constant, Expr, Pattern, Prim, Type},
+    typer::substitute_expr,
+};
 
-fn is_value(expr: &Expr) -> bool {
-    matches!(expr, Expr::Constant(_) | Expr::Var(_) | Expr::Abs { .. })
+/// Bounds on how much work evaluating a term may do, so that divergent or exploding terms
+/// fail with an `EvalError` instead of hanging or overflowing the stack.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct EvalLimits {
+    /// reduction steps
+    pub max_steps: u64,
+    /// nodes in the term being reduced
+    pub max_term_size: usize,
+    /// nested evaluations of subterms
+    pub max_depth: usize,
 }
 
-pub fn interpret(expr: &Expr, by_value: bool) -> Expr {
-    match expr {
-        Expr::Var(_) | Expr::Constant(_) | Expr::Abs { .. } => expr.clone(),
-        Expr::App { e1, e2 } => app(e1, e2, by_value),
-        Expr::If { e1, e2, e3 } => r#if(e1, e2, e3, by_value),
+impl EvalLimits {
+    /// The stack evaluating within these limits takes, or `None` if it's more than
+    /// `MAX_STACK`.
+    pub fn stack_size(&self) -> Option<usize> {
+        self.max_depth
+            .checked_mul(STACK_PER_LEVEL)?
+            .checked_add(self.max_term_size.checked_mul(STACK_PER_NODE)?)?
+            .checked_add(1 << 20)
+            .filter(|&stack| stack <= MAX_STACK)
+    }
+}
+
+impl Default for EvalLimits {
+    fn default() -> Self {
+        Self {
+            max_steps: 10_000,
+            max_term_size: 10_000,
+            max_depth: 1_000,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum EvalError {
+    FuelExhausted {
+        steps: u64,
+    },
+    TermTooLarge {
+        size: usize,
+    },
+    TooDeep {
+        depth: usize,
+    },
+    /// the interrupt flag was set, by Ctrl-C in the REPL
+    Interrupted,
+    /// `raise value` wasn't caught by any `try`
+    Uncaught {
+        value: Expr,
+    },
+    /// evaluation needed the value of a hole, `_` if it's unnamed
+    Hole {
+        name: Option<String>,
+    },
+    /// the limits need more stack than `MAX_STACK`, or than the system would give
+    NoStack,
+}
+
+impl EvalError {
+    /// The stable code that `--explain` looks up.
+    pub fn code(&self) -> &'static str {
+        match self {
+            EvalError::FuelExhausted { .. } => "E0301",
+            EvalError::TermTooLarge { .. } => "E0302",
+            EvalError::TooDeep { .. } => "E0303",
+            EvalError::Interrupted => "E0304",
+            EvalError::Uncaught { .. } => "E0305",
+            EvalError::Hole { .. } => "E0306",
+            EvalError::NoStack => "E0307",
+        }
+    }
+}
+
+impl fmt::Display for EvalError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            EvalError::FuelExhausted { steps } => write!(
+                f,
+                "ran out of fuel after {} steps; the term may diverge",
+                steps
+            ),
+            EvalError::TermTooLarge { size } => {
+                write!(f, "the term grew past {} nodes", size)
+            }
+            EvalError::TooDeep { depth } => {
+                write!(f, "evaluation nested more than {} levels deep", depth)
+            }
+            EvalError::Interrupted => write!(f, "interrupted"),
+            EvalError::Uncaught { value } => write!(f, "uncaught exception `{}`", value),
+            EvalError::Hole { name } => write!(
+                f,
+                "reached the hole `{}`, which has to be filled in first",
+                Expr::Hole(name.clone())
+            ),
+            EvalError::NoStack => write!(
+                f,
+                "couldn't get enough stack to evaluate within the limits; lower `--max-depth` or `--max-size`"
+            ),
+        }
+    }
+}
+
+impl std::error::Error for EvalError {}
+
+/// Evaluates `expr` to a value, call-by-value if `by_value` and call-by-name otherwise. `ref e`
+/// allocates a new location at the end of `store`. Gives up between steps once `interrupt` is
+/// set, and fails if an exception isn't caught.
+pub fn interpret(
+    expr: &Expr,
+    by_value: bool,
+    limits: &EvalLimits,
+    interrupt: &AtomicBool,
+    store: &mut Vec<Expr>,
+) -> Result<Expr, EvalError> {
+    match Evaluator::run(limits, interrupt, store, |eval| {
+        eval.interpret(expr, by_value)
+    })? {
+        Expr::Raise(value) => Err(EvalError::Uncaught { value: *value }),
+        value => Ok(value),
+    }
+}
+
+/// The value of a subterm, unless evaluating it raised an exception, which then propagates out
+/// of the enclosing term by breaking out of its reduction loop.
+macro_rules! propagate {
+    ($e:expr) => {
+        match $e {
+            e @ Expr::Raise(_) => break e,
+            e => e,
+        }
+    };
+}
+
+/// Reduces `e` to its normal form in normal order, under binders as well as at the top, so
+/// that `succ zero` becomes `/\X. \s: X -> X. \z: X. s z`. Untyped terms might not have a
+/// normal form, so this gives up once it reaches any of the `limits` or `interrupt` is set.
+/// References are left alone, since they have no normal form without a store.
+pub fn normalize(e: &Expr, limits: &EvalLimits, interrupt: &AtomicBool) -> Result<Expr, EvalError> {
+    Evaluator::run(limits, interrupt, &mut Vec::new(), |eval| eval.normalize(e))
+}
+
+/// How matching a scrutinee against the arms of a `match` ended.
+enum Matched {
+    /// the body of the arm that matched, with its variables substituted
+    Arm(Expr),
+    /// evaluating a subterm of the scrutinee raised this
+    Raised(Expr),
+    /// a subterm of the scrutinee, which is returned, didn't evaluate to a constructor
+    Stuck(Expr),
+}
+
+/// Stack for each level of `max_depth`, and for each node of `max_term_size` since
+/// substitution recurses over terms. Generous enough for debug builds.
+const STACK_PER_LEVEL: usize = 16 * 1024;
+const STACK_PER_NODE: usize = 1024;
+/// The most stack an evaluation may ask for.
+pub const MAX_STACK: usize = 1 << 30;
+
+/// Counts the work done so far against the limits. Reductions at the head of a term loop
+/// rather than recurse, so the only native recursion is into subterms, which `depth` bounds.
+struct Evaluator<'a> {
+    limits: &'a EvalLimits,
+    interrupt: &'a AtomicBool,
+    /// the value at each location
+    store: &'a mut Vec<Expr>,
+    steps: u64,
+    depth: usize,
+    /// the size of the term around the subterm being reduced
+    around: usize,
+}
+
+impl<'a> Evaluator<'a> {
+    /// Runs `f` on a thread with enough stack for the limits, so it errors out before it can
+    /// overflow whatever thread it was called from.
+    fn run<T: Send>(
+        limits: &EvalLimits,
+        interrupt: &AtomicBool,
+        store: &mut Vec<Expr>,
+        f: impl FnOnce(&mut Evaluator) -> Result<T, EvalError> + Send,
+    ) -> Result<T, EvalError> {
+        let stack = limits.stack_size().ok_or(EvalError::NoStack)?;
+        thread::scope(|scope| {
+            let eval = thread::Builder::new()
+                .stack_size(stack)
+                .spawn_scoped(scope, || {
+                    f(&mut Evaluator {
+                        limits,
+                        interrupt,
+                        store,
+                        steps: 0,
+                        depth: 0,
+                        around: 0,
+                    })
+                })
+                .map_err(|_| EvalError::NoStack)?;
+            eval.join()
+                .unwrap_or_else(|panic| std::panic::resume_unwind(panic))
+        })
+    }
+
+    fn interpret(&mut self, e: &Expr, by_value: bool) -> Result<Expr, EvalError> {
+        self.enter()?;
+        let mut e = e.clone();
+        let value = loop {
+            let next = match e {
+                Expr::App { e1, e2 } => {
+                    let e1 =
+                        propagate!(self.nested(size(&e2), |this| this.interpret(&e1, by_value))?);
+                    let e2 = if by_value {
+                        propagate!(self.nested(size(&e1), |this| this.interpret(&e2, by_value))?)
+                    } else {
+                        *e2
+                    };
+                    match e1 {
+                        Expr::Abs { x, t: _, e } => substitute(&e, &x, &e2),
+                        e1 => {
+                            let app = Expr::App {
+                                e1: Box::new(e1),
+                                e2: Box::new(e2),
+                            };
+                            let Some((p, mut args)) = saturated(&app) else {
+                                break app;
+                            };
+                            let mut raised = None;
+                            for &i in p.strict() {
+                                match self.nested(1, |this| this.interpret(&args[i], by_value))? {
+                                    e @ Expr::Raise(_) => {
+                                        raised = Some(e);
+                                        break;
+                                    }
+                                    v => args[i] = v,
+                                }
+                            }
+                            match raised {
+                                Some(e) => break e,
+                                None => apply(p, args)
+                                    .unwrap_or_else(|| unreachable!("failed typechecking")),
+                            }
+                        }
+                    }
+                }
+                Expr::TyApp { e, t } => match propagate!(self.interpret(&e, by_value)?) {
+                    // (/\X. e) [T] => e[X := T]
+                    Expr::TyAbs { x, e } => substitute_expr(&e, &x, &t),
+                    // nil [T] => []
+                    Expr::Prim(Prim::Nil) => Expr::List(Vec::new()),
+                    e => break Expr::TyApp { e: Box::new(e), t },
+                },
+                Expr::If { e1, e2, e3 } => match propagate!(
+                    self.nested(size(&e2) + size(&e3), |this| this.interpret(&e1, by_value))?
+                ) {
+                    Expr::Constant(Constant::True) => *e2,
+                    _ => *e3,
+                },
+                Expr::Ann { e, t: _ } => *e,
+                // the operands of references are always evaluated, even call-by-name, so that
+                // every location holds a value
+                Expr::Ref(e) => {
+                    let v = propagate!(self.nested(1, |this| this.interpret(&e, by_value))?);
+                    self.store.push(v);
+                    Expr::Loc(self.store.len() - 1)
+                }
+                Expr::Deref(e) => {
+                    match propagate!(self.nested(1, |this| this.interpret(&e, by_value))?) {
+                        Expr::Loc(l) => self.store[l].clone(),
+                        _ => unreachable!("failed typechecking"),
+                    }
+                }
+                Expr::Assign { e1, e2 } => {
+                    let l = propagate!(
+                        self.nested(1 + size(&e2), |this| this.interpret(&e1, by_value))?
+                    );
+                    let v = propagate!(self.nested(2, |this| this.interpret(&e2, by_value))?);
+                    match l {
+                        Expr::Loc(l) => self.store[l] = v,
+                        _ => unreachable!("failed typechecking"),
+                    }
+                    Expr::Constant(Constant::Unit)
+                }
+                // unfold [T] (fold [T] v) => v
+                Expr::Unfold { t: _, e } => {
+                    match propagate!(self.nested(1, |this| this.interpret(&e, by_value))?) {
+                        Expr::Fold { t: _, e } => *e,
+                        _ => unreachable!("failed typechecking"),
+                    }
+                }
+                // `fold [T] v` is a value, like an abstraction
+                Expr::Fold { t, e } if by_value => {
+                    let e = propagate!(self.nested(1, |this| this.interpret(&e, by_value))?);
+                    break Expr::Fold { t, e: Box::new(e) };
+                }
+                Expr::Raise(e) => {
+                    let v = propagate!(self.nested(1, |this| this.interpret(&e, by_value))?);
+                    break Expr::Raise(Box::new(v));
+                }
+                // like `fold`, lists are values whose elements are only evaluated call-by-value
+                Expr::List(es) if by_value => {
+                    let around = 1 + es.iter().map(size).sum::<usize>();
+                    let mut vs = Vec::new();
+                    let mut raised = None;
+                    for e in &es {
+                        match self.nested(around, |this| this.interpret(e, by_value))? {
+                            raise @ Expr::Raise(_) => {
+                                raised = Some(raise);
+                                break;
+                            }
+                            v => vs.push(v),
+                        }
+                    }
+                    break raised.unwrap_or(Expr::List(vs));
+                }
+                // try v with e2 => v, try raise v with e2 => e2 v
+                Expr::Try { e1, e2 } => {
+                    match self.nested(1 + size(&e2), |this| this.interpret(&e1, by_value))? {
+                        Expr::Raise(v) => Expr::App { e1: e2, e2: v },
+                        v => break v,
+                    }
+                }
+                Expr::Match { e, arms } => {
+                    let around = 1 + arms.iter().map(|(_, e)| size(e)).sum::<usize>();
+                    let v = propagate!(self.nested(around, |this| this.interpret(&e, by_value))?);
+                    let force = |this: &mut Self, e: &Expr| {
+                        this.nested(around, |this| this.interpret(e, by_value))
+                    };
+                    match self.select(v, &arms, force)? {
+                        Matched::Arm(e) => e,
+                        Matched::Raised(raise) => break raise,
+                        Matched::Stuck(_) => unreachable!("failed typechecking"),
+                    }
+                }
+                Expr::Hole(name) => return Err(EvalError::Hole { name }),
+                e => break e,
+            };
+            self.step(&next)?;
+            e = next;
+        };
+        self.depth -= 1;
+        Ok(value)
+    }
+
+    fn normalize(&mut self, e: &Expr) -> Result<Expr, EvalError> {
+        self.enter()?;
+        // `around` counts the rest of the term, normalized or not
+        let normalize = |this: &mut Self, e: &Expr, around: usize| {
+            this.nested(around, |this| this.normalize(e)).map(Box::new)
+        };
+        let normal = match self.whnf(e)? {
+            Expr::Abs { x, t, e } => Expr::Abs {
+                x,
+                t,
+                e: normalize(self, &e, 1)?,
+            },
+            Expr::TyAbs { x, e } => Expr::TyAbs {
+                x,
+                e: normalize(self, &e, 1)?,
+            },
+            // anything else is stuck on a variable at its head
+            Expr::App { e1, e2 } => Expr::App {
+                e1: normalize(self, &e1, 1 + size(&e2))?,
+                e2: normalize(self, &e2, 1 + size(&e1))?,
+            },
+            Expr::TyApp { e, t } => Expr::TyApp {
+                e: normalize(self, &e, 1)?,
+                t,
+            },
+            Expr::If { e1, e2, e3 } => Expr::If {
+                e1: normalize(self, &e1, 1 + size(&e2) + size(&e3))?,
+                e2: normalize(self, &e2, 1 + size(&e1) + size(&e3))?,
+                e3: normalize(self, &e3, 1 + size(&e1) + size(&e2))?,
+            },
+            Expr::Fold { t, e } => Expr::Fold {
+                t,
+                e: normalize(self, &e, 1)?,
+            },
+            Expr::Unfold { t, e } => Expr::Unfold {
+                t,
+                e: normalize(self, &e, 1)?,
+            },
+            Expr::Ref(e) => Expr::Ref(normalize(self, &e, 1)?),
+            Expr::Raise(e) => Expr::Raise(normalize(self, &e, 1)?),
+            Expr::List(es) => {
+                let around = 1 + es.iter().map(size).sum::<usize>();
+                Expr::List(
+                    es.iter()
+                        .map(|e| normalize(self, e, around).map(|e| *e))
+                        .collect::<Result<_, _>>()?,
+                )
+            }
+            Expr::Try { e1, e2 } => Expr::Try {
+                e1: normalize(self, &e1, 1 + size(&e2))?,
+                e2: normalize(self, &e2, 1 + size(&e1))?,
+            },
+            Expr::Deref(e) => Expr::Deref(normalize(self, &e, 1)?),
+            Expr::Assign { e1, e2 } => Expr::Assign {
+                e1: normalize(self, &e1, 1 + size(&e2))?,
+                e2: normalize(self, &e2, 1 + size(&e1))?,
+            },
+            Expr::Match { e, arms } => {
+                let around = 1 + size(&e) + arms.iter().map(|(_, e)| size(e)).sum::<usize>();
+                Expr::Match {
+                    e: normalize(self, &e, around)?,
+                    arms: arms
+                        .into_iter()
+                        .map(|(p, e)| Ok((p, *normalize(self, &e, around)?)))
+                        .collect::<Result<_, _>>()?,
+                }
+            }
+            e => e,
+        };
+        self.depth -= 1;
+        Ok(normal)
+    }
+
+    /// Reduces `e` until its head is a binder, a constant or a variable.
+    fn whnf(&mut self, e: &Expr) -> Result<Expr, EvalError> {
+        self.enter()?;
+        let mut e = e.clone();
+        let head = loop {
+            let next = match e {
+                Expr::App { e1, e2 } => match self.nested(size(&e2), |this| this.whnf(&e1))? {
+                    Expr::Abs { x, t: _, e } => substitute(&e, &x, &e2),
+                    e1 @ Expr::Raise(_) => break e1,
+                    e1 => {
+                        let app = Expr::App {
+                            e1: Box::new(e1),
+                            e2,
+                        };
+                        let Some((p, mut args)) = saturated(&app) else {
+                            break app;
+                        };
+                        let mut raised = None;
+                        for &i in p.strict() {
+                            args[i] = self.nested(1, |this| this.whnf(&args[i]))?;
+                            if let Expr::Raise(_) = args[i] {
+                                raised = Some(args[i].clone());
+                                break;
+                            }
+                        }
+                        if let Some(e) = raised {
+                            break e;
+                        }
+                        match apply(p, args) {
+                            Some(next) => next,
+                            None => break app,
+                        }
+                    }
+                },
+                Expr::TyApp { e, t } => match self.whnf(&e)? {
+                    Expr::TyAbs { x, e } => substitute_expr(&e, &x, &t),
+                    Expr::Prim(Prim::Nil) => Expr::List(Vec::new()),
+                    e @ Expr::Raise(_) => break e,
+                    e => break Expr::TyApp { e: Box::new(e), t },
+                },
+                Expr::Unfold { t, e } => match self.nested(1, |this| this.whnf(&e))? {
+                    Expr::Fold { t: _, e } => *e,
+                    e @ Expr::Raise(_) => break e,
+                    e => break Expr::Unfold { t, e: Box::new(e) },
+                },
+                Expr::Try { e1, e2 } => match self.nested(1 + size(&e2), |this| this.whnf(&e1))? {
+                    Expr::Raise(v) => Expr::App { e1: e2, e2: v },
+                    // stuck on a variable, which might still raise
+                    e1 @ (Expr::Var(_)
+                    | Expr::App { .. }
+                    | Expr::TyApp { .. }
+                    | Expr::If { .. }
+                    | Expr::Unfold { .. }
+                    | Expr::Deref(_)
+                    | Expr::Assign { .. }
+                    | Expr::Try { .. }
+                    | Expr::Match { .. }) => {
+                        break Expr::Try {
+                            e1: Box::new(e1),
+                            e2,
+                        }
+                    }
+                    v => break v,
+                },
+                Expr::If { e1, e2, e3 } => {
+                    match self.nested(size(&e2) + size(&e3), |this| this.whnf(&e1))? {
+                        Expr::Constant(Constant::True) => *e2,
+                        Expr::Constant(Constant::False) => *e3,
+                        e1 @ Expr::Raise(_) => break e1,
+                        e1 => {
+                            break Expr::If {
+                                e1: Box::new(e1),
+                                e2,
+                                e3,
+                            }
+                        }
+                    }
+                }
+                Expr::Match { e, arms } => {
+                    let around = 1 + arms.iter().map(|(_, e)| size(e)).sum::<usize>();
+                    let v = self.nested(around, |this| this.whnf(&e))?;
+                    if let Expr::Raise(_) = v {
+                        break v;
+                    }
+                    let force =
+                        |this: &mut Self, e: &Expr| this.nested(around, |this| this.whnf(e));
+                    match self.select(v, &arms, force)? {
+                        Matched::Arm(e) => e,
+                        Matched::Raised(raise) => break raise,
+                        Matched::Stuck(v) => {
+                            break Expr::Match {
+                                e: Box::new(v),
+                                arms,
+                            }
+                        }
+                    }
+                }
+                Expr::Ann { e, t: _ } => *e,
+                Expr::Hole(name) => return Err(EvalError::Hole { name }),
+                e => break e,
+            };
+            self.step(&next)?;
+            e = next;
+        };
+        self.depth -= 1;
+        Ok(head)
+    }
+
+    /// Matches the value `v` against `arms`, evaluating the subterms of `v` that the decision
+    /// tree tests with `force` as it gets to them. Call-by-name, those are the arguments of
+    /// constructors, which are only evaluated once a pattern needs to look inside them.
+    fn select(
+        &mut self,
+        mut v: Expr,
+        arms: &[(Pattern, Expr)],
+        mut force: impl FnMut(&mut Self, &Expr) -> Result<Expr, EvalError>,
+    ) -> Result<Matched, EvalError> {
+        let tree = matching::compile(arms);
+        loop {
+            match tree.select(&v) {
+                Select::Arm(i, bindings) => {
+                    let body = bindings
+                        .iter()
+                        .fold(arms[i].1.clone(), |e, (x, s)| substitute(&e, x, s));
+                    return Ok(Matched::Arm(body));
+                }
+                Select::Force(path) => {
+                    let e = matching::subterm_mut(&mut v, &path);
+                    *e = force(self, e)?;
+                    match e {
+                        Expr::Raise(_) => return Ok(Matched::Raised(e.clone())),
+                        e if matching::constructor(e).is_none() => return Ok(Matched::Stuck(v)),
+                        _ => {}
+                    }
+                }
+                Select::Fail => return Ok(Matched::Stuck(v)),
+            }
+        }
+    }
+
+    /// Reduces a subterm with `size` more nodes around it.
+    fn nested<T>(
+        &mut self,
+        size: usize,
+        f: impl FnOnce(&mut Self) -> Result<T, EvalError>,
+    ) -> Result<T, EvalError> {
+        self.around += size;
+        let result = f(self);
+        self.around -= size;
+        result
+    }
+
+    fn enter(&mut self) -> Result<(), EvalError> {
+        if self.depth == self.limits.max_depth {
+            return Err(EvalError::TooDeep {
+                depth: self.limits.max_depth,
+            });
+        }
+        self.depth += 1;
+        Ok(())
+    }
+
+    /// Counts a reduction to `next`.
+    fn step(&mut self, next: &Expr) -> Result<(), EvalError> {
+        if self.interrupt.load(Ordering::Relaxed) {
+            return Err(EvalError::Interrupted);
+        }
+        if self.steps == self.limits.max_steps {
+            return Err(EvalError::FuelExhausted {
+                steps: self.limits.max_steps,
+            });
+        }
+        self.steps += 1;
+        if self.around + size(next) > self.limits.max_term_size {
+            return Err(EvalError::TermTooLarge {
+                size: self.limits.max_term_size,
+            });
+        }
+        Ok(())
     }
 }
 
-fn app(e1: &Expr, e2: &Expr, by_value: bool) -> Expr {
-    if !is_value(e1) {
-        let e1_new = interpret(e1, by_value);
-        app(&e1_new, e2, by_value)
-    } else if by_value && !is_value(e2) {
-        let e2_new = interpret(e2, by_value);
-        app(e1, &e2_new, by_value)
-    } else if let Expr::Abs { x, t: _, e } = e1 {
-        interpret(&substitute(e, x, e2), by_value)
-    } else {
-        unreachable!("failed typechecking")
+/// The primitive at the head of `e` and the terms it's applied to, if there are as many as it
+/// takes. `nil` takes none, but computes once it's applied to a type.
+pub fn saturated(e: &Expr) -> Option<(Prim, Vec<Expr>)> {
+    fn spine(e: &Expr) -> Option<(Prim, Vec<Expr>)> {
+        match e {
+            Expr::Prim(p) => Some((*p, Vec::new())),
+            Expr::TyApp { e, t: _ } => spine(e),
+            Expr::App { e1, e2 } => {
+                let (p, mut args) = spine(e1)?;
+                args.push(*e2.clone());
+                Some((p, args))
+            }
+            _ => None,
+        }
     }
+    spine(e).filter(|(p, args)| args.len() == p.arity())
 }
 
-fn r#if(e1: &Expr, e2: &Expr, e3: &Expr, by_value: bool) -> Expr {
-    if !is_value(e1) {
-        let e1_new = interpret(e1, by_value);
-        r#if(&e1_new, e2, e3, by_value)
-    } else if let Expr::Constant(Constant::True) = e1 {
-        interpret(e2, by_value)
-    } else {
-        interpret(e3, by_value)
+/// What `p` applied to `args` reduces to, once its strict arguments are values, or `None` if
+/// they aren't lists or strings. `head` and `tail` raise `true` on the empty list.
+pub fn apply(p: Prim, mut args: Vec<Expr>) -> Option<Expr> {
+    let list = |e: Expr| match e {
+        Expr::List(es) => Some(es),
+        _ => None,
+    };
+    let string = |e: &Expr| match e {
+        Expr::Constant(Constant::String(s)) => Some(s.clone()),
+        _ => None,
+    };
+    let boolean = |b: bool| {
+        Expr::Constant(match b {
+            true => Constant::True,
+            false => Constant::False,
+        })
+    };
+    let empty = || Expr::Raise(Box::new(Expr::Constant(Constant::True)));
+    let app = |e1: Expr, e2: Expr| Expr::App {
+        e1: Box::new(e1),
+        e2: Box::new(e2),
+    };
+    Some(match p {
+        Prim::Nil => Expr::List(Vec::new()),
+        Prim::Cons => {
+            let mut es = list(args.pop()?)?;
+            es.insert(0, args.pop()?);
+            Expr::List(es)
+        }
+        Prim::IsNil => boolean(list(args.pop()?)?.is_empty()),
+        Prim::Head => {
+            let mut es = list(args.pop()?)?;
+            match es.is_empty() {
+                true => empty(),
+                false => es.remove(0),
+            }
+        }
+        Prim::Tail => {
+            let mut es = list(args.pop()?)?;
+            match es.is_empty() {
+                true => empty(),
+                false => Expr::List(es.split_off(1)),
+            }
+        }
+        // foldr f z [x1, x2] => f x1 (f x2 z)
+        Prim::Foldr => {
+            let es = list(args.pop()?)?;
+            let z = args.pop()?;
+            let f = args.pop()?;
+            es.into_iter()
+                .rev()
+                .fold(z, |acc, x| app(app(f.clone(), x), acc))
+        }
+        Prim::Concat => Expr::Constant(Constant::String(string(&args[0])? + &string(&args[1])?)),
+        Prim::Length => church::encode(string(&args[0])?.chars().count() as u64),
+        Prim::Eq => boolean(string(&args[0])? == string(&args[1])?),
+        // char_at "ab" n => head [Char] (n [List Char] (tail [Char]) ['a', 'b']), which raises
+        // like `head` past the end
+        Prim::CharAt => {
+            let chars = string(&args[0])?
+                .chars()
+                .map(|c| Expr::Constant(Constant::Char(c)))
+                .collect();
+            let char_prim = |p: Prim| Expr::TyApp {
+                e: Box::new(Expr::Prim(p)),
+                t: Type::Char,
+            };
+            let n = Expr::TyApp {
+                e: Box::new(args.pop()?),
+                t: Type::List(Box::new(Type::Char)),
+            };
+            app(
+                char_prim(Prim::Head),
+                app(app(n, char_prim(Prim::Tail)), Expr::List(chars)),
+            )
+        }
+    })
+}
+
+/// The number of nodes in `e`, not counting types.
+fn size(e: &Expr) -> usize {
+    1 + match e {
+        Expr::Var(_)
+        | Expr::Constant(_)
+        | Expr::Loc(_)
+        | Expr::Prim(_)
+        | Expr::Con(_)
+        | Expr::Hole(_) => 0,
+        Expr::List(es) => es.iter().map(size).sum(),
+        Expr::Match { e, arms } => size(e) + arms.iter().map(|(_, e)| size(e)).sum::<usize>(),
+        Expr::Abs { x: _, t: _, e } | Expr::Ann { e, t: _ } => size(e),
+        Expr::TyAbs { x: _, e } | Expr::TyApp { e, t: _ } => size(e),
+        Expr::Ref(e) | Expr::Deref(e) | Expr::Raise(e) => size(e),
+        Expr::Fold { t: _, e } | Expr::Unfold { t: _, e } => size(e),
+        Expr::App { e1, e2 } | Expr::Assign { e1, e2 } | Expr::Try { e1, e2 } => {
+            size(e1) + size(e2)
+        }
+        Expr::If { e1, e2, e3 } => size(e1) + size(e2) + size(e3),
     }
 }
 
-fn substitute(expr: &Expr, old: &String, new: &Expr) -> Expr {
+pub fn substitute(expr: &Expr, old: &String, new: &Expr) -> Expr {
     match expr {
         Expr::Var(x) if x == old => new.clone(),
-        Expr::Var(_) | Expr::Constant(_) => expr.clone(),
+        Expr::Var(_)
+        | Expr::Constant(_)
+        | Expr::Loc(_)
+        | Expr::Prim(_)
+        | Expr::Con(_)
+        | Expr::Hole(_) => expr.clone(),
+        Expr::List(es) => Expr::List(es.iter().map(|e| substitute(e, old, new)).collect()),
+        Expr::Match { e, arms } => Expr::Match {
+            e: Box::new(substitute(e, old, new)),
+            arms: arms
+                .iter()
+                .map(|(p, e)| {
+                    if p.vars().contains(&old) {
+                        return (p.clone(), e.clone());
+                    }
+                    // rename the variables of `p` that would capture ones in `new`
+                    let fv_new = fv(new);
+                    let (mut p, mut e) = (p.clone(), e.clone());
+                    for x in p.clone().vars() {
+                        if fv_new.contains(x) {
+                            let mut avoid: HashSet<&String> = fv(&e);
+                            avoid.extend(&fv_new);
+                            let mut new_name = format!("{x}1");
+                            while avoid.contains(&new_name) {
+                                new_name = format!("{new_name}1");
+                            }
+                            e = substitute(&e, x, &Expr::Var(new_name.clone()));
+                            p = p.rename(x, &new_name);
+                        }
+                    }
+                    (p, substitute(&e, old, new))
+                })
+                .collect(),
+        },
         Expr::App { e1, e2 } => Expr::App {
             e1: Box::new(substitute(e1, old, new)),
             e2: Box::new(substitute(e2, old, new)),
         },
+        Expr::Assign { e1, e2 } => Expr::Assign {
+            e1: Box::new(substitute(e1, old, new)),
+            e2: Box::new(substitute(e2, old, new)),
+        },
+        Expr::Ref(e) => Expr::Ref(Box::new(substitute(e, old, new))),
+        Expr::Deref(e) => Expr::Deref(Box::new(substitute(e, old, new))),
+        Expr::Raise(e) => Expr::Raise(Box::new(substitute(e, old, new))),
+        Expr::Try { e1, e2 } => Expr::Try {
+            e1: Box::new(substitute(e1, old, new)),
+            e2: Box::new(substitute(e2, old, new)),
+        },
+        Expr::Fold { t, e } => Expr::Fold {
+            t: t.clone(),
+            e: Box::new(substitute(e, old, new)),
+        },
+        Expr::Unfold { t, e } => Expr::Unfold {
+            t: t.clone(),
+            e: Box::new(substitute(e, old, new)),
+        },
         Expr::Abs { x, t: _, e: _ } if x == old => expr.clone(),
         Expr::Abs { x, t, e } => {
             let fv_body = fv(new);
@@ -76,14 +816,37 @@ fn substitute(expr: &Expr, old: &String, new: &Expr) -> Expr {
             e2: Box::new(substitute(e2, old, new)),
             e3: Box::new(substitute(e3, old, new)),
         },
+        Expr::Ann { e, t } => Expr::Ann {
+            e: Box::new(substitute(e, old, new)),
+            t: t.clone(),
+        },
+        Expr::TyAbs { x, e } => Expr::TyAbs {
+            x: x.to_string(),
+            e: Box::new(substitute(e, old, new)),
+        },
+        Expr::TyApp { e, t } => Expr::TyApp {
+            e: Box::new(substitute(e, old, new)),
+            t: t.clone(),
+        },
     }
 }
 
 fn fv(e: &Expr) -> HashSet<&String> {
     match e {
         Expr::Var(x) => HashSet::from([x]),
-        Expr::Constant(_) => HashSet::new(),
-        Expr::App { e1, e2 } => {
+        Expr::Constant(_) | Expr::Loc(_) | Expr::Prim(_) | Expr::Con(_) | Expr::Hole(_) => {
+            HashSet::new()
+        }
+        Expr::List(es) => es.iter().flat_map(fv).collect(),
+        Expr::Match { e, arms } => {
+            let mut set = fv(e);
+            for (p, e) in arms {
+                let vars = p.vars();
+                set.extend(fv(e).into_iter().filter(|x| !vars.contains(x)));
+            }
+            set
+        }
+        Expr::App { e1, e2 } | Expr::Assign { e1, e2 } | Expr::Try { e1, e2 } => {
             let fv1 = fv(e1);
             let fv2 = fv(e2);
             fv1.union(&fv2).cloned().collect()
@@ -104,19 +867,23 @@ fn fv(e: &Expr) -> HashSet<&String> {
                 .cloned()
                 .collect()
         }
+        Expr::Ann { e, t: _ } | Expr::TyAbs { x: _, e } | Expr::TyApp { e, t: _ } => fv(e),
+        Expr::Ref(e) | Expr::Deref(e) | Expr::Raise(e) => fv(e),
+        Expr::Fold { t: _, e } | Expr::Unfold { t: _, e } => fv(e),
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::session::Session;
 
     #[test]
     fn substitution() {
         // (\x.x y)[y := x] => (\x1.x1 x)
         let e = Expr::Abs {
             x: "x".into(),
-            t: crate::parser::Type::Bool,
+            t: Some(crate::parser::Type::Bool),
             e: Box::new(Expr::App {
                 e1: Box::new(Expr::Var("x".into())),
                 e2: Box::new(Expr::Var("y".into())),
@@ -127,7 +894,7 @@ mod tests {
             e2,
             Expr::Abs {
                 x: "x1".into(),
-                t: crate::parser::Type::Bool,
+                t: Some(crate::parser::Type::Bool),
                 e: Box::new(Expr::App {
                     e1: Box::new(Expr::Var("x1".into())),
                     e2: Box::new(Expr::Var("x".into()))
@@ -135,4 +902,123 @@ mod tests {
             }
         );
     }
+
+    #[test]
+    fn stack_limits() {
+        let e = Expr::Constant(Constant::True);
+        let huge = EvalLimits {
+            max_depth: usize::MAX,
+            ..EvalLimits::default()
+        };
+        assert_eq!(huge.stack_size(), None);
+        assert_eq!(
+            interpret(&e, false, &huge, &AtomicBool::new(false), &mut Vec::new()),
+            Err(EvalError::NoStack)
+        );
+        assert!(EvalLimits::default().stack_size().is_some());
+    }
+
+    #[test]
+    fn references() {
+        let mut session = Session::default();
+        session.by_value = true;
+        assert_eq!(session.eval("r = ref true"), "r : Ref Bool");
+        // the definition allocated once, so both uses see the same location
+        assert_eq!(session.eval("r := false"), "unit : Unit");
+        assert_eq!(session.eval("!r"), "false : Bool");
+        assert_eq!(
+            session.eval("(\\s: Ref Bool. s) (ref true)"),
+            "<loc 1> : Ref Bool"
+        );
+        assert_eq!(
+            session.store(),
+            [
+                Expr::Constant(Constant::False),
+                Expr::Constant(Constant::True)
+            ]
+        );
+        assert!(session.exec("r := r").is_err());
+        assert!(session.exec("!true").is_err());
+    }
+
+    #[test]
+    fn exceptions() {
+        let mut session = Session::default();
+        assert_eq!(
+            session.eval("try (if true then raise false else true) with \\e: Bool. e"),
+            "false : Bool"
+        );
+        // call-by-name never evaluates the argument that raises
+        let line = "try (\\x: Bool. true) (raise false) with \\e: Bool. e";
+        assert_eq!(session.eval(line), "true : Bool");
+        session.by_value = true;
+        assert_eq!(session.eval(line), "false : Bool");
+        assert_eq!(
+            session
+                .exec("(raise true : Bool -> Bool) false")
+                .unwrap_err()
+                .downcast_ref(),
+            Some(&EvalError::Uncaught {
+                value: Expr::Constant(Constant::True)
+            })
+        );
+        assert!(session.exec("raise true").is_err());
+        assert!(session.exec("try true with \\e: Bool. unit").is_err());
+    }
+
+    #[test]
+    fn lists() {
+        let mut session = Session::default();
+        assert_eq!(
+            session.eval("cons [Bool] true [false]"),
+            "[true, false] : List Bool"
+        );
+        assert_eq!(
+            session.eval("cons [Bool] true"),
+            "cons [Bool] true : List Bool -> List Bool"
+        );
+        assert_eq!(
+            session.eval(
+                "foldr [Bool] [Bool] (\\x: Bool. \\y: Bool. if x then y else false) true \
+                 (tail [Bool] [false, true, true])"
+            ),
+            "true : Bool"
+        );
+        assert_eq!(session.eval("isnil [Bool] (nil [Bool])"), "true : Bool");
+        assert_eq!(
+            session
+                .exec("head [Bool] ([] : List Bool)")
+                .unwrap_err()
+                .downcast_ref(),
+            Some(&EvalError::Uncaught {
+                value: Expr::Constant(Constant::True)
+            })
+        );
+        assert!(session.exec("[]").is_err());
+        assert!(session.exec("[true, unit]").is_err());
+    }
+
+    #[test]
+    fn strings() {
+        let mut session = Session::with_prelude();
+        assert_eq!(session.eval(r#"eq (concat "a" "b") "ab""#), "true : Bool");
+        assert_eq!(
+            session.eval(r#"length "héllo""#),
+            "/\\X. \\f: X -> X. \\x: X. f (f (f (f (f x)))) : forall X. (X -> X) -> X -> X"
+        );
+        assert_eq!(
+            session.eval(r#"char_at "abc" (succ (succ zero))"#),
+            "'c' : Char"
+        );
+        assert_eq!(
+            session
+                .exec(r#"char_at "abc" (length "abc")"#)
+                .unwrap_err()
+                .downcast_ref(),
+            Some(&EvalError::Uncaught {
+                value: Expr::Constant(Constant::True)
+            })
+        );
+        assert!(session.exec(r#"concat "a" 'b'"#).is_err());
+    }
 }
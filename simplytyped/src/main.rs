@@ -1,37 +1,234 @@
-use parser::Parser;
-use scanner::Scanner;
+use std::{io, sync::atomic::Ordering};
 
-use crate::typer::Typer;
+use anyhow::{anyhow, bail};
+use rustyline::error::ReadlineError;
 
+use crate::{
+    interpreter::EvalLimits,
+    parser::{Expr, Type},
+    session::{Outcome, Session, SessionError},
+    typer::alpha_eq,
+};
+
+mod church;
+mod derivation;
+mod explain;
 mod interpreter;
+mod matching;
 mod parser;
+mod pretty;
 mod scanner;
+mod session;
+#[cfg(test)]
+mod soundness;
 mod typer;
 
 fn main() {
+    let mut args = std::env::args().skip_while(|arg| arg != "--explain");
+    if args.next().is_some() {
+        match args
+            .next()
+            .ok_or_else(|| anyhow!("`--explain` needs an error code, as in `--explain E0203`"))
+            .and_then(|code| explain::explain(&code))
+        {
+            Ok(text) => print!("{}", text),
+            Err(e) => {
+                eprintln!("{}", e);
+                std::process::exit(1);
+            }
+        }
+        return;
+    }
+
+    if let Some(format) = std::env::args().find_map(|arg| {
+        arg.strip_prefix("--derivation=")
+            .map(|format| format.to_string())
+    }) {
+        if let Err(e) = derive_batch(&format) {
+            report(&e);
+            std::process::exit(1);
+        }
+        return;
+    }
+
+    let untyped = std::env::args().any(|arg| arg == "--untyped");
+    let mut session = Session::with_prelude();
+    session.limits = match limits() {
+        Ok(limits) => limits,
+        Err(e) => {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        }
+    };
+    session.by_value = std::env::args().any(|arg| arg == "--by-value");
+    let interrupt = session.interrupt.clone();
+    ctrlc::set_handler(move || interrupt.store(true, Ordering::Relaxed))
+        .expect("failed to install the Ctrl-C handler");
+    let mut decode = false;
     let mut rl = rustyline::DefaultEditor::new().unwrap();
     loop {
         let readline = rl.readline("λ ");
         match readline {
+            Ok(line) if line.trim() == ":quit" => break,
             Ok(line) => {
                 rl.add_history_entry(&line).unwrap();
-                if let Err(e) = type_line(line) {
-                    eprintln!("{}", e);
+                if let Err(e) = run_line(&line, &mut session, untyped, &mut decode) {
+                    report(&e);
                 }
             }
-            Err(_) => {
-                println!("Connection terminated");
+            // Ctrl-C at the prompt just clears the line
+            Err(ReadlineError::Interrupted) => continue,
+            Err(ReadlineError::Eof) => break,
+            Err(e) => {
+                eprintln!("{}", e);
                 break;
             }
         }
     }
+    println!("Connection terminated");
 }
 
-fn type_line(line: String) -> anyhow::Result<()> {
-    let tokens = Scanner::scan(line)?;
-    let expr = Parser::parse(tokens)?;
-    let ty = Typer::default().typecheck(&expr)?;
-    let out = interpreter::interpret(&expr, false);
-    println!("{} : {}", out.to_string(), ty.to_string());
+/// Runs a line of the REPL: a command, a definition, a `data` declaration or a term.
+fn run_line(
+    line: &str,
+    session: &mut Session,
+    untyped: bool,
+    decode: &mut bool,
+) -> anyhow::Result<()> {
+    if untyped && line.starts_with(":derive ") {
+        bail!(SessionError::UntypedDerivation)
+    } else if let Some(rest) = line.strip_prefix(":derive ") {
+        session
+            .derive(rest)
+            .map(|derivation| println!("{}", derivation.ascii()))
+    } else if let Some(code) = line.strip_prefix(":explain ") {
+        explain::explain(code).map(|text| print!("{}", text))
+    } else if line.trim() == ":context" {
+        match session.error_context() {
+            Some(context) if context.frames().iter().all(Vec::is_empty) => {
+                println!("no variables were in scope at the last type error")
+            }
+            Some(context) => println!("{}", context),
+            None if !session.holes().is_empty() => {
+                for hole in session.holes() {
+                    println!("hole {}", hole);
+                }
+            }
+            None => println!("the last line had no type error or holes"),
+        }
+        Ok(())
+    } else if let Some(mode) = line.strip_prefix(":decode ") {
+        match mode.trim() {
+            "church" => *decode = true,
+            "off" => *decode = false,
+            mode => eprintln!("unknown decoding `{}`; expected `church` or `off`", mode),
+        }
+        Ok(())
+    } else if let Some(n) = line.strip_prefix(":encode ") {
+        n.trim()
+            .parse()
+            .map_err(anyhow::Error::from)
+            .map(|n| println!("{} : {}", church::encode(n), church::numeral_type()))
+    } else if untyped {
+        session
+            .exec_untyped(line)
+            .map(|e| println!("{}", show(&e, None, *decode, session)))
+    } else {
+        let result = session.exec(line).map(|outcome| match outcome {
+            Outcome::Value(e, ty) => {
+                println!("{} : {}", show(&e, Some(&ty), *decode, session), ty);
+                for (l, v) in session.store().iter().enumerate() {
+                    println!("  {} = {}", Expr::Loc(l), v);
+                }
+            }
+            Outcome::Data(constructors) => {
+                for (c, ty) in constructors {
+                    println!("{} : {}", c, ty);
+                }
+            }
+        });
+        for hole in session.holes() {
+            println!("hole {}", hole);
+        }
+        result
+    }
+}
+
+/// Prints `e`, prefixed with its code if it has one.
+fn report(e: &anyhow::Error) {
+    match explain::code(e) {
+        Some(code) => eprintln!("error[{}]: {}", code, e),
+        None => eprintln!("{}", e),
+    }
+}
+
+/// Prints Church and Scott numerals as numbers if `decode` is on. Typed terms are only decoded
+/// if they have the type of numerals; untyped ones are already normal forms.
+fn show(e: &Expr, ty: Option<&Type>, decode: bool, session: &Session) -> String {
+    if !decode {
+        return e.to_string();
+    }
+    let n = match ty {
+        Some(ty) if alpha_eq(ty, &church::numeral_type()) => {
+            interpreter::normalize(e, &session.limits, &session.interrupt)
+                .ok()
+                .and_then(|e| church::decode(&e))
+        }
+        Some(ty) if alpha_eq(ty, &church::scott_numeral_type()) => {
+            interpreter::normalize(e, &session.limits, &session.interrupt)
+                .ok()
+                .and_then(|e| church::decode_scott(&e))
+        }
+        Some(_) => None,
+        None => church::decode(e),
+    };
+    n.map_or_else(|| e.to_string(), |n| n.to_string())
+}
+
+/// Reads `--max-steps=N`, `--max-size=N` and `--max-depth=N`, defaulting the rest.
+fn limits() -> anyhow::Result<EvalLimits> {
+    let mut limits = EvalLimits::default();
+    for arg in std::env::args() {
+        let Some((flag, n)) = arg.split_once('=') else {
+            continue;
+        };
+        match flag {
+            "--max-steps" => limits.max_steps = n.parse()?,
+            "--max-size" => limits.max_term_size = n.parse()?,
+            "--max-depth" => limits.max_depth = n.parse()?,
+            _ => {}
+        }
+    }
+    if limits.stack_size().is_none() {
+        bail!(
+            "`--max-depth={}` and `--max-size={}` need more than {} MiB of stack; lower them",
+            limits.max_depth,
+            limits.max_term_size,
+            interpreter::MAX_STACK >> 20
+        );
+    }
+    Ok(limits)
+}
+
+/// Prints a derivation for every line of stdin, as `ascii` trees or `latex` for `bussproofs`.
+fn derive_batch(format: &str) -> anyhow::Result<()> {
+    if !matches!(format, "ascii" | "latex") {
+        bail!(
+            "unknown derivation format `{}`; expected `ascii` or `latex`",
+            format
+        );
+    }
+    let session = Session::with_prelude();
+    for line in io::stdin().lines() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let derivation = session.derive(&line)?;
+        match format {
+            "latex" => print!("{}", derivation.latex()),
+            _ => println!("{}\n", derivation.ascii()),
+        }
+    }
     Ok(())
 }
@@ -0,0 +1,423 @@
+//! Pattern matching: checking that the arms of a `match` cover every value and can all be
+//! reached, following Maranget's "Warnings for pattern matching", and compiling them to
+//! decision trees that test each subterm of the scrutinee at most once.
+
+use crate::parser::{Expr, Pattern};
+
+/// The constructors of the `data` type that a constructor belongs to, with how many arguments
+/// each takes.
+pub type Signature<'a> = dyn Fn(&str) -> Vec<(String, usize)> + 'a;
+
+const WILDCARD: &Pattern = &Pattern::Wildcard;
+
+/// A value that none of `patterns` matches, with `_` for the parts that don't matter, if there
+/// is one.
+pub fn missing(patterns: &[&Pattern], signature: &Signature) -> Option<Pattern> {
+    let rows: Vec<_> = patterns.iter().map(|p| vec![*p]).collect();
+    useful(&rows, &[WILDCARD], signature).map(|mut w| w.remove(0))
+}
+
+/// The first pattern that only matches values that the ones before it already do.
+pub fn redundant(patterns: &[&Pattern], signature: &Signature) -> Option<usize> {
+    let rows: Vec<_> = patterns.iter().map(|p| vec![*p]).collect();
+    (0..rows.len()).find(|&i| useful(&rows[..i], &rows[i], signature).is_none())
+}
+
+/// Values that `q` matches but no row of `rows` does, as patterns of the same width, or `None`
+/// if every row of values matching `q` matches some row already.
+fn useful(rows: &[Vec<&Pattern>], q: &[&Pattern], signature: &Signature) -> Option<Vec<Pattern>> {
+    let Some((head, rest)) = q.split_first() else {
+        return rows.is_empty().then(Vec::new);
+    };
+    let specialized = |c: &str, args: Vec<&Pattern>| {
+        let n = args.len();
+        let q: Vec<_> = args.into_iter().chain(rest.iter().copied()).collect();
+        let w = useful(&specialize(rows, c, n), &q, signature)?;
+        Some(rebuild(c, n, w))
+    };
+    if let Pattern::Con(c, args) = head {
+        return specialized(c, args.iter().collect());
+    }
+    let mut heads: Vec<&String> = Vec::new();
+    for row in rows {
+        if let Pattern::Con(c, _) = row[0] {
+            if !heads.contains(&c) {
+                heads.push(c);
+            }
+        }
+    }
+    let constructors = heads.first().map_or_else(Vec::new, |c| signature(c));
+    if !heads.is_empty() && constructors.iter().all(|(c, _)| heads.contains(&c)) {
+        // the rows mention every constructor, so the values `q` misses have to be found
+        // under one of them
+        return constructors
+            .iter()
+            .find_map(|(c, n)| specialized(c, vec![WILDCARD; *n]));
+    }
+    let defaults: Vec<_> = rows
+        .iter()
+        .filter(|row| !matches!(row[0], Pattern::Con(..)))
+        .map(|row| row[1..].to_vec())
+        .collect();
+    let mut w = useful(&defaults, rest, signature)?;
+    let head = match constructors.iter().find(|(c, _)| !heads.contains(&c)) {
+        Some((c, n)) => Pattern::Con(c.clone(), vec![Pattern::Wildcard; *n]),
+        None => Pattern::Wildcard,
+    };
+    w.insert(0, head);
+    Some(w)
+}
+
+/// The rows that match values built with `c`, with the first column replaced by the `n`
+/// arguments of `c`.
+fn specialize<'p>(rows: &[Vec<&'p Pattern>], c: &str, n: usize) -> Vec<Vec<&'p Pattern>> {
+    rows.iter()
+        .filter_map(|row| {
+            let args: Vec<&Pattern> = match row[0] {
+                Pattern::Con(c1, args) if c1 == c => args.iter().collect(),
+                Pattern::Con(..) => return None,
+                Pattern::Var(_) | Pattern::Wildcard => vec![WILDCARD; n],
+            };
+            Some(args.into_iter().chain(row[1..].iter().copied()).collect())
+        })
+        .collect()
+}
+
+/// Puts the first `n` patterns of `w` back under `c`.
+fn rebuild(c: &str, n: usize, mut w: Vec<Pattern>) -> Vec<Pattern> {
+    let rest = w.split_off(n);
+    let mut rebuilt = vec![Pattern::Con(c.to_string(), w)];
+    rebuilt.extend(rest);
+    rebuilt
+}
+
+/// Where a subterm is in the scrutinee: the index of an argument of each constructor on the
+/// way down.
+pub type Path = Vec<usize>;
+
+/// A decision tree for the arms of a `match`.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Tree {
+    /// The arm at `arm` matches, binding each variable to the subterm at its path.
+    Leaf {
+        arm: usize,
+        bindings: Vec<(String, Path)>,
+    },
+    /// Tests which constructor the subterm at `path` is built with. Constructors that no
+    /// case mentions go to `default`.
+    Switch {
+        path: Path,
+        cases: Vec<(String, Tree)>,
+        default: Box<Tree>,
+    },
+    /// No arm matches.
+    Fail,
+}
+
+/// What matching a scrutinee against a `Tree` needs next.
+#[derive(Debug, PartialEq)]
+pub enum Select {
+    /// The arm at the index matches, with the subterms its variables bind.
+    Arm(usize, Vec<(String, Expr)>),
+    /// The subterm at the path has to be evaluated to a constructor first.
+    Force(Path),
+    Fail,
+}
+
+/// A row of the pattern matrix: the patterns left to match, the arm they come from, and the
+/// variables bound so far.
+#[derive(Clone)]
+struct Row<'p> {
+    patterns: Vec<&'p Pattern>,
+    arm: usize,
+    bindings: Vec<(String, Path)>,
+}
+
+impl<'p> Row<'p> {
+    /// Takes the pattern in column `i` out, binding it if it's a variable at `path`.
+    fn take(&self, i: usize, path: &Path) -> (&'p Pattern, Row<'p>) {
+        let mut patterns = self.patterns.clone();
+        let p = patterns.remove(i);
+        let mut bindings = self.bindings.clone();
+        if let Pattern::Var(x) = p {
+            bindings.push((x.clone(), path.clone()));
+        }
+        let arm = self.arm;
+        (
+            p,
+            Row {
+                patterns,
+                arm,
+                bindings,
+            },
+        )
+    }
+}
+
+/// Compiles the arms of a `match` to a decision tree, testing the subterms that the first arm
+/// that could match needs, left to right.
+pub fn compile(arms: &[(Pattern, Expr)]) -> Tree {
+    let rows = arms
+        .iter()
+        .enumerate()
+        .map(|(arm, (p, _))| Row {
+            patterns: vec![p],
+            arm,
+            bindings: Vec::new(),
+        })
+        .collect();
+    tree(&[Vec::new()], rows)
+}
+
+/// The tree for `rows`, whose columns are the subterms at `paths`.
+fn tree(paths: &[Path], rows: Vec<Row>) -> Tree {
+    let Some(first) = rows.first() else {
+        return Tree::Fail;
+    };
+    let Some(i) = first
+        .patterns
+        .iter()
+        .position(|p| matches!(p, Pattern::Con(..)))
+    else {
+        // the first row matches whatever is left
+        let mut row = first.clone();
+        for path in paths {
+            row = row.take(0, path).1;
+        }
+        return Tree::Leaf {
+            arm: row.arm,
+            bindings: row.bindings,
+        };
+    };
+    let path = &paths[i];
+    let mut rest = paths.to_vec();
+    rest.remove(i);
+    let mut constructors: Vec<(&String, usize)> = Vec::new();
+    for row in &rows {
+        if let Pattern::Con(c, args) = row.patterns[i] {
+            if !constructors.iter().any(|(c1, _)| *c1 == c) {
+                constructors.push((c, args.len()));
+            }
+        }
+    }
+    let cases = constructors
+        .into_iter()
+        .map(|(c, n)| {
+            let mut paths: Vec<Path> = (0..n)
+                .map(|j| path.iter().copied().chain([j]).collect())
+                .collect();
+            paths.extend(rest.iter().cloned());
+            let rows = rows
+                .iter()
+                .filter_map(|row| {
+                    let (p, mut row) = row.take(i, path);
+                    let args: Vec<&Pattern> = match p {
+                        Pattern::Con(c1, args) if c1 == c => args.iter().collect(),
+                        Pattern::Con(..) => return None,
+                        Pattern::Var(_) | Pattern::Wildcard => vec![WILDCARD; n],
+                    };
+                    row.patterns.splice(0..0, args);
+                    Some(row)
+                })
+                .collect();
+            (c.clone(), tree(&paths, rows))
+        })
+        .collect();
+    let defaults = rows
+        .iter()
+        .filter(|row| !matches!(row.patterns[i], Pattern::Con(..)))
+        .map(|row| row.take(i, path).1)
+        .collect();
+    Tree::Switch {
+        path: path.clone(),
+        cases,
+        default: Box::new(tree(&rest, defaults)),
+    }
+}
+
+impl Tree {
+    /// Follows the tree as far as the subterms of `e` allow.
+    pub fn select(&self, e: &Expr) -> Select {
+        match self {
+            Tree::Leaf { arm, bindings } => Select::Arm(
+                *arm,
+                bindings
+                    .iter()
+                    .map(|(x, path)| (x.clone(), subterm(e, path).clone()))
+                    .collect(),
+            ),
+            Tree::Switch {
+                path,
+                cases,
+                default,
+            } => match constructor(subterm(e, path)) {
+                Some((c, _)) => cases
+                    .iter()
+                    .find(|(c1, _)| c1 == c)
+                    .map_or(default.as_ref(), |(_, t)| t)
+                    .select(e),
+                None => Select::Force(path.clone()),
+            },
+            Tree::Fail => Select::Fail,
+        }
+    }
+}
+
+/// The constructor `e` is built with and its arguments, if it's a constructor application.
+pub fn constructor(e: &Expr) -> Option<(&String, Vec<&Expr>)> {
+    match e {
+        Expr::Con(c) => Some((c, Vec::new())),
+        Expr::App { e1, e2 } => {
+            let (c, mut args) = constructor(e1)?;
+            args.push(e2);
+            Some((c, args))
+        }
+        _ => None,
+    }
+}
+
+pub fn subterm<'e>(mut e: &'e Expr, path: &[usize]) -> &'e Expr {
+    for &i in path {
+        e = constructor(e)
+            .expect("paths only lead through constructors")
+            .1[i];
+    }
+    e
+}
+
+pub fn subterm_mut<'e>(mut e: &'e mut Expr, path: &[usize]) -> &'e mut Expr {
+    for &i in path {
+        let n = constructor(e).map_or(0, |(_, args)| args.len());
+        for _ in i + 1..n {
+            let Expr::App { e1, .. } = e else {
+                unreachable!("paths only lead through constructors")
+            };
+            e = e1.as_mut();
+        }
+        let Expr::App { e2, .. } = e else {
+            unreachable!("paths only lead through constructors")
+        };
+        e = e2.as_mut();
+    }
+    e
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{parser::Parser, scanner::Scanner, session::Session};
+
+    fn arms(src: &str) -> Vec<(Pattern, Expr)> {
+        match Parser::parse(Scanner::scan(src.to_string()).unwrap()).unwrap() {
+            Expr::Match { arms, .. } => arms,
+            e => panic!("`{}` isn't a match", e),
+        }
+    }
+
+    fn signature(c: &str) -> Vec<(String, usize)> {
+        let tree = vec![("Leaf".to_string(), 0), ("Node".to_string(), 3)];
+        let nat = vec![("Z".to_string(), 0), ("S".to_string(), 1)];
+        match c {
+            "Leaf" | "Node" => tree,
+            _ => nat,
+        }
+    }
+
+    #[test]
+    fn coverage() {
+        let check = |src: &str| {
+            let arms = arms(src);
+            let patterns: Vec<_> = arms.iter().map(|(p, _)| p).collect();
+            (
+                missing(&patterns, &signature).map(|p| p.to_string()),
+                redundant(&patterns, &signature),
+            )
+        };
+        assert_eq!(
+            check("match t with | Leaf -> a | Node l n r -> b"),
+            (None, None)
+        );
+        assert_eq!(
+            check("match t with | Leaf -> a | Node Leaf n r -> b"),
+            (Some("Node (Node _ _ _) _ _".to_string()), None)
+        );
+        assert_eq!(
+            check("match t with | Node _ (S n) _ -> a | Leaf -> b"),
+            (Some("Node _ Z _".to_string()), None)
+        );
+        assert_eq!(check("match t with | x -> a | Leaf -> b"), (None, Some(1)));
+        assert_eq!(
+            check(
+                "match t with | Node l Z r -> a | Node l (S m) r -> b | Node _ _ _ -> c | _ -> d"
+            ),
+            (None, Some(2))
+        );
+    }
+
+    #[test]
+    fn decision_trees() {
+        let arms = arms("match t with | Node Leaf n r -> a | Node l Z Leaf -> b | x -> c");
+        let tree = compile(&arms);
+        let e = |src: &str| Parser::parse(Scanner::scan(src.to_string()).unwrap()).unwrap();
+        assert_eq!(
+            tree.select(&e("Leaf")),
+            Select::Arm(2, vec![("x".into(), e("Leaf"))])
+        );
+        assert_eq!(
+            tree.select(&e("Node Leaf (S Z) Leaf")),
+            Select::Arm(0, vec![("n".into(), e("S Z")), ("r".into(), e("Leaf"))])
+        );
+        // the first argument is tested before the second
+        let mut t = e("Node (f x) (g y) Leaf");
+        assert_eq!(tree.select(&t), Select::Force(vec![0]));
+        *subterm_mut(&mut t, &[0]) = e("Node Leaf Z Leaf");
+        assert_eq!(tree.select(&t), Select::Force(vec![1]));
+        *subterm_mut(&mut t, &[1]) = e("Z");
+        assert_eq!(
+            tree.select(&t),
+            Select::Arm(1, vec![("l".into(), e("Node Leaf Z Leaf"))])
+        );
+    }
+
+    #[test]
+    fn datatypes() {
+        let mut session = Session::with_prelude();
+        assert_eq!(
+            session.eval("data Tree = Leaf | Node Tree Nat Tree"),
+            "Leaf : Tree\nNode : Tree -> Nat -> Tree -> Tree"
+        );
+        session.eval("t = Node (Node Leaf Z Leaf) (S Z) Leaf");
+        assert_eq!(
+            session.eval(
+                "match t with | Leaf -> Z | Node Leaf n _ -> n | Node (Node _ m _) _ _ -> S m"
+            ),
+            "S Z : Nat"
+        );
+        // call-by-name, only the subterms that the patterns look at are evaluated
+        assert_eq!(
+            session.eval(
+                "match Node Leaf Z (head [Tree] ([] : List Tree)) with \
+                 | Node Leaf n _ -> n | _ -> S Z"
+            ),
+            "Z : Nat"
+        );
+
+        let err = |session: &mut Session, line: &str| session.exec(line).unwrap_err().to_string();
+        let missing = err(
+            &mut session,
+            "match t with | Leaf -> Z | Node Leaf n r -> n",
+        );
+        assert!(
+            missing.ends_with("matches `Node (Node _ _ _) _ _`"),
+            "{missing}"
+        );
+        let redundant = err(&mut session, "match t with | _ -> Z | Leaf -> Z");
+        assert!(redundant.contains("unreachable"), "{redundant}");
+        assert!(session.exec("match Z with | Leaf -> Z | _ -> Z").is_err());
+        assert!(session
+            .exec("match t with | Node l n -> n | Leaf -> Z")
+            .is_err());
+        assert!(session.exec("data Tree = Leaf").is_err());
+        assert!(session.exec("data List2 = Nil2 | Cons2 X List2").is_err());
+    }
+}
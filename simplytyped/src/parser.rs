@@ -1,88 +1,263 @@
-use anyhow::{anyhow, bail};
+use std::fmt;
+
+use anyhow::bail;
 
 use crate::scanner::Token;
 
-#[derive(Debug, PartialEq, Clone)]
+#[derive(Debug, PartialEq, Eq, Clone)]
 pub enum Expr {
     /// x
     Var(String),
     /// c
     Constant(Constant),
     /// e1 e2
-    App { e1: Box<Expr>, e2: Box<Expr> },
-    /// \x:t.e
-    Abs { x: String, t: Type, e: Box<Expr> },
+    App {
+        e1: Box<Expr>,
+        e2: Box<Expr>,
+    },
+    /// \x:t.e, or \x.e where the type of `x` is known from context
+    Abs {
+        x: String,
+        t: Option<Type>,
+        e: Box<Expr>,
+    },
+    /// (e : t)
+    Ann {
+        e: Box<Expr>,
+        t: Type,
+    },
     /// if e1 then e2 else e3
     If {
         e1: Box<Expr>,
         e2: Box<Expr>,
         e3: Box<Expr>,
     },
+    /// /\X.e
+    TyAbs {
+        x: String,
+        e: Box<Expr>,
+    },
+    /// e [t]
+    TyApp {
+        e: Box<Expr>,
+        t: Type,
+    },
+    /// ref e
+    Ref(Box<Expr>),
+    /// !e
+    Deref(Box<Expr>),
+    /// e1 := e2
+    Assign {
+        e1: Box<Expr>,
+        e2: Box<Expr>,
+    },
+    /// A location in the store. These only come from evaluating `ref e`.
+    Loc(usize),
+    /// fold [t] e
+    Fold {
+        t: Type,
+        e: Box<Expr>,
+    },
+    /// unfold [t] e
+    Unfold {
+        t: Type,
+        e: Box<Expr>,
+    },
+    /// raise e
+    Raise(Box<Expr>),
+    /// try e1 with e2
+    Try {
+        e1: Box<Expr>,
+        e2: Box<Expr>,
+    },
+    /// [e1, e2, e3]
+    List(Vec<Expr>),
+    Prim(Prim),
+    /// A constructor of a `data` type. Names that start with a capital letter are constructors.
+    Con(String),
+    /// match e with | p1 -> e1 | p2 -> e2
+    Match {
+        e: Box<Expr>,
+        arms: Vec<(Pattern, Expr)>,
+    },
+    /// _ or ?name, a part of the term still to be written
+    Hole(Option<String>),
+}
+
+/// What an arm of a `match` matches.
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub enum Pattern {
+    /// x, which matches anything and binds it to `x`
+    Var(String),
+    /// _
+    Wildcard,
+    /// C p1 p2
+    Con(String, Vec<Pattern>),
+}
+
+impl Pattern {
+    /// The variables `self` binds, from left to right.
+    pub fn vars(&self) -> Vec<&String> {
+        match self {
+            Pattern::Var(x) => vec![x],
+            Pattern::Wildcard => Vec::new(),
+            Pattern::Con(_, ps) => ps.iter().flat_map(Pattern::vars).collect(),
+        }
+    }
+
+    /// Renames the variable `x` to `y`.
+    pub fn rename(&self, x: &str, y: &str) -> Pattern {
+        match self {
+            Pattern::Var(z) if z == x => Pattern::Var(y.to_string()),
+            Pattern::Var(_) | Pattern::Wildcard => self.clone(),
+            Pattern::Con(c, ps) => {
+                Pattern::Con(c.clone(), ps.iter().map(|p| p.rename(x, y)).collect())
+            }
+        }
+    }
+}
+
+/// A line of the REPL or the prelude
+#[derive(Debug, PartialEq)]
+pub enum Statement {
+    /// x = e
+    Def {
+        x: String,
+        e: Expr,
+    },
+    Expr(Expr),
+    /// data T = C1 T1 T2 | C2
+    Data {
+        name: String,
+        constructors: Vec<(String, Vec<Type>)>,
+    },
 }
 
-#[derive(Debug, PartialEq, Clone)]
+#[derive(Debug, PartialEq, Eq, Clone)]
 pub enum Constant {
     True,
     False,
+    Unit,
+    String(String),
+    Char(char),
 }
 
-#[derive(Debug, PartialEq, Eq, Clone)]
-pub enum Type {
-    Bool,
-    Fn(Box<Type>, Box<Type>),
+/// The built-in operations on lists and strings, which take their arguments one at a time like
+/// functions.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum Prim {
+    Nil,
+    Cons,
+    IsNil,
+    Head,
+    Tail,
+    Foldr,
+    Concat,
+    Length,
+    Eq,
+    CharAt,
 }
 
-impl ToString for Type {
-    fn to_string(&self) -> String {
+impl Prim {
+    /// How many terms it's applied to before it computes, not counting types.
+    pub fn arity(self) -> usize {
         match self {
-            Type::Bool => "Bool".to_string(),
-            Type::Fn(a, b) => {
-                if let Type::Fn(_, _) = **a {
-                    format!("({}) -> {}", a.to_string(), b.to_string())
-                } else {
-                    format!("{} -> {}", a.to_string(), b.to_string())
-                }
-            }
+            Prim::Nil => 0,
+            Prim::IsNil | Prim::Head | Prim::Tail | Prim::Length => 1,
+            Prim::Cons | Prim::Concat | Prim::Eq | Prim::CharAt => 2,
+            Prim::Foldr => 3,
+        }
+    }
+
+    /// The arguments it evaluates before it computes, even call-by-name: the list it takes
+    /// apart, or the strings.
+    pub fn strict(self) -> &'static [usize] {
+        match self {
+            Prim::Nil => &[],
+            Prim::IsNil | Prim::Head | Prim::Tail | Prim::Length | Prim::CharAt => &[0],
+            Prim::Cons => &[1],
+            Prim::Foldr => &[2],
+            Prim::Concat | Prim::Eq => &[0, 1],
         }
     }
 }
 
-impl ToString for Expr {
-    fn to_string(&self) -> String {
-        let maybe_parenthesize = |e: &Expr| {
-            if matches!(*e, Expr::Var(_) | Expr::Constant(_)) {
-                e.to_string()
-            } else {
-                format!("({})", e.to_string())
-            }
-        };
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub enum Type {
+    Bool,
+    Unit,
+    /// Ref t
+    Ref(Box<Type>),
+    Fn(Box<Type>, Box<Type>),
+    /// X
+    Var(String),
+    /// forall X. t
+    Forall(String, Box<Type>),
+    /// mu X. t
+    Mu(String, Box<Type>),
+    /// List t
+    List(Box<Type>),
+    String,
+    Char,
+}
+
+/// Why a line of tokens isn't a term, a definition or a `data` declaration.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ParseError {
+    /// `found` is where `expected` should be, or the line ended there if it's `None`
+    Unexpected {
+        expected: String,
+        found: Option<Token>,
+    },
+    /// a variable that starts with a capital letter
+    CapitalizedName { x: String },
+    /// a constructor that doesn't
+    LowercaseConstructor { c: String },
+    /// a token after a whole term
+    Trailing { found: Token },
+}
 
+impl ParseError {
+    /// The stable code that `--explain` looks up.
+    pub fn code(&self) -> &'static str {
         match self {
-            Expr::Var(x) => x.to_string(),
-            Expr::Constant(c) => c.to_string(),
-            Expr::App { e1, e2 } => {
-                format!("{} {}", maybe_parenthesize(e1), maybe_parenthesize(e2))
-            }
-            Expr::Abs { x, t, e } => format!("\\{}: {}.{}", x, t.to_string(), e.to_string()),
-            Expr::If { e1, e2, e3 } => format!(
-                "if {} then {} else {}",
-                e1.to_string(),
-                e2.to_string(),
-                e3.to_string()
-            ),
+            ParseError::Unexpected { found: Some(_), .. } => "E0101",
+            ParseError::Unexpected { found: None, .. } => "E0102",
+            ParseError::CapitalizedName { .. } => "E0103",
+            ParseError::LowercaseConstructor { .. } => "E0104",
+            ParseError::Trailing { .. } => "E0105",
         }
     }
 }
 
-impl ToString for Constant {
-    fn to_string(&self) -> String {
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
-            Constant::True => "true".to_string(),
-            Constant::False => "false".to_string(),
+            ParseError::Unexpected {
+                expected,
+                found: Some(found),
+            } => write!(f, "expected {}, found `{}`", expected, found),
+            ParseError::Unexpected {
+                expected,
+                found: None,
+            } => write!(f, "expected {}, but the line ended", expected),
+            ParseError::CapitalizedName { x } => write!(
+                f,
+                "`{}` starts with a capital letter, so it can only name a constructor",
+                x
+            ),
+            ParseError::LowercaseConstructor { c } => {
+                write!(f, "constructor `{}` must start with a capital letter", c)
+            }
+            ParseError::Trailing { found } => {
+                write!(f, "expected the end of the line, found `{}`", found)
+            }
         }
     }
 }
 
+impl std::error::Error for ParseError {}
+
 pub struct Parser {
     stream: Vec<Token>,
     pos: usize,
@@ -91,15 +266,66 @@ pub struct Parser {
 impl Parser {
     pub fn parse(stream: Vec<Token>) -> anyhow::Result<Expr> {
         let mut parser = Self { stream, pos: 0 };
-        parser.expr()
+        let e = parser.expr()?;
+        if let Some(found) = parser.peek().cloned() {
+            bail!(ParseError::Trailing { found });
+        }
+        Ok(e)
+    }
+
+    pub fn parse_statement(stream: Vec<Token>) -> anyhow::Result<Statement> {
+        if let [Token::Data, ..] = stream.as_slice() {
+            return Self { stream, pos: 0 }.data();
+        }
+        if let [Token::Identifier(x), Token::Equal, ..] = stream.as_slice() {
+            let x = x.clone();
+            if is_constructor(&x) {
+                bail!(ParseError::CapitalizedName { x });
+            }
+            let e = Self::parse(stream.into_iter().skip(2).collect())?;
+            return Ok(Statement::Def { x, e });
+        }
+        Ok(Statement::Expr(Self::parse(stream)?))
+    }
+
+    fn data(&mut self) -> anyhow::Result<Statement> {
+        self.consume(&Token::Data)?;
+        let name = self.consume_ident()?;
+        self.consume(&Token::Equal)?;
+        let mut constructors = Vec::new();
+        loop {
+            let c = self.consume_ident()?;
+            if !is_constructor(&c) {
+                bail!(ParseError::LowercaseConstructor { c });
+            }
+            let mut args = Vec::new();
+            while !matches!(self.peek(), None | Some(Token::Pipe)) {
+                args.push(self.ty_atom()?);
+            }
+            constructors.push((c, args));
+            if self.consume(&Token::Pipe).is_err() {
+                return Ok(Statement::Data { name, constructors });
+            }
+        }
     }
 
     fn expr(&mut self) -> anyhow::Result<Expr> {
-        self.do_expr(true)
+        let e1 = self.do_expr(true)?;
+        if self.consume(&Token::ColonEqual).is_ok() {
+            let e2 = self.expr()?;
+            return Ok(Expr::Assign {
+                e1: Box::new(e1),
+                e2: Box::new(e2),
+            });
+        }
+        Ok(e1)
     }
 
     fn do_expr(&mut self, recurse_app: bool) -> anyhow::Result<Expr> {
-        match self.peek().ok_or_else(|| anyhow!("empty stream"))? {
+        let Some(t) = self.peek() else {
+            bail!(self.unexpected("an expression"));
+        };
+        match t {
             Token::Identifier(_) => {
                 let e1 = self.var()?;
                 if recurse_app {
@@ -109,10 +335,29 @@ impl Parser {
                 }
             }
             Token::Lambda => self.abstraction(),
+            Token::TyLambda => self.ty_abstraction(),
             Token::If => self.if_then_else(),
+            Token::Raise => {
+                self.consume(&Token::Raise)?;
+                Ok(Expr::Raise(Box::new(self.expr()?)))
+            }
+            Token::Try => {
+                self.consume(&Token::Try)?;
+                let e1 = self.expr()?;
+                self.consume(&Token::With)?;
+                let e2 = self.expr()?;
+                Ok(Expr::Try {
+                    e1: Box::new(e1),
+                    e2: Box::new(e2),
+                })
+            }
             Token::LeftParen => {
                 self.consume(&Token::LeftParen)?;
-                let e = self.expr()?;
+                let mut e = self.expr()?;
+                if self.consume(&Token::Colon).is_ok() {
+                    let t = self.ty()?;
+                    e = Expr::Ann { e: Box::new(e), t };
+                }
                 self.consume(&Token::RightParen)?;
                 if recurse_app {
                     self.maybe_app(e)
@@ -128,6 +373,111 @@ impl Parser {
                 self.consume(&Token::False)?;
                 Ok(Expr::Constant(Constant::False))
             }
+            Token::UnitValue => {
+                self.consume(&Token::UnitValue)?;
+                Ok(Expr::Constant(Constant::Unit))
+            }
+            Token::StringLiteral(text) => {
+                let text = text.clone();
+                self.pos += 1;
+                Ok(Expr::Constant(Constant::String(text)))
+            }
+            Token::CharLiteral(c) => {
+                let c = *c;
+                self.pos += 1;
+                Ok(Expr::Constant(Constant::Char(c)))
+            }
+            Token::Ref | Token::Bang => {
+                let e = if self.consume(&Token::Ref).is_ok() {
+                    Expr::Ref(Box::new(self.do_expr(false)?))
+                } else {
+                    self.consume(&Token::Bang)?;
+                    Expr::Deref(Box::new(self.do_expr(false)?))
+                };
+                if recurse_app {
+                    self.maybe_app(e)
+                } else {
+                    Ok(e)
+                }
+            }
+            Token::LeftBracket => {
+                self.consume(&Token::LeftBracket)?;
+                let mut es = Vec::new();
+                if self.consume(&Token::RightBracket).is_err() {
+                    es.push(self.expr()?);
+                    while self.consume(&Token::Comma).is_ok() {
+                        es.push(self.expr()?);
+                    }
+                    self.consume(&Token::RightBracket)?;
+                }
+                if recurse_app {
+                    self.maybe_app(Expr::List(es))
+                } else {
+                    Ok(Expr::List(es))
+                }
+            }
+            Token::Nil
+            | Token::Cons
+            | Token::IsNil
+            | Token::Head
+            | Token::Tail
+            | Token::Foldr
+            | Token::Concat
+            | Token::Length
+            | Token::Eq
+            | Token::CharAt => {
+                let prim = match self.stream[self.pos] {
+                    Token::Nil => Prim::Nil,
+                    Token::Cons => Prim::Cons,
+                    Token::IsNil => Prim::IsNil,
+                    Token::Head => Prim::Head,
+                    Token::Tail => Prim::Tail,
+                    Token::Concat => Prim::Concat,
+                    Token::Length => Prim::Length,
+                    Token::Eq => Prim::Eq,
+                    Token::CharAt => Prim::CharAt,
+                    _ => Prim::Foldr,
+                };
+                self.pos += 1;
+                if recurse_app {
+                    self.maybe_app(Expr::Prim(prim))
+                } else {
+                    Ok(Expr::Prim(prim))
+                }
+            }
+            Token::Underscore | Token::Hole(_) => {
+                let e = match &self.stream[self.pos] {
+                    Token::Hole(name) => Expr::Hole(Some(name.clone())),
+                    _ => Expr::Hole(None),
+                };
+                self.pos += 1;
+                if recurse_app {
+                    self.maybe_app(e)
+                } else {
+                    Ok(e)
+                }
+            }
+            Token::Match => self.match_arms(),
+            Token::Fold | Token::Unfold => {
+                let fold = self.consume(&Token::Fold).is_ok();
+                if !fold {
+                    self.consume(&Token::Unfold)?;
+                }
+                self.consume(&Token::LeftBracket)?;
+                let t = self.ty()?;
+                self.consume(&Token::RightBracket)?;
+                let e = Box::new(self.do_expr(false)?);
+                let e = if fold {
+                    Expr::Fold { t, e }
+                } else {
+                    Expr::Unfold { t, e }
+                };
+                if recurse_app {
+                    self.maybe_app(e)
+                } else {
+                    Ok(e)
+                }
+            }
 
             t @ (Token::Then
             | Token::Else
@@ -136,8 +486,24 @@ impl Parser {
             | Token::Dot
             | Token::Arrow
             | Token::Bool
-            | Token::RightParen) => {
-                bail!("unexpected token {:?}", t)
+            | Token::RightParen
+            | Token::RightBracket
+            | Token::Forall
+            | Token::RefType
+            | Token::Unit
+            | Token::ColonEqual
+            | Token::Mu
+            | Token::With
+            | Token::Comma
+            | Token::ListType
+            | Token::Data
+            | Token::Pipe
+            | Token::StringType
+            | Token::CharType) => {
+                bail!(ParseError::Unexpected {
+                    expected: "an expression".to_string(),
+                    found: Some(t.clone()),
+                })
             }
         }
     }
@@ -158,10 +524,96 @@ impl Parser {
 
     fn var(&mut self) -> anyhow::Result<Expr> {
         let ident = self.consume_ident()?;
-        Ok(Expr::Var(ident))
+        if is_constructor(&ident) {
+            Ok(Expr::Con(ident))
+        } else {
+            Ok(Expr::Var(ident))
+        }
+    }
+
+    /// Parses `match e with | p1 -> e1 | p2 -> e2`. The `|` before the first arm is optional,
+    /// and the last arm extends as far right as it can.
+    fn match_arms(&mut self) -> anyhow::Result<Expr> {
+        self.consume(&Token::Match)?;
+        let e = self.expr()?;
+        self.consume(&Token::With)?;
+        let _ = self.consume(&Token::Pipe);
+        let mut arms = Vec::new();
+        loop {
+            let p = self.pattern()?;
+            self.consume(&Token::Arrow)?;
+            arms.push((p, self.expr()?));
+            if self.consume(&Token::Pipe).is_err() {
+                return Ok(Expr::Match {
+                    e: Box::new(e),
+                    arms,
+                });
+            }
+        }
+    }
+
+    fn pattern(&mut self) -> anyhow::Result<Pattern> {
+        match self.peek() {
+            Some(Token::Identifier(c)) if is_constructor(c) => {
+                let c = self.consume_ident()?;
+                let mut ps = Vec::new();
+                while matches!(
+                    self.peek(),
+                    Some(Token::Identifier(_) | Token::Underscore | Token::LeftParen)
+                ) {
+                    ps.push(self.pattern_atom()?);
+                }
+                Ok(Pattern::Con(c, ps))
+            }
+            _ => self.pattern_atom(),
+        }
     }
 
+    /// A pattern that doesn't need parentheses to be an argument of a constructor.
+    fn pattern_atom(&mut self) -> anyhow::Result<Pattern> {
+        Ok(match self.peek() {
+            Some(Token::Underscore) => {
+                self.consume(&Token::Underscore)?;
+                Pattern::Wildcard
+            }
+            Some(Token::Identifier(_)) => {
+                let x = self.consume_ident()?;
+                if is_constructor(&x) {
+                    Pattern::Con(x, Vec::new())
+                } else {
+                    Pattern::Var(x)
+                }
+            }
+            Some(Token::LeftParen) => {
+                self.consume(&Token::LeftParen)?;
+                let p = self.pattern()?;
+                self.consume(&Token::RightParen)?;
+                p
+            }
+            peek => bail!(ParseError::Unexpected {
+                expected: "a pattern".to_string(),
+                found: peek.cloned(),
+            }),
+        })
+    }
+
+    /// Parses the arguments after `e1`. `f [x]` applies `f` to the type `x`; brackets that
+    /// don't hold a type are a list literal.
     fn maybe_app(&mut self, e1: Expr) -> anyhow::Result<Expr> {
+        let start = self.pos;
+        if self.consume(&Token::LeftBracket).is_ok() {
+            if let Ok(t) = self.ty() {
+                if self.consume(&Token::RightBracket).is_ok() {
+                    return self.maybe_app(Expr::TyApp { e: Box::new(e1), t });
+                }
+            }
+            self.pos = start;
+            let e2 = self.do_expr(false)?;
+            return self.maybe_app(Expr::App {
+                e1: Box::new(e1),
+                e2: Box::new(e2),
+            });
+        }
         if let Ok(e2) = self.do_expr(false) {
             self.maybe_app(Expr::App {
                 e1: Box::new(e1),
@@ -174,24 +626,58 @@ impl Parser {
 
     fn abstraction(&mut self) -> anyhow::Result<Expr> {
         self.consume(&Token::Lambda)?;
-        let x = self.var()?;
-        self.consume(&Token::Colon)?;
-        let t = self.ty()?;
+        let x = self.consume_ident()?;
+        if is_constructor(&x) {
+            bail!(ParseError::CapitalizedName { x });
+        }
+        let t = if self.consume(&Token::Colon).is_ok() {
+            Some(self.ty()?)
+        } else {
+            None
+        };
         self.consume(&Token::Dot)?;
         let e = self.expr()?;
-        if let Expr::Var(x) = x {
-            Ok(Expr::Abs {
-                x,
-                t,
-                e: Box::new(e),
-            })
-        } else {
-            unreachable!()
-        }
+        Ok(Expr::Abs {
+            x,
+            t,
+            e: Box::new(e),
+        })
+    }
+
+    fn ty_abstraction(&mut self) -> anyhow::Result<Expr> {
+        self.consume(&Token::TyLambda)?;
+        let x = self.consume_ident()?;
+        self.consume(&Token::Dot)?;
+        let e = self.expr()?;
+        Ok(Expr::TyAbs { x, e: Box::new(e) })
     }
 
     fn ty(&mut self) -> anyhow::Result<Type> {
-        let t1 = match self.peek() {
+        if self.consume(&Token::Forall).is_ok() {
+            let x = self.consume_ident()?;
+            self.consume(&Token::Dot)?;
+            return Ok(Type::Forall(x, Box::new(self.ty()?)));
+        }
+        if self.consume(&Token::Mu).is_ok() {
+            let x = self.consume_ident()?;
+            self.consume(&Token::Dot)?;
+            return Ok(Type::Mu(x, Box::new(self.ty()?)));
+        }
+        let t1 = self.ty_atom()?;
+        match self.peek() {
+            Some(Token::Arrow) => {
+                self.consume(&Token::Arrow)?;
+                let t2 = self.ty()?;
+                Ok(Type::Fn(Box::new(t1), Box::new(t2)))
+            }
+            _ => Ok(t1),
+        }
+    }
+
+    /// A type that doesn't need parentheses to be an argument of `Ref` or `List`.
+    fn ty_atom(&mut self) -> anyhow::Result<Type> {
+        Ok(match self.peek() {
+            Some(Token::Identifier(_)) => Type::Var(self.consume_ident()?),
             Some(Token::LeftParen) => {
                 self.consume(&Token::LeftParen)?;
                 let t = self.ty()?;
@@ -202,16 +688,31 @@ impl Parser {
                 self.consume(&Token::Bool)?;
                 Type::Bool
             }
-            peek => bail!("expected type, got `{:?}`", peek),
-        };
-        match self.peek() {
-            Some(Token::Arrow) => {
-                self.consume(&Token::Arrow)?;
-                let t2 = self.ty()?;
-                Ok(Type::Fn(Box::new(t1), Box::new(t2)))
+            Some(Token::Unit) => {
+                self.consume(&Token::Unit)?;
+                Type::Unit
             }
-            _ => Ok(t1),
-        }
+            Some(Token::StringType) => {
+                self.consume(&Token::StringType)?;
+                Type::String
+            }
+            Some(Token::CharType) => {
+                self.consume(&Token::CharType)?;
+                Type::Char
+            }
+            Some(Token::RefType) => {
+                self.consume(&Token::RefType)?;
+                Type::Ref(Box::new(self.ty_atom()?))
+            }
+            Some(Token::ListType) => {
+                self.consume(&Token::ListType)?;
+                Type::List(Box::new(self.ty_atom()?))
+            }
+            peek => bail!(ParseError::Unexpected {
+                expected: "a type".to_string(),
+                found: peek.cloned(),
+            }),
+        })
     }
 
     fn peek(&mut self) -> Option<&Token> {
@@ -223,27 +724,38 @@ impl Parser {
     }
 
     fn consume(&mut self, tok: &Token) -> anyhow::Result<Option<&Token>> {
-        let peek = self.peek();
-        if peek == Some(tok) {
+        if self.peek() == Some(tok) {
             self.pos += 1;
             Ok(self.peek())
         } else {
-            bail!("expected {:?}, found {:?}", tok, peek)
+            bail!(self.unexpected(format!("`{}`", tok)))
         }
     }
 
     fn consume_ident(&mut self) -> anyhow::Result<String> {
-        let peek = self.peek();
-        if let Some(Token::Identifier(ident)) = peek {
+        if let Some(Token::Identifier(ident)) = self.peek() {
             let ident = ident.clone();
             self.pos += 1;
             Ok(ident)
         } else {
-            bail!("exptected identifier, found {:?}", peek)
+            bail!(self.unexpected("an identifier"))
+        }
+    }
+
+    /// The error for a next token that isn't `expected`.
+    fn unexpected(&mut self, expected: impl Into<String>) -> ParseError {
+        ParseError::Unexpected {
+            expected: expected.into(),
+            found: self.peek().cloned(),
         }
     }
 }
 
+/// Whether `x` names a constructor rather than a variable.
+pub fn is_constructor(x: &str) -> bool {
+    x.starts_with(|c: char| c.is_uppercase())
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -267,11 +779,80 @@ mod tests {
             Expr::App {
                 e1: Box::new(Expr::Abs {
                     x: "x".into(),
-                    t: Type::Bool,
+                    t: Some(Type::Bool),
                     e: Box::new(Expr::Var("x".into()))
                 }),
                 e2: Box::new(Expr::Constant(Constant::True))
             }
         );
     }
+
+    #[test]
+    fn data() {
+        let parse =
+            |s: &str| Parser::parse_statement(crate::scanner::Scanner::scan(s.into()).unwrap());
+        let var = |x: &str| Pattern::Var(x.into());
+        assert_eq!(
+            parse("data T = A | B (Bool -> Bool) T").unwrap(),
+            Statement::Data {
+                name: "T".into(),
+                constructors: vec![
+                    ("A".into(), vec![]),
+                    (
+                        "B".into(),
+                        vec![
+                            Type::Fn(Box::new(Type::Bool), Box::new(Type::Bool)),
+                            Type::Var("T".into())
+                        ]
+                    ),
+                ],
+            }
+        );
+        assert_eq!(
+            parse("match x with B f (B _ y) -> f | A -> A").unwrap(),
+            Statement::Expr(Expr::Match {
+                e: Box::new(Expr::Var("x".into())),
+                arms: vec![
+                    (
+                        Pattern::Con(
+                            "B".into(),
+                            vec![
+                                var("f"),
+                                Pattern::Con("B".into(), vec![Pattern::Wildcard, var("y")])
+                            ]
+                        ),
+                        Expr::Var("f".into())
+                    ),
+                    (Pattern::Con("A".into(), vec![]), Expr::Con("A".into())),
+                ],
+            })
+        );
+        assert!(parse("data T = a").is_err());
+        assert!(parse("\\X. X").is_err());
+    }
+    #[test]
+    fn trailing_tokens() {
+        let error = |s: &str| {
+            Parser::parse(crate::scanner::Scanner::scan(s.into()).unwrap())
+                .unwrap_err()
+                .downcast::<ParseError>()
+                .unwrap()
+        };
+        assert_eq!(
+            error("true )"),
+            ParseError::Trailing {
+                found: Token::RightParen
+            }
+        );
+        assert_eq!(
+            error("true (false"),
+            ParseError::Trailing {
+                found: Token::LeftParen
+            }
+        );
+        assert_eq!(
+            error("(\\x: Bool. x) true else"),
+            ParseError::Trailing { found: Token::Else }
+        );
+    }
 }
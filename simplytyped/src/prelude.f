@@ -0,0 +1,83 @@
+# Church encodings, loaded into every session.
+# A value is represented by what it does when eliminated, so each type is polymorphic in the
+# result of the elimination. Indented lines continue the definition above them.
+#
+# Scott encodings follow them. A Scott value is eliminated only one step at a time, so its
+# type mentions itself, as in `mu N. forall R. R -> (N -> R) -> R` for naturals, and it's
+# built with `fold` and taken apart with `unfold`.
+
+# Booleans: forall X. X -> X -> X
+ctrue = /\X. \t: X. \f: X. t
+cfalse = /\X. \t: X. \f: X. f
+cnot = \b: forall X. X -> X -> X. /\X. \t: X. \f: X. b [X] f t
+cand = \a: forall X. X -> X -> X. \b: forall X. X -> X -> X.
+  /\X. \t: X. \f: X. a [X] (b [X] t f) f
+cor = \a: forall X. X -> X -> X. \b: forall X. X -> X -> X.
+  /\X. \t: X. \f: X. a [X] t (b [X] t f)
+
+# Pairs of X and Y: forall R. (X -> Y -> R) -> R
+pair = /\X. /\Y. \x: X. \y: Y. /\R. \k: X -> Y -> R. k x y
+fst = /\X. /\Y. \p: forall R. (X -> Y -> R) -> R. p [X] (\x: X. \y: Y. x)
+snd = /\X. /\Y. \p: forall R. (X -> Y -> R) -> R. p [Y] (\x: X. \y: Y. y)
+
+# Naturals: forall X. (X -> X) -> X -> X
+zero = /\X. \s: X -> X. \z: X. z
+succ = \n: forall X. (X -> X) -> X -> X. /\X. \s: X -> X. \z: X. s (n [X] s z)
+plus = \m: forall X. (X -> X) -> X -> X. \n: forall X. (X -> X) -> X -> X.
+  /\X. \s: X -> X. \z: X. m [X] s (n [X] s z)
+mult = \m: forall X. (X -> X) -> X -> X. \n: forall X. (X -> X) -> X -> X.
+  /\X. \s: X -> X. m [X] (n [X] s)
+iszero = \n: forall X. (X -> X) -> X -> X. n [Bool] (\b: Bool. false) true
+# counts up to n with pairs (i - 1, i), starting from (0, 0)
+pred = \n: forall X. (X -> X) -> X -> X.
+  fst [forall X. (X -> X) -> X -> X] [forall X. (X -> X) -> X -> X]
+    (n [forall R. ((forall X. (X -> X) -> X -> X) -> (forall X. (X -> X) -> X -> X) -> R) -> R]
+      (\p: forall R. ((forall X. (X -> X) -> X -> X) -> (forall X. (X -> X) -> X -> X) -> R) -> R.
+        pair [forall X. (X -> X) -> X -> X] [forall X. (X -> X) -> X -> X]
+          (snd [forall X. (X -> X) -> X -> X] [forall X. (X -> X) -> X -> X] p)
+          (succ (snd [forall X. (X -> X) -> X -> X] [forall X. (X -> X) -> X -> X] p)))
+      (pair [forall X. (X -> X) -> X -> X] [forall X. (X -> X) -> X -> X] zero zero))
+
+# Lists of X: forall R. (X -> R -> R) -> R -> R
+# The `c` keeps them apart from the built-in `nil`, `cons` and `isnil` on `List X`.
+cnil = /\X. /\R. \c: X -> R -> R. \n: R. n
+ccons = /\X. \h: X. \t: forall R. (X -> R -> R) -> R -> R.
+  /\R. \c: X -> R -> R. \n: R. c h (t [R] c n)
+cisnil = /\X. \l: forall R. (X -> R -> R) -> R -> R. l [Bool] (\h: X. \t: Bool. false) true
+
+# Scott booleans and pairs are the same as the Church ones, since they aren't recursive
+strue = ctrue
+sfalse = cfalse
+
+# Scott naturals: mu N. forall R. R -> (N -> R) -> R, the zero case and then the successor
+# case, which gets the predecessor
+szero = fold [mu N. forall R. R -> (N -> R) -> R]
+  (/\R. \z: R. \s: (mu N. forall R. R -> (N -> R) -> R) -> R. z)
+ssucc = \n: mu N. forall R. R -> (N -> R) -> R. fold [mu N. forall R. R -> (N -> R) -> R]
+  (/\R. \z: R. \s: (mu N. forall R. R -> (N -> R) -> R) -> R. s n)
+spred = \n: mu N. forall R. R -> (N -> R) -> R.
+  unfold [mu N. forall R. R -> (N -> R) -> R] n [mu N. forall R. R -> (N -> R) -> R] szero
+    (\m: mu N. forall R. R -> (N -> R) -> R. m)
+siszero = \n: mu N. forall R. R -> (N -> R) -> R.
+  unfold [mu N. forall R. R -> (N -> R) -> R] n [Bool] true
+    (\m: mu N. forall R. R -> (N -> R) -> R. false)
+
+# Scott lists of X: mu L. forall R. R -> (X -> L -> R) -> R
+snil = /\X. fold [mu L. forall R. R -> (X -> L -> R) -> R]
+  (/\R. \n: R. \c: X -> (mu L. forall R. R -> (X -> L -> R) -> R) -> R. n)
+scons = /\X. \h: X. \t: mu L. forall R. R -> (X -> L -> R) -> R.
+  fold [mu L. forall R. R -> (X -> L -> R) -> R]
+    (/\R. \n: R. \c: X -> (mu L. forall R. R -> (X -> L -> R) -> R) -> R. c h t)
+sisnil = /\X. \l: mu L. forall R. R -> (X -> L -> R) -> R.
+  unfold [mu L. forall R. R -> (X -> L -> R) -> R] l [Bool] true
+    (\h: X. \t: mu L. forall R. R -> (X -> L -> R) -> R. false)
+# the head, or `d` if the list is empty
+shead = /\X. \d: X. \l: mu L. forall R. R -> (X -> L -> R) -> R.
+  unfold [mu L. forall R. R -> (X -> L -> R) -> R] l [X] d
+    (\h: X. \t: mu L. forall R. R -> (X -> L -> R) -> R. h)
+stail = /\X. \l: mu L. forall R. R -> (X -> L -> R) -> R.
+  unfold [mu L. forall R. R -> (X -> L -> R) -> R] l [mu L. forall R. R -> (X -> L -> R) -> R] l
+    (\h: X. \t: mu L. forall R. R -> (X -> L -> R) -> R. t)
+
+# Naturals as a `data` type, taken apart with `match`
+data Nat = Z | S Nat
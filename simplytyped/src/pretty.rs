@@ -0,0 +1,564 @@
+use std::fmt;
+
+use crate::{
+    parser::{Constant, Expr, Pattern, Prim, Type},
+    scanner::Token,
+    typer::{Context, Hole},
+};
+
+/// A document in the style of Wadler's "A prettier printer": text with optional line breaks,
+/// where each group is laid out on one line if it fits and broken at every `Line` otherwise.
+#[derive(Debug, Clone)]
+pub enum Doc {
+    Text(String),
+    /// a space, or a newline if the enclosing group doesn't fit
+    Line,
+    Nest(usize, Box<Doc>),
+    Concat(Vec<Doc>),
+    Group(Box<Doc>),
+}
+
+impl Doc {
+    pub fn render(&self, width: usize) -> String {
+        let width = width.min(isize::MAX as usize) as isize;
+        let mut out = String::new();
+        let mut col = 0;
+        let mut stack = vec![(0, false, self)];
+        while let Some((indent, flat, doc)) = stack.pop() {
+            match doc {
+                Doc::Text(s) => {
+                    out.push_str(s);
+                    col += s.chars().count();
+                }
+                Doc::Line if flat => {
+                    out.push(' ');
+                    col += 1;
+                }
+                Doc::Line => {
+                    out.push('\n');
+                    out.push_str(&" ".repeat(indent));
+                    col = indent;
+                }
+                Doc::Nest(i, doc) => stack.push((indent + i, flat, doc)),
+                Doc::Concat(docs) => stack.extend(docs.iter().rev().map(|d| (indent, flat, d))),
+                Doc::Group(doc) => {
+                    let flat = flat || fits(width - col as isize, doc, &stack);
+                    stack.push((indent, flat, doc));
+                }
+            }
+        }
+        out
+    }
+}
+
+/// Whether `doc` laid out flat, and whatever follows it up to the next line break, fits in
+/// `width` columns.
+fn fits(mut width: isize, doc: &Doc, rest: &[(usize, bool, &Doc)]) -> bool {
+    let mut stack = vec![(true, doc)];
+    let mut rest = rest.iter().rev();
+    while width >= 0 {
+        let Some((flat, doc)) = stack
+            .pop()
+            .or_else(|| rest.next().map(|(_, f, d)| (*f, *d)))
+        else {
+            return true;
+        };
+        match doc {
+            Doc::Text(s) => width -= s.chars().count() as isize,
+            Doc::Line if flat => width -= 1,
+            Doc::Line => return true,
+            Doc::Nest(_, doc) | Doc::Group(doc) => stack.push((flat, doc)),
+            Doc::Concat(docs) => stack.extend(docs.iter().rev().map(|d| (flat, d))),
+        }
+    }
+    false
+}
+
+fn text(s: impl Into<String>) -> Doc {
+    Doc::Text(s.into())
+}
+
+fn concat(docs: impl IntoIterator<Item = Doc>) -> Doc {
+    Doc::Concat(docs.into_iter().collect())
+}
+
+fn nest(doc: Doc) -> Doc {
+    Doc::Nest(2, Box::new(doc))
+}
+
+fn group(doc: Doc) -> Doc {
+    Doc::Group(Box::new(doc))
+}
+
+fn parens(doc: Doc) -> Doc {
+    concat([text("("), doc, text(")")])
+}
+
+/// Prints `e` with as few parentheses as the parser allows. Abstractions, conditionals,
+/// assignments, exceptions and matches extend as far right as they can, so they only need
+/// parentheses when something follows them; `tail` says whether anything does.
+pub fn expr(e: &Expr, tail: bool) -> Doc {
+    match e {
+        Expr::Var(x) => text(x),
+        Expr::Hole(None) => text("_"),
+        Expr::Hole(Some(name)) => text(format!("?{}", name)),
+        Expr::Constant(c) => text(c.to_string()),
+        Expr::App { .. } | Expr::TyApp { .. } => {
+            // e1 e2 e3 is (e1 e2) e3, so collect the spine and print the arguments together
+            let mut args = Vec::new();
+            let mut head = e;
+            loop {
+                match head {
+                    Expr::App { e1, e2 } => {
+                        args.push(Ok(e2.as_ref()));
+                        head = e1;
+                    }
+                    Expr::TyApp { e, t } => {
+                        args.push(Err(t));
+                        head = e;
+                    }
+                    _ => break,
+                }
+            }
+            args.reverse();
+            let last = args.len() - 1;
+            let args = args.into_iter().enumerate().map(|(i, arg)| match arg {
+                Ok(arg) => concat([Doc::Line, arg_expr(arg, tail && i == last)]),
+                Err(t) => concat([Doc::Line, text(format!("[{}]", ty(t)))]),
+            });
+            group(concat([arg_expr(head, false), nest(concat(args))]))
+        }
+        Expr::Abs { .. }
+        | Expr::TyAbs { .. }
+        | Expr::If { .. }
+        | Expr::Assign { .. }
+        | Expr::Raise(_)
+        | Expr::Try { .. }
+        | Expr::Match { .. }
+            if !tail =>
+        {
+            parens(expr(e, true))
+        }
+        Expr::Abs { x, t, e } => group(concat([
+            match t {
+                Some(t) => text(format!("\\{}: {}.", x, ty(t))),
+                None => text(format!("\\{}.", x)),
+            },
+            nest(concat([Doc::Line, expr(e, tail)])),
+        ])),
+        Expr::TyAbs { x, e } => group(concat([
+            text(format!("/\\{}.", x)),
+            nest(concat([Doc::Line, expr(e, tail)])),
+        ])),
+        Expr::If { e1, e2, e3 } => group(concat([
+            text("if "),
+            expr(e1, true),
+            text(" then"),
+            nest(concat([Doc::Line, expr(e2, true)])),
+            Doc::Line,
+            text("else"),
+            nest(concat([Doc::Line, expr(e3, tail)])),
+        ])),
+        Expr::Ann { e, t } => parens(concat([expr(e, true), text(format!(" : {}", ty(t)))])),
+        Expr::Ref(e) => concat([text("ref "), arg_expr(e, tail)]),
+        Expr::Deref(e) => concat([text("!"), arg_expr(e, tail)]),
+        Expr::Assign { e1, e2 } => group(concat([
+            expr(e1, false),
+            text(" :="),
+            nest(concat([Doc::Line, expr(e2, tail)])),
+        ])),
+        Expr::Loc(l) => text(format!("<loc {}>", l)),
+        Expr::List(es) => {
+            let mut items = Vec::new();
+            for (i, e) in es.iter().enumerate() {
+                if i > 0 {
+                    items.push(text(","));
+                    items.push(Doc::Line);
+                }
+                items.push(expr(e, true));
+            }
+            group(concat([text("["), nest(concat(items)), text("]")]))
+        }
+        Expr::Prim(p) => text(p.to_string()),
+        Expr::Con(c) => text(c),
+        Expr::Match { e, arms } => {
+            let last = arms.len() - 1;
+            let arms = arms.iter().enumerate().map(|(i, (p, body))| {
+                let body = if i == last {
+                    expr(body, tail)
+                } else if ends_in_match(body) {
+                    parens(expr(body, true))
+                } else {
+                    expr(body, true)
+                };
+                concat([
+                    Doc::Line,
+                    text(format!("| {} ->", p)),
+                    nest(concat([Doc::Line, body])),
+                ])
+            });
+            group(concat([
+                text("match "),
+                expr(e, true),
+                text(" with"),
+                nest(concat(arms)),
+            ]))
+        }
+        Expr::Raise(e) => group(concat([
+            text("raise"),
+            nest(concat([Doc::Line, expr(e, tail)])),
+        ])),
+        Expr::Try { e1, e2 } => group(concat([
+            text("try "),
+            expr(e1, true),
+            text(" with"),
+            nest(concat([Doc::Line, expr(e2, tail)])),
+        ])),
+        Expr::Fold { t, e } => concat([text(format!("fold [{}] ", ty(t))), arg_expr(e, tail)]),
+        Expr::Unfold { t, e } => concat([text(format!("unfold [{}] ", ty(t))), arg_expr(e, tail)]),
+    }
+}
+
+/// An expression in function or argument position.
+fn arg_expr(e: &Expr, tail: bool) -> Doc {
+    match e {
+        Expr::App { .. } | Expr::TyApp { .. } | Expr::Assign { .. } => parens(expr(e, true)),
+        // `f [x]` is a type application
+        Expr::List(es) if matches!(es.as_slice(), [Expr::Var(_) | Expr::Con(_)]) => {
+            parens(expr(e, true))
+        }
+        _ => expr(e, tail),
+    }
+}
+
+/// Whether `e` printed in tail position ends with a `match`, which would take any arms that
+/// follow `e` in an outer `match` as its own.
+fn ends_in_match(e: &Expr) -> bool {
+    // arguments that `arg_expr` puts in parentheses end with `)`
+    let arg = |e: &Expr| {
+        !matches!(
+            e,
+            Expr::App { .. } | Expr::TyApp { .. } | Expr::Assign { .. }
+        ) && ends_in_match(e)
+    };
+    match e {
+        Expr::Match { .. } => true,
+        Expr::Abs { e, .. } | Expr::TyAbs { e, .. } | Expr::Raise(e) => ends_in_match(e),
+        Expr::If { e3: e, .. } | Expr::Assign { e2: e, .. } | Expr::Try { e2: e, .. } => {
+            ends_in_match(e)
+        }
+        Expr::App { e2: e, .. }
+        | Expr::Ref(e)
+        | Expr::Deref(e)
+        | Expr::Fold { e, .. }
+        | Expr::Unfold { e, .. } => arg(e),
+        _ => false,
+    }
+}
+
+/// `->` associates to the right and `forall` and `mu` extend as far right as they can, so only
+/// types on the left of an arrow or under `Ref` or `List` need parentheses.
+pub fn ty(t: &Type) -> String {
+    match t {
+        Type::Bool => "Bool".to_string(),
+        Type::Unit => "Unit".to_string(),
+        Type::String => "String".to_string(),
+        Type::Char => "Char".to_string(),
+        Type::Ref(t) => match **t {
+            Type::Fn(..) | Type::Forall(..) | Type::Mu(..) => format!("Ref ({})", ty(t)),
+            _ => format!("Ref {}", ty(t)),
+        },
+        Type::List(t) => match **t {
+            Type::Fn(..) | Type::Forall(..) | Type::Mu(..) => format!("List ({})", ty(t)),
+            _ => format!("List {}", ty(t)),
+        },
+        Type::Var(x) => x.to_string(),
+        Type::Fn(a, b) => match **a {
+            Type::Fn(..) | Type::Forall(..) | Type::Mu(..) => format!("({}) -> {}", ty(a), ty(b)),
+            _ => format!("{} -> {}", ty(a), ty(b)),
+        },
+        Type::Forall(x, t) => format!("forall {}. {}", x, ty(t)),
+        Type::Mu(x, t) => format!("mu {}. {}", x, ty(t)),
+    }
+}
+
+/// Formats to the formatter's width, 80 columns by default: `format!("{:40}", e)` prints `e`
+/// to fit in 40 columns.
+impl fmt::Display for Expr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", expr(self, true).render(f.width().unwrap_or(80)))
+    }
+}
+
+impl fmt::Display for Type {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", ty(self))
+    }
+}
+
+impl fmt::Display for Constant {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Constant::True => write!(f, "true"),
+            Constant::False => write!(f, "false"),
+            Constant::Unit => write!(f, "unit"),
+            Constant::String(s) => write!(f, "\"{}\"", escape(s, '"')),
+            Constant::Char(c) => write!(f, "'{}'", escape(&c.to_string(), '\'')),
+        }
+    }
+}
+
+/// Writes `s` as it would be in a literal delimited by `quote`.
+fn escape(s: &str, quote: char) -> String {
+    s.chars()
+        .map(|c| match c {
+            '\n' => "\\n".to_string(),
+            '\t' => "\\t".to_string(),
+            '\r' => "\\r".to_string(),
+            '\0' => "\\0".to_string(),
+            '\\' => "\\\\".to_string(),
+            c if c == quote => format!("\\{}", c),
+            c => c.to_string(),
+        })
+        .collect()
+}
+
+/// Constructors applied to arguments are in parentheses when they're arguments themselves.
+impl fmt::Display for Pattern {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Pattern::Var(x) => write!(f, "{}", x),
+            Pattern::Wildcard => write!(f, "_"),
+            Pattern::Con(c, ps) => {
+                write!(f, "{}", c)?;
+                for p in ps {
+                    match p {
+                        Pattern::Con(_, args) if !args.is_empty() => write!(f, " ({})", p)?,
+                        p => write!(f, " {}", p)?,
+                    }
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+impl fmt::Display for Prim {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Prim::Nil => "nil",
+            Prim::Cons => "cons",
+            Prim::IsNil => "isnil",
+            Prim::Head => "head",
+            Prim::Tail => "tail",
+            Prim::Foldr => "foldr",
+            Prim::Concat => "concat",
+            Prim::Length => "length",
+            Prim::Eq => "eq",
+            Prim::CharAt => "char_at",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+/// Tokens as they're written, for parse errors.
+impl fmt::Display for Token {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let text = match self {
+            Token::Identifier(x) => x,
+            Token::Lambda => "\\",
+            Token::Equal => "=",
+            Token::Dot => ".",
+            Token::LeftParen => "(",
+            Token::RightParen => ")",
+            Token::Colon => ":",
+            Token::True => "true",
+            Token::False => "false",
+            Token::Bool => "Bool",
+            Token::If => "if",
+            Token::Then => "then",
+            Token::Else => "else",
+            Token::Arrow => "->",
+            Token::TyLambda => "/\\",
+            Token::LeftBracket => "[",
+            Token::RightBracket => "]",
+            Token::Forall => "forall",
+            Token::Ref => "ref",
+            Token::RefType => "Ref",
+            Token::UnitValue => "unit",
+            Token::Unit => "Unit",
+            Token::Bang => "!",
+            Token::ColonEqual => ":=",
+            Token::Mu => "mu",
+            Token::Fold => "fold",
+            Token::Unfold => "unfold",
+            Token::Raise => "raise",
+            Token::Try => "try",
+            Token::With => "with",
+            Token::Comma => ",",
+            Token::ListType => "List",
+            Token::Nil => "nil",
+            Token::Cons => "cons",
+            Token::IsNil => "isnil",
+            Token::Head => "head",
+            Token::Tail => "tail",
+            Token::Foldr => "foldr",
+            Token::Data => "data",
+            Token::Match => "match",
+            Token::Pipe => "|",
+            Token::Underscore => "_",
+            Token::Hole(x) => return write!(f, "?{}", x),
+            Token::StringType => "String",
+            Token::CharType => "Char",
+            Token::StringLiteral(s) => return write!(f, "\"{}\"", escape(s, '"')),
+            Token::CharLiteral(c) => return write!(f, "'{}'", escape(&c.to_string(), '\'')),
+            Token::Concat => "concat",
+            Token::Length => "length",
+            Token::Eq => "eq",
+            Token::CharAt => "char_at",
+        };
+        write!(f, "{}", text)
+    }
+}
+
+/// One binding per line, outermost first, with those a later binder hides marked shadowed.
+impl fmt::Display for Context {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let bindings: Vec<_> = self.frames().iter().flatten().collect();
+        for (i, (x, t)) in bindings.iter().enumerate() {
+            if i > 0 {
+                writeln!(f)?;
+            }
+            write!(f, "{} : {}", x, t)?;
+            if bindings[i + 1..].iter().any(|(y, _)| y == x) {
+                write!(f, "  (shadowed)")?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// The hole and its type, then the variables in scope there, indented.
+impl fmt::Display for Hole {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let hole = Expr::Hole(self.name.clone());
+        write!(f, "{} : {}", hole, self.ty)?;
+        if !self.checked {
+            write!(
+                f,
+                ", which nothing constrains; ascribe it as `({} : T)`",
+                hole
+            )?;
+        }
+        for line in self.context.to_string().lines() {
+            write!(f, "\n  {}", line)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{parser::Parser, scanner::Scanner};
+
+    fn parse(s: &str) -> Expr {
+        Parser::parse(Scanner::scan(s.to_string()).unwrap()).unwrap()
+    }
+
+    #[test]
+    fn minimal_parens() {
+        let cases = [
+            ("(f x) y", "f x y"),
+            ("f (g x)", "f (g x)"),
+            ("(\\x:bool.x) true", "(\\x: Bool. x) true"),
+            ("f (\\x:bool.x)", "f \\x: Bool. x"),
+            (
+                "\\f:(bool -> bool) -> bool.f",
+                "\\f: (Bool -> Bool) -> Bool. f",
+            ),
+            (
+                "\\f:bool -> (bool -> bool).f",
+                "\\f: Bool -> Bool -> Bool. f",
+            ),
+            ("(if b then f else g) x", "(if b then f else g) x"),
+            ("f (if b then x else y) z", "f (if b then x else y) z"),
+            ("(\\x.x : bool -> bool) y", "(\\x. x : Bool -> Bool) y"),
+            ("f (g [X]) [Y]", "f (g [X]) [Y]"),
+            (
+                "f (r := x) (ref (g x)) !(ref y)",
+                "f (r := x) ref (g x) !ref y",
+            ),
+            ("(\\x. r) := (\\x. x)", "(\\x. r) := \\x. x"),
+            (
+                "\\r: Ref (Bool -> Bool). \\u: Unit. r",
+                "\\r: Ref (Bool -> Bool). \\u: Unit. r",
+            ),
+            (
+                "unfold [mu X. X -> Bool] (fold [mu X. X -> Bool] (f x)) y",
+                "unfold [mu X. X -> Bool] fold [mu X. X -> Bool] (f x) y",
+            ),
+            (
+                "\\l: (mu L. Unit -> L) -> Bool. l",
+                "\\l: (mu L. Unit -> L) -> Bool. l",
+            ),
+            ("f (raise x) (try g x with h)", "f (raise x) try g x with h"),
+            (
+                "try raise x with \\e. raise e",
+                "try raise x with \\e. raise e",
+            ),
+            (
+                "f ([x]) [y, z] [] [List Bool]",
+                "f ([x]) [y, z] [] [List Bool]",
+            ),
+            ("[f x, [y]]", "[f x, [y]]"),
+            ("f ([Z]) [S Z]", "f ([Z]) [S Z]"),
+            (
+                r#"concat "a\"b\\" (char_at "\n" n) '\'' '"'"#,
+                r#"concat "a\"b\\" (char_at "\n" n) '\'' '"'"#,
+            ),
+            (
+                "match t with Leaf -> (match u with _ -> a) | Node (Node _ _ _) n r -> \\x. x",
+                "match t with | Leaf -> (match u with | _ -> a) | Node (Node _ _ _) n r -> \\x. x",
+            ),
+            (
+                "f (match x with S n -> n) (\\y. match y with Z -> \\z. z | _ -> y)",
+                "f (match x with | S n -> n) \\y. match y with | Z -> \\z. z | _ -> y",
+            ),
+            (
+                "match t with | A -> f (\\y. match y with Z -> y) | B -> g (h x) | C -> u",
+                "match t with | A -> (f \\y. match y with | Z -> y) | B -> g (h x) | C -> u",
+            ),
+            ("(/\\X.\\x:X.x) [Bool]", "(/\\X. \\x: X. x) [Bool]"),
+            (
+                "\\f:(forall X. X -> X) -> Bool.f",
+                "\\f: (forall X. X -> X) -> Bool. f",
+            ),
+        ];
+        for (src, printed) in cases {
+            assert_eq!(parse(src).to_string(), printed);
+        }
+    }
+
+    #[test]
+    fn round_trip() {
+        let src = "(\\f: Bool -> Bool. \\x: Bool. if f x then (\\y: Bool. y) (f true) else \
+                   f (if x then false else true)) (\\z: Bool. z) ((\\w: Bool. w) false)";
+        let e = parse(src);
+        for width in [0, 10, 30, 80] {
+            let printed = format!("{:width$}", e);
+            assert_eq!(parse(&printed), e, "{printed}");
+        }
+        assert_eq!(
+            format!(
+                "{:30}",
+                parse("\\x: Bool. if x then (\\y: Bool. y) else (\\y: Bool. x)")
+            ),
+            "\\x: Bool.
+  if x then
+    \\y: Bool. y
+  else
+    \\y: Bool. x"
+        );
+    }
+}
@@ -1,6 +1,6 @@
-use anyhow::anyhow;
+use std::fmt;
 
-#[derive(Debug, PartialEq)]
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub enum Token {
     Identifier(String),
     Lambda,
@@ -16,16 +16,112 @@ pub enum Token {
     Then,
     Else,
     Arrow,
+    /// /\
+    TyLambda,
+    LeftBracket,
+    RightBracket,
+    Forall,
+    /// `ref`
+    Ref,
+    /// `Ref`
+    RefType,
+    /// `unit`
+    UnitValue,
+    /// `Unit`
+    Unit,
+    Bang,
+    /// :=
+    ColonEqual,
+    Mu,
+    Fold,
+    Unfold,
+    Raise,
+    Try,
+    With,
+    Comma,
+    /// `List`
+    ListType,
+    Nil,
+    Cons,
+    IsNil,
+    Head,
+    Tail,
+    Foldr,
+    Data,
+    Match,
+    /// |
+    Pipe,
+    /// _
+    Underscore,
+    /// ?name
+    Hole(String),
+    /// `String`
+    StringType,
+    /// `Char`
+    CharType,
+    /// "text", with its escapes replaced
+    StringLiteral(String),
+    /// 'c'
+    CharLiteral(char),
+    Concat,
+    Length,
+    Eq,
+    CharAt,
 }
 
+/// Why a line couldn't be split into tokens.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ScanError {
+    /// a character that can't start a token, or a `/` or `-` that isn't `/\` or `->`
+    UnexpectedSymbol(char),
+    UnterminatedString,
+    EmptyChar,
+    UnterminatedChar,
+    UnknownEscape(char),
+    /// a `?` with no name after it
+    UnnamedHole,
+}
+
+impl ScanError {
+    /// The stable code that `--explain` looks up.
+    pub fn code(&self) -> &'static str {
+        match self {
+            ScanError::UnexpectedSymbol(_) => "E0001",
+            ScanError::UnterminatedString => "E0002",
+            ScanError::EmptyChar => "E0003",
+            ScanError::UnterminatedChar => "E0004",
+            ScanError::UnknownEscape(_) => "E0005",
+            ScanError::UnnamedHole => "E0006",
+        }
+    }
+}
+
+impl fmt::Display for ScanError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ScanError::UnexpectedSymbol(c) => write!(f, "unexpected symbol `{}`", c),
+            ScanError::UnterminatedString => write!(f, "unterminated string literal"),
+            ScanError::EmptyChar => write!(f, "empty character literal"),
+            ScanError::UnterminatedChar => write!(f, "unterminated character literal"),
+            ScanError::UnknownEscape(c) => write!(f, "unknown escape `\\{}`", c),
+            ScanError::UnnamedHole => write!(f, "expected a name after `?`, as in `?goal`"),
+        }
+    }
+}
+
+impl std::error::Error for ScanError {}
+
 pub struct Scanner {
-    stream: String,
+    stream: Vec<char>,
     pos: usize,
 }
 
 impl Scanner {
-    pub fn scan(stream: String) -> anyhow::Result<Vec<Token>> {
-        let mut scanner = Self { stream, pos: 0 };
+    pub fn scan(stream: String) -> Result<Vec<Token>, ScanError> {
+        let mut scanner = Self {
+            stream: stream.chars().collect(),
+            pos: 0,
+        };
         let mut tokens = Vec::new();
         while let Some(token) = scanner.scan_token() {
             tokens.push(token?);
@@ -33,35 +129,123 @@ impl Scanner {
         Ok(tokens)
     }
 
-    fn scan_token(&mut self) -> Option<anyhow::Result<Token>> {
+    fn scan_token(&mut self) -> Option<Result<Token, ScanError>> {
         match self.advance()? {
             '\\' => Some(Ok(Token::Lambda)),
             '.' => Some(Ok(Token::Dot)),
             '=' => Some(Ok(Token::Equal)),
             '(' => Some(Ok(Token::LeftParen)),
             ')' => Some(Ok(Token::RightParen)),
-            ':' => Some(Ok(Token::Colon)),
+            ':' => {
+                if self.stream.get(self.pos) == Some(&'=') {
+                    self.pos += 1;
+                    Some(Ok(Token::ColonEqual))
+                } else {
+                    Some(Ok(Token::Colon))
+                }
+            }
+            '!' => Some(Ok(Token::Bang)),
+            ',' => Some(Ok(Token::Comma)),
+            '|' => Some(Ok(Token::Pipe)),
+            '_' => Some(Ok(Token::Underscore)),
+            '?' => {
+                let start = self.pos;
+                while matches!(self.stream.get(self.pos), Some(c) if c.is_alphanumeric() || *c == '_')
+                {
+                    self.pos += 1;
+                }
+                if start == self.pos {
+                    return Some(Err(ScanError::UnnamedHole));
+                }
+                Some(Ok(Token::Hole(
+                    self.stream[start..self.pos].iter().collect(),
+                )))
+            }
+            '"' => {
+                let mut text = String::new();
+                loop {
+                    match self.advance() {
+                        Some('"') => return Some(Ok(Token::StringLiteral(text))),
+                        Some('\\') => match self.escape(ScanError::UnterminatedString) {
+                            Ok(c) => text.push(c),
+                            Err(e) => return Some(Err(e)),
+                        },
+                        Some(c) => text.push(c),
+                        None => return Some(Err(ScanError::UnterminatedString)),
+                    }
+                }
+            }
+            '\'' => {
+                let c = match self.advance() {
+                    Some('\\') => self.escape(ScanError::UnterminatedChar),
+                    Some('\'') => Err(ScanError::EmptyChar),
+                    Some(c) => Ok(c),
+                    None => Err(ScanError::UnterminatedChar),
+                };
+                match (c, self.advance()) {
+                    (Ok(c), Some('\'')) => Some(Ok(Token::CharLiteral(c))),
+                    (Ok(_), _) => Some(Err(ScanError::UnterminatedChar)),
+                    (Err(e), _) => Some(Err(e)),
+                }
+            }
+            '[' => Some(Ok(Token::LeftBracket)),
+            ']' => Some(Ok(Token::RightBracket)),
+            '/' => {
+                if let Some('\\') = self.advance() {
+                    Some(Ok(Token::TyLambda))
+                } else {
+                    Some(Err(ScanError::UnexpectedSymbol('/')))
+                }
+            }
             '-' => {
                 if let Some('>') = self.advance() {
                     Some(Ok(Token::Arrow))
                 } else {
-                    Some(Err(anyhow!("unexpected symbol `-`")))
+                    Some(Err(ScanError::UnexpectedSymbol('-')))
                 }
             }
             c => {
                 if c.is_alphabetic() {
                     let start = self.pos - 1;
-                    while matches!(self.advance(), Some(c) if !" .\\():-".contains(c)) {}
+                    while matches!(self.advance(), Some(c) if !c.is_whitespace() && !".\\():-[]/!,|\"?".contains(c))
+                    {
+                    }
                     self.pos -= 1;
-                    let ident = self.stream.get(start..self.pos)?;
-                    let tok = match ident {
+                    let ident: String = self.stream.get(start..self.pos)?.iter().collect();
+                    let tok = match ident.as_str() {
                         "true" => Token::True,
                         "false" => Token::False,
                         "if" => Token::If,
                         "then" => Token::Then,
                         "else" => Token::Else,
-                        "bool" => Token::Bool,
-                        _ => Token::Identifier(ident.to_string()),
+                        "bool" | "Bool" => Token::Bool,
+                        "forall" => Token::Forall,
+                        "ref" => Token::Ref,
+                        "Ref" => Token::RefType,
+                        "unit" => Token::UnitValue,
+                        "Unit" => Token::Unit,
+                        "mu" => Token::Mu,
+                        "fold" => Token::Fold,
+                        "unfold" => Token::Unfold,
+                        "raise" => Token::Raise,
+                        "try" => Token::Try,
+                        "with" => Token::With,
+                        "List" => Token::ListType,
+                        "nil" => Token::Nil,
+                        "cons" => Token::Cons,
+                        "isnil" => Token::IsNil,
+                        "head" => Token::Head,
+                        "tail" => Token::Tail,
+                        "foldr" => Token::Foldr,
+                        "data" => Token::Data,
+                        "match" => Token::Match,
+                        "String" => Token::StringType,
+                        "Char" => Token::CharType,
+                        "concat" => Token::Concat,
+                        "length" => Token::Length,
+                        "eq" => Token::Eq,
+                        "char_at" => Token::CharAt,
+                        _ => Token::Identifier(ident),
                     };
                     Some(Ok(tok))
                 } else if c.is_whitespace() {
@@ -69,21 +253,36 @@ impl Scanner {
                     self.pos -= 1;
                     self.scan_token()
                 } else {
-                    Some(Err(anyhow!("invalid identifier")))
+                    Some(Err(ScanError::UnexpectedSymbol(c)))
                 }
             }
         }
     }
 
+    /// The character that an escape in a literal stands for, after its `\`. `unterminated` is
+    /// the error if the line ends first.
+    fn escape(&mut self, unterminated: ScanError) -> Result<char, ScanError> {
+        match self.advance() {
+            Some('n') => Ok('\n'),
+            Some('t') => Ok('\t'),
+            Some('r') => Ok('\r'),
+            Some('0') => Ok('\0'),
+            Some(c @ ('\\' | '"' | '\'')) => Ok(c),
+            Some(c) => Err(ScanError::UnknownEscape(c)),
+            None => Err(unterminated),
+        }
+    }
+
     fn advance(&mut self) -> Option<char> {
         self.pos += 1;
-        self.stream.chars().nth(self.pos - 1)
+        self.stream.get(self.pos - 1).copied()
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::session::Session;
 
     #[test]
     fn works() {
@@ -104,4 +303,32 @@ mod tests {
             ]
         );
     }
+
+    #[test]
+    fn literals() {
+        let tokens = Scanner::scan(r#"concat "a \"b\"\n" '\'' 'c'"#.to_string()).unwrap();
+        assert_eq!(
+            tokens,
+            vec![
+                Token::Concat,
+                Token::StringLiteral("a \"b\"\n".into()),
+                Token::CharLiteral('\''),
+                Token::CharLiteral('c'),
+            ]
+        );
+        let error = |line: &str| Scanner::scan(line.to_string()).unwrap_err();
+        assert_eq!(error(r#""abc"#), ScanError::UnterminatedString);
+        assert_eq!(error("''"), ScanError::EmptyChar);
+        assert_eq!(error(r#""\q""#), ScanError::UnknownEscape('q'));
+        assert_eq!(error("'\\"), ScanError::UnterminatedChar);
+    }
+
+    #[test]
+    fn escapes_print_back() {
+        let mut session = Session::default();
+        assert_eq!(
+            session.eval(r#"concat "tab\t" "\"quote\"""#),
+            r#""tab\t\"quote\"" : String"#
+        );
+    }
 }
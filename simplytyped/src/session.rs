@@ -0,0 +1,357 @@
+use std::{
+    fmt,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+};
+
+use anyhow::bail;
+
+use crate::{
+    derivation::Derivation,
+    interpreter::{self, EvalLimits},
+    parser::{Expr, Parser, Statement, Type},
+    scanner::Scanner,
+    typer::{Context, Hole, Typer},
+};
+
+/// Church encodings of booleans, pairs, naturals and lists.
+const PRELUDE: &str = include_str!("prelude.f");
+
+/// What running a line of the REPL gives.
+#[derive(Debug)]
+pub enum Outcome {
+    /// the value of an expression, or the name of a definition, and its type
+    Value(Expr, Type),
+    /// the constructors a `data` declaration introduced, and their types
+    Data(Vec<(String, Type)>),
+}
+
+/// Lines that scan, parse and typecheck but that the session still won't run.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SessionError {
+    /// a definition `x = e` where `e` has holes, so there's no value to bind
+    UnfilledHoles { x: String },
+    /// a `data` declaration in untyped mode
+    UntypedData,
+    /// `:derive` in untyped mode
+    UntypedDerivation,
+}
+
+impl SessionError {
+    /// The stable code that `--explain` looks up.
+    pub fn code(&self) -> &'static str {
+        match self {
+            SessionError::UnfilledHoles { .. } => "E0222",
+            SessionError::UntypedData => "E0401",
+            SessionError::UntypedDerivation => "E0402",
+        }
+    }
+}
+
+impl fmt::Display for SessionError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SessionError::UnfilledHoles { x } => {
+                write!(f, "`{}` can't be defined until its holes are filled in", x)
+            }
+            SessionError::UntypedData => write!(
+                f,
+                "untyped terms need no `data` declarations; any constructor can be used"
+            ),
+            SessionError::UntypedDerivation => {
+                write!(f, "untyped terms have no typing derivations")
+            }
+        }
+    }
+}
+
+impl std::error::Error for SessionError {}
+
+/// The top-level definitions in scope for a REPL session.
+#[derive(Default)]
+pub struct Session {
+    /// definitions closed over the ones before them, oldest first
+    defs: Vec<(String, Expr)>,
+    /// the types of the definitions
+    typer: Typer,
+    /// the values of the references allocated so far
+    store: Vec<Expr>,
+    /// where the last line failed to typecheck, if it did
+    error_context: Option<Context>,
+    /// the holes in the last line
+    holes: Vec<Hole>,
+    pub limits: EvalLimits,
+    /// evaluate call-by-value rather than call-by-name
+    pub by_value: bool,
+    /// cancels the running evaluation when set; cleared at the start of each line
+    pub interrupt: Arc<AtomicBool>,
+}
+
+impl Session {
+    /// A session with the prelude loaded.
+    pub fn with_prelude() -> Self {
+        let mut session = Self::default();
+        let mut defs: Vec<String> = Vec::new();
+        for line in PRELUDE.lines() {
+            if line.trim().is_empty() || line.starts_with('#') {
+                continue;
+            }
+            match defs.last_mut() {
+                Some(def) if line.starts_with(' ') => def.push_str(line),
+                _ => defs.push(line.to_string()),
+            }
+        }
+        for def in defs {
+            session.exec(&def).expect("prelude should load");
+        }
+        session
+    }
+
+    /// Typechecks and runs a line. A definition `x = e` evaluates `e` once, so that
+    /// `r = ref true` names a single location, and then evaluates to `x`.
+    pub fn exec(&mut self, line: &str) -> anyhow::Result<Outcome> {
+        self.interrupt.store(false, Ordering::Relaxed);
+        self.error_context = None;
+        self.holes.clear();
+        match Parser::parse_statement(Scanner::scan(line.to_string())?)? {
+            Statement::Def { x, e } => {
+                let ty = self.typecheck(&e)?;
+                if !self.holes.is_empty() {
+                    bail!(SessionError::UnfilledHoles { x });
+                }
+                let value = self.interpret(&e)?;
+                self.typer.define(&x, ty.clone());
+                self.defs.push((x.clone(), value));
+                Ok(Outcome::Value(Expr::Var(x), ty))
+            }
+            Statement::Expr(e) => {
+                let ty = self.typecheck(&e)?;
+                Ok(Outcome::Value(self.interpret(&e)?, ty))
+            }
+            Statement::Data { name, constructors } => {
+                Ok(Outcome::Data(self.typer.declare(&name, &constructors)?))
+            }
+        }
+    }
+
+    /// Typechecks `e` against the definitions, keeping its holes and the context of any type
+    /// error.
+    fn typecheck(&mut self, e: &Expr) -> anyhow::Result<Type> {
+        let mut typer = self.typer.clone();
+        let result = typer.typecheck(e);
+        if result.is_err() {
+            self.error_context = Some(typer.error_context.unwrap_or_default());
+        }
+        self.holes = typer.holes;
+        result
+    }
+
+    /// The holes in the last line, with the types they need.
+    pub fn holes(&self) -> &[Hole] {
+        &self.holes
+    }
+
+    /// The variables in scope where the last line failed to typecheck, or `None` if it didn't.
+    pub fn error_context(&self) -> Option<&Context> {
+        self.error_context.as_ref()
+    }
+
+    fn interpret(&mut self, e: &Expr) -> anyhow::Result<Expr> {
+        Ok(interpreter::interpret(
+            &self.close(e),
+            self.by_value,
+            &self.limits,
+            &self.interrupt,
+            &mut self.store,
+        )?)
+    }
+
+    /// The value at each location, in order of allocation.
+    pub fn store(&self) -> &[Expr] {
+        &self.store
+    }
+
+    /// Runs a line without typechecking it, reducing expressions to normal form. A definition
+    /// `x = e` evaluates to `x`.
+    pub fn exec_untyped(&mut self, line: &str) -> anyhow::Result<Expr> {
+        self.interrupt.store(false, Ordering::Relaxed);
+        match Parser::parse_statement(Scanner::scan(line.to_string())?)? {
+            Statement::Def { x, e } => {
+                let e = self.close(&e);
+                self.defs.push((x.clone(), e));
+                Ok(Expr::Var(x))
+            }
+            Statement::Expr(e) => Ok(interpreter::normalize(
+                &self.close(&e),
+                &self.limits,
+                &self.interrupt,
+            )?),
+            Statement::Data { .. } => {
+                bail!(SessionError::UntypedData)
+            }
+        }
+    }
+
+    /// Typechecks an expression, recording the rule used at every step.
+    pub fn derive(&self, line: &str) -> anyhow::Result<Derivation> {
+        let e = Parser::parse(Scanner::scan(line.to_string())?)?;
+        self.typer.clone().derive(&e)
+    }
+
+    /// Substitutes the definitions into `e`. They're closed, so nothing can be captured.
+    fn close(&self, e: &Expr) -> Expr {
+        self.defs
+            .iter()
+            .rev()
+            .fold(e.clone(), |e, (x, def)| interpreter::substitute(&e, x, def))
+    }
+}
+
+#[cfg(test)]
+impl Session {
+    /// Runs a line that should succeed, and shows its outcome as `value : type`, with a line
+    /// per constructor for a `data` declaration.
+    pub fn eval(&mut self, line: &str) -> String {
+        match self.exec(line).unwrap() {
+            Outcome::Value(e, ty) => format!("{} : {}", e, ty),
+            Outcome::Data(constructors) => constructors
+                .iter()
+                .map(|(c, ty)| format!("{} : {}", c, ty))
+                .collect::<Vec<_>>()
+                .join("\n"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::interpreter::EvalError;
+
+    #[test]
+    fn church_booleans() {
+        let mut session = Session::with_prelude();
+        assert_eq!(
+            session.eval("cand ctrue (cnot cfalse) [Bool] true false"),
+            "true : Bool"
+        );
+        assert_eq!(
+            session.eval("cor cfalse cfalse [Bool] true false"),
+            "false : Bool"
+        );
+    }
+
+    #[test]
+    fn church_numerals() {
+        let mut session = Session::with_prelude();
+        session.eval("two = succ (succ zero)");
+        assert_eq!(session.eval("iszero (mult two zero)"), "true : Bool");
+        assert_eq!(session.eval("iszero (plus zero two)"), "false : Bool");
+        assert_eq!(
+            session.eval("cisnil [Bool] (ccons [Bool] true (cnil [Bool]))"),
+            "false : Bool"
+        );
+        assert_eq!(session.eval("cisnil [Bool] (cnil [Bool])"), "true : Bool");
+    }
+
+    #[test]
+    fn scott_encodings() {
+        let mut session = Session::with_prelude();
+        assert_eq!(session.eval("strue [Bool] true false"), "true : Bool");
+        assert_eq!(session.eval("siszero (spred (ssucc szero))"), "true : Bool");
+        assert_eq!(session.eval("siszero (ssucc szero)"), "false : Bool");
+        session.eval("l = scons [Bool] true (scons [Bool] false (snil [Bool]))");
+        assert_eq!(
+            session.eval("shead [Bool] true (stail [Bool] l)"),
+            "false : Bool"
+        );
+        assert_eq!(
+            session.eval("sisnil [Bool] (stail [Bool] (stail [Bool] l))"),
+            "true : Bool"
+        );
+        assert_eq!(
+            session.eval("shead [Bool] true (snil [Bool])"),
+            "true : Bool"
+        );
+    }
+
+    #[test]
+    fn untyped() {
+        let mut session = Session::with_prelude();
+        session.exec_untyped("two = \\f. \\x. f (f x)").unwrap();
+        assert_eq!(
+            session.exec_untyped("two two").unwrap().to_string(),
+            "\\x. \\x1. x (x (x (x x1)))"
+        );
+        assert_eq!(
+            session
+                .exec_untyped("(\\x. x x) (\\x. x x)")
+                .unwrap_err()
+                .downcast_ref(),
+            Some(&EvalError::FuelExhausted { steps: 10_000 })
+        );
+    }
+
+    #[test]
+    fn limits() {
+        let mut session = Session::with_prelude();
+        session.limits.max_term_size = 100;
+        let err = session
+            .exec_untyped("(\\x. x x x) (\\x. x x x)")
+            .unwrap_err();
+        assert_eq!(
+            err.downcast_ref(),
+            Some(&EvalError::TermTooLarge { size: 100 })
+        );
+        session.limits = EvalLimits::default();
+        session.limits.max_depth = 10;
+        let deep = (0..20).fold("x".to_string(), |e, _| format!("\\f. f ({})", e));
+        let err = session.exec_untyped(&format!("\\x. {}", deep)).unwrap_err();
+        assert_eq!(err.downcast_ref(), Some(&EvalError::TooDeep { depth: 10 }));
+        // the session is still usable afterwards
+        session.limits = EvalLimits::default();
+        assert_eq!(session.eval("cnot ctrue [Bool] true false"), "false : Bool");
+    }
+
+    #[test]
+    fn interrupted() {
+        let mut session = Session::default();
+        session.limits.max_steps = u64::MAX;
+        let interrupt = session.interrupt.clone();
+        let done = Arc::new(AtomicBool::new(false));
+        let timer = {
+            let done = done.clone();
+            // keep interrupting, since `exec_untyped` clears the flag when it starts
+            std::thread::spawn(move || {
+                while !done.load(Ordering::Relaxed) {
+                    std::thread::sleep(std::time::Duration::from_millis(10));
+                    interrupt.store(true, Ordering::Relaxed);
+                }
+            })
+        };
+        let err = session.exec_untyped("(\\x. x x) (\\x. x x)").unwrap_err();
+        done.store(true, Ordering::Relaxed);
+        timer.join().unwrap();
+        assert_eq!(err.downcast_ref(), Some(&EvalError::Interrupted));
+        // the flag is cleared for the next line
+        assert!(session.exec_untyped("\\x. x").is_ok());
+    }
+
+    #[test]
+    fn type_application() {
+        let mut session = Session::default();
+        assert_eq!(
+            session.eval("(/\\X. \\x: X. x) [Bool -> Bool] (\\b: Bool. b)"),
+            "\\b: Bool. b : Bool -> Bool"
+        );
+        // the inner `Y` is renamed rather than capturing the argument
+        assert_eq!(
+            session.eval("/\\Y. (/\\X. /\\Y. \\f: X -> Y. f) [Y]"),
+            "/\\Y. (/\\X. /\\Y. \\f: X -> Y. f) [Y] : forall Y. forall Y1. (Y -> Y1) -> Y -> Y1"
+        );
+        assert!(session.exec("\\x: X. x").is_err());
+        assert!(session.exec("(\\x: Bool. x) [Bool]").is_err());
+    }
+}
@@ -0,0 +1,795 @@
+//! Property tests: random well-typed terms typecheck, keep their type as they step, never get
+//! stuck, and survive printing and parsing.
+//!
+//! Steps can drop the annotations bidirectional typing relies on: `(\x. x : Bool -> Bool) true`
+//! steps to `(\x. x) true`, whose function can't be synthesized. So preservation is checked on
+//! the elaboration of each term, with every binder annotated from its derivation. Terms that
+//! mention locations are typed against the types of the values in the store, and every term
+//! can use the constructors of `data Nat = Z | S Nat`.
+
+use std::sync::atomic::AtomicBool;
+
+use proptest::prelude::*;
+
+use crate::{
+    derivation::Derivation,
+    interpreter::{self, EvalLimits},
+    matching::{self, Select},
+    parser::{Constant, Expr, Parser, Pattern, Prim, Type},
+    scanner::Scanner,
+    typer::{alpha_eq, exn_type, substitute_expr, Typer},
+};
+
+/// Small types: `Bool` and functions between them.
+fn ty() -> impl Strategy<Value = Type> {
+    Just(Type::Bool).prop_recursive(3, 8, 2, |t| {
+        (t.clone(), t).prop_map(|(a, b)| Type::Fn(Box::new(a), Box::new(b)))
+    })
+}
+
+/// Closed terms of type `t`, paired with it.
+fn typed_term() -> impl Strategy<Value = (Expr, Type)> {
+    ty().prop_flat_map(|t| term(t.clone(), Vec::new(), 4).prop_map(move |e| (e, t.clone())))
+}
+
+/// Terms of type `t` in `context`. Every type has a term that doesn't need `depth`: a constant
+/// or an abstraction around a smaller type, so the recursion bottoms out.
+fn term(t: Type, context: Vec<(String, Type)>, depth: u32) -> BoxedStrategy<Expr> {
+    let mut leaves: Vec<BoxedStrategy<Expr>> = context
+        .iter()
+        .filter(|(_, t1)| t1 == &t)
+        .map(|(x, _)| Just(Expr::Var(x.clone())).boxed())
+        .collect();
+    match &t {
+        Type::Bool => {
+            leaves.push(Just(Expr::Constant(Constant::True)).boxed());
+            leaves.push(Just(Expr::Constant(Constant::False)).boxed());
+        }
+        Type::Fn(a, b) => {
+            // fresh names, so nothing is shadowed
+            let x = format!("x{}", context.len());
+            let mut inner = context.clone();
+            inner.push((x.clone(), *a.clone()));
+            let a = *a.clone();
+            let b = *b.clone();
+            leaves.push(
+                (
+                    lazy(move || term(b.clone(), inner.clone(), depth)),
+                    any::<bool>(),
+                )
+                    .prop_map(move |(e, annotated)| Expr::Abs {
+                        x: x.clone(),
+                        t: annotated.then(|| a.clone()),
+                        e: Box::new(e),
+                    })
+                    .prop_map({
+                        let t = t.clone();
+                        // unannotated abstractions can only be checked
+                        move |e| match &e {
+                            Expr::Abs { t: None, .. } => Expr::Ann {
+                                e: Box::new(e),
+                                t: t.clone(),
+                            },
+                            _ => e,
+                        }
+                    })
+                    .boxed(),
+            );
+        }
+        _ => unreachable!("only Bool and function types are generated"),
+    }
+    let leaf = proptest::strategy::Union::new(leaves).boxed();
+    if depth == 0 {
+        return leaf;
+    }
+
+    let app = {
+        let (t, context) = (t.clone(), context.clone());
+        ty().prop_flat_map(move |a| {
+            let f = Type::Fn(Box::new(a.clone()), Box::new(t.clone()));
+            (
+                term(f, context.clone(), depth - 1),
+                term(a, context.clone(), depth - 1),
+            )
+                .prop_map(|(e1, e2)| Expr::App {
+                    e1: Box::new(e1),
+                    e2: Box::new(e2),
+                })
+        })
+    };
+    let r#if = {
+        let (t, context) = (t.clone(), context.clone());
+        lazy(move || {
+            (
+                term(Type::Bool, context.clone(), depth - 1),
+                term(t.clone(), context.clone(), depth - 1),
+                term(t.clone(), context.clone(), depth - 1),
+            )
+                .prop_map(|(e1, e2, e3)| Expr::If {
+                    e1: Box::new(e1),
+                    e2: Box::new(e2),
+                    e3: Box::new(e3),
+                })
+                .boxed()
+        })
+    };
+    // (/\X. \y: X. y) [t] e
+    let poly = {
+        let (t, t1, context) = (t.clone(), t.clone(), context.clone());
+        lazy(move || term(t1.clone(), context.clone(), depth - 1)).prop_map(move |e| {
+            let id = Expr::TyAbs {
+                x: "X".into(),
+                e: Box::new(Expr::Abs {
+                    x: "y".into(),
+                    t: Some(Type::Var("X".into())),
+                    e: Box::new(Expr::Var("y".into())),
+                }),
+            };
+            Expr::App {
+                e1: Box::new(Expr::TyApp {
+                    e: Box::new(id),
+                    t: t.clone(),
+                }),
+                e2: Box::new(e),
+            }
+        })
+    };
+    // (\r: Ref t. (\u: Unit. !r) (r := e2)) (ref e1)
+    let reference = {
+        let (t, context) = (t.clone(), context.clone());
+        lazy(move || {
+            let t = t.clone();
+            (
+                term(t.clone(), context.clone(), depth - 1),
+                term(t.clone(), context.clone(), depth - 1),
+            )
+                .prop_map(move |(e1, e2)| {
+                    let r = || Box::new(Expr::Var("r".into()));
+                    let body = Expr::App {
+                        e1: Box::new(Expr::Abs {
+                            x: "u".into(),
+                            t: Some(Type::Unit),
+                            e: Box::new(Expr::Deref(r())),
+                        }),
+                        e2: Box::new(Expr::Assign {
+                            e1: r(),
+                            e2: Box::new(e2),
+                        }),
+                    };
+                    Expr::App {
+                        e1: Box::new(Expr::Abs {
+                            x: "r".into(),
+                            t: Some(Type::Ref(Box::new(t.clone()))),
+                            e: Box::new(body),
+                        }),
+                        e2: Box::new(Expr::Ref(Box::new(e1))),
+                    }
+                })
+                .boxed()
+        })
+    };
+    // (\y: U. unfold [U] y y) (fold [U] (\y: U. e)) where U = mu X. X -> t, the type that
+    // lets terms be applied to themselves
+    let recursive = {
+        let (t, t1, context) = (t.clone(), t.clone(), context.clone());
+        lazy(move || term(t1.clone(), context.clone(), depth - 1)).prop_map(move |e| {
+            let u = Type::Mu(
+                "X".into(),
+                Box::new(Type::Fn(
+                    Box::new(Type::Var("X".into())),
+                    Box::new(t.clone()),
+                )),
+            );
+            let y = || Box::new(Expr::Var("y".into()));
+            let abs = |e: Expr| Expr::Abs {
+                x: "y".into(),
+                t: Some(u.clone()),
+                e: Box::new(e),
+            };
+            Expr::App {
+                e1: Box::new(abs(Expr::App {
+                    e1: Box::new(Expr::Unfold {
+                        t: u.clone(),
+                        e: y(),
+                    }),
+                    e2: y(),
+                })),
+                e2: Box::new(Expr::Fold {
+                    t: u.clone(),
+                    e: Box::new(abs(e)),
+                }),
+            }
+        })
+    };
+    // foldr [t] [t] (\x: t. \y: t. if isnil [t] (tail [t] (cons [t] x (nil [t]))) then x else y)
+    //   e1 [head [t] [e2]]
+    let list = {
+        let (t, context) = (t.clone(), context.clone());
+        lazy(move || {
+            let term = |t: &Type| term(t.clone(), context.clone(), depth - 1);
+            let t = t.clone();
+            (term(&t), term(&t))
+                .prop_map(move |(e1, e2)| {
+                    let prim = |p: Prim, types: usize, args: Vec<Expr>| {
+                        let e = (0..types).fold(Expr::Prim(p), |e, _| Expr::TyApp {
+                            e: Box::new(e),
+                            t: t.clone(),
+                        });
+                        args.into_iter().fold(e, |e1, e2| Expr::App {
+                            e1: Box::new(e1),
+                            e2: Box::new(e2),
+                        })
+                    };
+                    let var = |x: &str| Expr::Var(x.into());
+                    let abs = |x: &str, e: Expr| Expr::Abs {
+                        x: x.into(),
+                        t: Some(t.clone()),
+                        e: Box::new(e),
+                    };
+                    let cons = prim(Prim::Cons, 1, vec![var("x"), prim(Prim::Nil, 1, vec![])]);
+                    let f = abs(
+                        "x",
+                        abs(
+                            "y",
+                            Expr::If {
+                                e1: Box::new(prim(
+                                    Prim::IsNil,
+                                    1,
+                                    vec![prim(Prim::Tail, 1, vec![cons])],
+                                )),
+                                e2: Box::new(var("x")),
+                                e3: Box::new(var("y")),
+                            },
+                        ),
+                    );
+                    let head = prim(Prim::Head, 1, vec![Expr::List(vec![e2])]);
+                    prim(Prim::Foldr, 2, vec![f, e1, Expr::List(vec![head])])
+                })
+                .boxed()
+        })
+    };
+    // match S (if c then Z else S Z) with | S (S n) -> e1 | x -> e2
+    let data = {
+        let (t, context) = (t.clone(), context.clone());
+        lazy(move || {
+            let term = |t: &Type| term(t.clone(), context.clone(), depth - 1);
+            (term(&Type::Bool), term(&t), term(&t))
+                .prop_map(|(c, e1, e2)| {
+                    let con = |c: &str| Expr::Con(c.into());
+                    let s = |e: Expr| Expr::App {
+                        e1: Box::new(con("S")),
+                        e2: Box::new(e),
+                    };
+                    let pattern = |c: &str, ps| Pattern::Con(c.into(), ps);
+                    Expr::Match {
+                        e: Box::new(s(Expr::If {
+                            e1: Box::new(c),
+                            e2: Box::new(con("Z")),
+                            e3: Box::new(s(con("Z"))),
+                        })),
+                        arms: vec![
+                            (
+                                pattern("S", vec![pattern("S", vec![Pattern::Var("n".into())])]),
+                                e1,
+                            ),
+                            (Pattern::Var("x".into()), e2),
+                        ],
+                    }
+                })
+                .boxed()
+        })
+    };
+    // if eq (concat "\"a" "b\n") "\"ab\n" then (\c: Char. e1) (char_at "\"a" (length "a"))
+    //   else e2
+    let string = {
+        let (t, context) = (t.clone(), context.clone());
+        lazy(move || {
+            let term = |t: &Type| term(t.clone(), context.clone(), depth - 1);
+            (term(&t), term(&t))
+                .prop_map(|(e1, e2)| {
+                    let prim = |p: Prim, args: Vec<Expr>| {
+                        args.into_iter().fold(Expr::Prim(p), |e1, e2| Expr::App {
+                            e1: Box::new(e1),
+                            e2: Box::new(e2),
+                        })
+                    };
+                    let string = |s: &str| Expr::Constant(Constant::String(s.into()));
+                    let c = prim(
+                        Prim::CharAt,
+                        vec![string("\"a"), prim(Prim::Length, vec![string("a")])],
+                    );
+                    Expr::If {
+                        e1: Box::new(prim(
+                            Prim::Eq,
+                            vec![
+                                prim(Prim::Concat, vec![string("\"a"), string("b\n")]),
+                                string("\"ab\n"),
+                            ],
+                        )),
+                        e2: Box::new(Expr::App {
+                            e1: Box::new(Expr::Abs {
+                                x: "c".into(),
+                                t: Some(Type::Char),
+                                e: Box::new(e1),
+                            }),
+                            e2: Box::new(c),
+                        }),
+                        e3: Box::new(e2),
+                    }
+                })
+                .boxed()
+        })
+    };
+    // try (if c then raise b else e1) with \y: Bool. e2
+    let exception = lazy(move || {
+        let term = |t: &Type| term(t.clone(), context.clone(), depth - 1);
+        (term(&Type::Bool), term(&exn_type()), term(&t), term(&t))
+            .prop_map(|(c, b, e1, e2)| Expr::Try {
+                e1: Box::new(Expr::If {
+                    e1: Box::new(c),
+                    e2: Box::new(Expr::Raise(Box::new(b))),
+                    e3: Box::new(e1),
+                }),
+                e2: Box::new(Expr::Abs {
+                    x: "y".into(),
+                    t: Some(exn_type()),
+                    e: Box::new(e2),
+                }),
+            })
+            .boxed()
+    });
+    prop_oneof![
+        2 => leaf,
+        2 => app,
+        1 => r#if,
+        1 => poly,
+        1 => reference,
+        1 => recursive,
+        1 => exception,
+        1 => list,
+        1 => data,
+        1 => string
+    ]
+    .boxed()
+}
+
+/// Builds the strategy from `f` only when a value is drawn from it, since building every
+/// branch of `term` up front takes time exponential in the depth.
+fn lazy(f: impl Fn() -> BoxedStrategy<Expr> + 'static) -> BoxedStrategy<Expr> {
+    Just(()).prop_flat_map(move |()| f()).boxed()
+}
+
+/// Call-by-name doesn't evaluate under `fold`, in lists or in the arguments of constructors, so
+/// `fold [T] e`, `[e]` and `C e` are values for any `e`.
+fn is_value(e: &Expr, by_value: bool) -> bool {
+    match e {
+        Expr::Constant(_)
+        | Expr::Abs { .. }
+        | Expr::TyAbs { .. }
+        | Expr::Loc(_)
+        | Expr::Prim(_)
+        | Expr::Con(_) => true,
+        Expr::Fold { t: _, e } => !by_value || is_value(e, by_value),
+        Expr::List(es) => !by_value || es.iter().all(|e| is_value(e, by_value)),
+        Expr::App { .. } => {
+            partial(e, by_value)
+                || matching::constructor(e).is_some_and(|(_, args)| {
+                    !by_value || args.into_iter().all(|e| is_value(e, by_value))
+                })
+        }
+        Expr::TyApp { .. } => partial(e, by_value),
+        _ => false,
+    }
+}
+
+/// Whether `e` is a primitive applied to fewer terms than it takes.
+fn partial(e: &Expr, by_value: bool) -> bool {
+    fn args(e: &Expr, by_value: bool) -> Option<(Prim, usize)> {
+        match e {
+            Expr::Prim(p) => Some((*p, 0)),
+            Expr::TyApp { e, t: _ } => args(e, by_value),
+            Expr::App { e1, e2 } if !by_value || is_value(e2, by_value) => {
+                args(e1, by_value).map(|(p, n)| (p, n + 1))
+            }
+            _ => None,
+        }
+    }
+    matches!(args(e, by_value), Some((p, n)) if n < p.arity())
+}
+
+/// How far from the end of the saturated primitive application `e` its first strict argument
+/// that isn't a value yet is.
+fn strict_arg(e: &Expr, by_value: bool) -> Option<usize> {
+    let (p, args) = interpreter::saturated(e)?;
+    let i = p
+        .strict()
+        .iter()
+        .find(|&&i| !is_value(&args[i], by_value))?;
+    Some(args.len() - 1 - i)
+}
+
+/// The argument `n` from the end of the application `e`, past any type applications.
+fn arg(e: &Expr, n: usize) -> &Expr {
+    match e {
+        Expr::TyApp { e, t: _ } => arg(e, n),
+        Expr::App { e1: _, e2 } if n == 0 => e2,
+        Expr::App { e1, e2: _ } => arg(e1, n - 1),
+        _ => unreachable!("`n` is within the arguments of `e`"),
+    }
+}
+
+/// [`arg`], mutably.
+fn arg_mut(e: &mut Expr, n: usize) -> &mut Expr {
+    match e {
+        Expr::TyApp { e, t: _ } => arg_mut(e, n),
+        Expr::App { e1: _, e2 } if n == 0 => e2,
+        Expr::App { e1, e2: _ } => arg_mut(e1, n - 1),
+        _ => unreachable!("`n` is within the arguments of `e`"),
+    }
+}
+
+/// The subterm of `e` that evaluates next, if it's one whose exceptions propagate out of `e`.
+fn active(e: &Expr, by_value: bool) -> Option<&Expr> {
+    let is_value = |e: &Expr| is_value(e, by_value);
+    match e {
+        Expr::App { e1, e2 } | Expr::Assign { e1, e2 } => match is_value(e1) {
+            false => Some(e1),
+            true if by_value || matches!(e, Expr::Assign { .. }) => Some(e2),
+            // the strict arguments of a primitive are always evaluated
+            true => strict_arg(e, by_value).map(|n| arg(e, n)),
+        },
+        Expr::List(es) if by_value => es.iter().find(|e| !is_value(e)),
+        // the subterm of the scrutinee that the decision tree tests next
+        Expr::Match { e, arms } if is_value(e) => match matching::compile(arms).select(e) {
+            Select::Force(path) => Some(matching::subterm(e, &path)),
+            _ => None,
+        },
+        Expr::Match { e, .. } => Some(e),
+        Expr::Fold { t: _, e } if by_value => Some(e),
+        Expr::TyApp { e, t: _ }
+        | Expr::If { e1: e, .. }
+        | Expr::Ref(e)
+        | Expr::Deref(e)
+        | Expr::Unfold { t: _, e }
+        | Expr::Raise(e) => Some(e),
+        _ => None,
+    }
+}
+
+/// One step of the reduction `interpreter::interpret` performs, or `None` if `e` is stuck or
+/// an uncaught `raise v`. Allocations are typed as they happen, extending `store_ty`.
+fn step(e: &Expr, by_value: bool, store: &mut Vec<Expr>, store_ty: &mut Vec<Type>) -> Option<Expr> {
+    if let Some(Expr::Raise(v)) = active(e, by_value) {
+        if is_value(v, by_value) {
+            return Some(Expr::Raise(v.clone()));
+        }
+    }
+    let mut step = |e: &Expr| step(e, by_value, store, store_ty).map(Box::new);
+    let is_value = |e: &Expr| is_value(e, by_value);
+    Some(match e {
+        Expr::App { e1, e2 } if !is_value(e1) => Expr::App {
+            e1: step(e1)?,
+            e2: e2.clone(),
+        },
+        Expr::App { e1, e2 } if by_value && !is_value(e2) => Expr::App {
+            e1: e1.clone(),
+            e2: step(e2)?,
+        },
+        Expr::App { e1, e2 } => match e1.as_ref() {
+            Expr::Abs { x, t: _, e } => interpreter::substitute(e, x, e2),
+            _ => match strict_arg(e, by_value) {
+                Some(n) => {
+                    let mut e = e.clone();
+                    let arg = arg_mut(&mut e, n);
+                    *arg = *step(arg)?;
+                    e
+                }
+                None => {
+                    let (p, args) = interpreter::saturated(e)?;
+                    interpreter::apply(p, args)?
+                }
+            },
+        },
+        Expr::TyApp { e, t } if !is_value(e) => Expr::TyApp {
+            e: step(e)?,
+            t: t.clone(),
+        },
+        Expr::TyApp { e, t } => match e.as_ref() {
+            Expr::TyAbs { x, e } => substitute_expr(e, x, t),
+            Expr::Prim(Prim::Nil) => Expr::List(Vec::new()),
+            _ => return None,
+        },
+        Expr::List(es) => {
+            let i = es.iter().position(|e| !is_value(e))?;
+            let mut es = es.clone();
+            es[i] = *step(&es[i])?;
+            Expr::List(es)
+        }
+        Expr::If { e1, e2, e3 } => match e1.as_ref() {
+            Expr::Constant(Constant::True) => *e2.clone(),
+            Expr::Constant(Constant::False) => *e3.clone(),
+            e1 => Expr::If {
+                e1: step(e1)?,
+                e2: e2.clone(),
+                e3: e3.clone(),
+            },
+        },
+        Expr::Ann { e, t: _ } => *e.clone(),
+        Expr::Ref(e) if !is_value(e) => Expr::Ref(step(e)?),
+        Expr::Ref(e) => {
+            store_ty.push(derive(e, store_ty).ty);
+            store.push(*e.clone());
+            Expr::Loc(store.len() - 1)
+        }
+        Expr::Deref(e) if !is_value(e) => Expr::Deref(step(e)?),
+        Expr::Deref(e) => match e.as_ref() {
+            Expr::Loc(l) => store[*l].clone(),
+            _ => return None,
+        },
+        Expr::Assign { e1, e2 } if !is_value(e1) => Expr::Assign {
+            e1: step(e1)?,
+            e2: e2.clone(),
+        },
+        Expr::Assign { e1, e2 } if !is_value(e2) => Expr::Assign {
+            e1: e1.clone(),
+            e2: step(e2)?,
+        },
+        Expr::Assign { e1, e2 } => match e1.as_ref() {
+            Expr::Loc(l) => {
+                store[*l] = *e2.clone();
+                Expr::Constant(Constant::Unit)
+            }
+            _ => return None,
+        },
+        Expr::Fold { t, e } if !is_value(e) => Expr::Fold {
+            t: t.clone(),
+            e: step(e)?,
+        },
+        Expr::Unfold { t, e } if !is_value(e) => Expr::Unfold {
+            t: t.clone(),
+            e: step(e)?,
+        },
+        Expr::Unfold { t: _, e } => match e.as_ref() {
+            Expr::Fold { t: _, e } => *e.clone(),
+            _ => return None,
+        },
+        Expr::Raise(e) if !is_value(e) => Expr::Raise(step(e)?),
+        Expr::Try { e1, e2: _ } if is_value(e1) => *e1.clone(),
+        Expr::Try { e1, e2 } => match e1.as_ref() {
+            Expr::Raise(v) if is_value(v) => Expr::App {
+                e1: e2.clone(),
+                e2: v.clone(),
+            },
+            e1 => Expr::Try {
+                e1: step(e1)?,
+                e2: e2.clone(),
+            },
+        },
+        Expr::Match { e, arms } if !is_value(e) => Expr::Match {
+            e: step(e)?,
+            arms: arms.clone(),
+        },
+        Expr::Match { e, arms } => match matching::compile(arms).select(e) {
+            Select::Arm(i, bindings) => bindings.iter().fold(arms[i].1.clone(), |body, (x, s)| {
+                interpreter::substitute(&body, x, s)
+            }),
+            Select::Force(path) => {
+                let mut e = e.clone();
+                let sub = matching::subterm_mut(&mut e, &path);
+                *sub = *step(sub)?;
+                Expr::Match {
+                    e,
+                    arms: arms.clone(),
+                }
+            }
+            Select::Fail => return None,
+        },
+        Expr::Var(_)
+        | Expr::Prim(_)
+        | Expr::Con(_)
+        | Expr::Hole(_)
+        | Expr::Raise(_)
+        | Expr::Constant(_)
+        | Expr::Abs { .. }
+        | Expr::TyAbs { .. }
+        | Expr::Loc(_)
+        | Expr::Fold { .. } => return None,
+    })
+}
+
+/// Typechecks `e` against the store typing `store_ty`.
+fn derive(e: &Expr, store_ty: &[Type]) -> Derivation {
+    let mut typer = Typer::default();
+    typer.store = store_ty.to_vec();
+    let nat = Type::Var("Nat".into());
+    typer
+        .declare("Nat", &[("Z".into(), vec![]), ("S".into(), vec![nat])])
+        .unwrap();
+    typer
+        .derive(e)
+        .unwrap_or_else(|err| panic!("`{}` doesn't typecheck: {}", e, err))
+}
+
+/// Annotates every abstraction in `d.expr` with the type of its binder.
+fn elaborate(d: &Derivation) -> Expr {
+    let premise = |i: usize| Box::new(elaborate(&d.premises[i]));
+    match &d.expr {
+        Expr::Var(_)
+        | Expr::Constant(_)
+        | Expr::Loc(_)
+        | Expr::Prim(_)
+        | Expr::Con(_)
+        | Expr::Hole(_) => d.expr.clone(),
+        Expr::List(_) => Expr::List(d.premises.iter().map(elaborate).collect()),
+        Expr::Match { e: _, arms } => Expr::Match {
+            e: premise(0),
+            arms: arms
+                .iter()
+                .zip(&d.premises[1..])
+                .map(|((p, _), d)| (p.clone(), elaborate(d)))
+                .collect(),
+        },
+        Expr::Abs { x, .. } => match &d.ty {
+            Type::Fn(a, _) => Expr::Abs {
+                x: x.clone(),
+                t: Some(*a.clone()),
+                e: premise(0),
+            },
+            _ => unreachable!("abstractions have function types"),
+        },
+        Expr::App { .. } => Expr::App {
+            e1: premise(0),
+            e2: premise(1),
+        },
+        Expr::If { .. } => Expr::If {
+            e1: premise(0),
+            e2: premise(1),
+            e3: premise(2),
+        },
+        Expr::Ann { e: _, t } => Expr::Ann {
+            e: premise(0),
+            t: t.clone(),
+        },
+        Expr::TyAbs { x, .. } => Expr::TyAbs {
+            x: x.clone(),
+            e: premise(0),
+        },
+        Expr::TyApp { e: _, t } => Expr::TyApp {
+            e: premise(0),
+            t: t.clone(),
+        },
+        Expr::Ref(_) => Expr::Ref(premise(0)),
+        Expr::Deref(_) => Expr::Deref(premise(0)),
+        Expr::Raise(_) => Expr::Raise(premise(0)),
+        Expr::Try { .. } => Expr::Try {
+            e1: premise(0),
+            e2: premise(1),
+        },
+        Expr::Fold { t, e: _ } => Expr::Fold {
+            t: t.clone(),
+            e: premise(0),
+        },
+        Expr::Unfold { t, e: _ } => Expr::Unfold {
+            t: t.clone(),
+            e: premise(0),
+        },
+        Expr::Assign { .. } => Expr::Assign {
+            e1: premise(0),
+            e2: premise(1),
+        },
+    }
+}
+
+/// Drops every annotation, so that elaborated and unelaborated terms can be compared.
+fn erase(e: &Expr) -> Expr {
+    let erase = |e: &Expr| Box::new(erase(e));
+    match e {
+        Expr::Var(_)
+        | Expr::Constant(_)
+        | Expr::Loc(_)
+        | Expr::Prim(_)
+        | Expr::Con(_)
+        | Expr::Hole(_) => e.clone(),
+        Expr::List(es) => Expr::List(es.iter().map(|e| *erase(e)).collect()),
+        Expr::Match { e, arms } => Expr::Match {
+            e: erase(e),
+            arms: arms.iter().map(|(p, e)| (p.clone(), *erase(e))).collect(),
+        },
+        Expr::Abs { x, t: _, e } => Expr::Abs {
+            x: x.clone(),
+            t: None,
+            e: erase(e),
+        },
+        Expr::App { e1, e2 } => Expr::App {
+            e1: erase(e1),
+            e2: erase(e2),
+        },
+        Expr::If { e1, e2, e3 } => Expr::If {
+            e1: erase(e1),
+            e2: erase(e2),
+            e3: erase(e3),
+        },
+        Expr::Ann { e, t: _ } => *erase(e),
+        Expr::TyAbs { x, e } => Expr::TyAbs {
+            x: x.clone(),
+            e: erase(e),
+        },
+        Expr::TyApp { e, t } => Expr::TyApp {
+            e: erase(e),
+            t: t.clone(),
+        },
+        Expr::Ref(e) => Expr::Ref(erase(e)),
+        Expr::Deref(e) => Expr::Deref(erase(e)),
+        Expr::Raise(e) => Expr::Raise(erase(e)),
+        Expr::Try { e1, e2 } => Expr::Try {
+            e1: erase(e1),
+            e2: erase(e2),
+        },
+        Expr::Fold { t, e } => Expr::Fold {
+            t: t.clone(),
+            e: erase(e),
+        },
+        Expr::Unfold { t, e } => Expr::Unfold {
+            t: t.clone(),
+            e: erase(e),
+        },
+        Expr::Assign { e1, e2 } => Expr::Assign {
+            e1: erase(e1),
+            e2: erase(e2),
+        },
+    }
+}
+
+proptest! {
+    #[test]
+    fn generated_terms_typecheck((e, t) in typed_term()) {
+        prop_assert!(alpha_eq(&derive(&e, &[]).ty, &t));
+        let elaborated = elaborate(&derive(&e, &[]));
+        prop_assert!(alpha_eq(&derive(&elaborated, &[]).ty, &t), "{}", elaborated);
+    }
+
+    #[test]
+    fn progress_and_preservation((e, t) in typed_term(), by_value in any::<bool>()) {
+        let mut e1 = elaborate(&derive(&e, &[]));
+        let (mut store, mut store_ty) = (Vec::new(), Vec::new());
+        for _ in 0..10_000 {
+            match step(&e1, by_value, &mut store, &mut store_ty) {
+                Some(e2) => {
+                    prop_assert!(
+                        alpha_eq(&derive(&e2, &store_ty).ty, &t),
+                        "`{}` steps to `{}`",
+                        e1,
+                        e2
+                    );
+                    // the store stays well-typed too
+                    for (v, t) in store.iter().zip(&store_ty) {
+                        prop_assert!(alpha_eq(&derive(v, &store_ty).ty, t), "{}", v);
+                    }
+                    e1 = e2;
+                }
+                None => {
+                    prop_assert!(is_value(&e1, by_value), "`{}` is stuck", e1);
+                    // the interpreter panics if it gets stuck
+                    let value = interpreter::interpret(
+                        &e,
+                        by_value,
+                        &EvalLimits::default(),
+                        &AtomicBool::new(false),
+                        &mut Vec::new(),
+                    )
+                    .unwrap();
+                    prop_assert_eq!(erase(&value), erase(&e1));
+                    return Ok(());
+                }
+            }
+        }
+        prop_assert!(false, "`{}` didn't reach a value", e);
+    }
+
+    #[test]
+    fn printing_round_trips((e, _) in typed_term(), width in 0..100usize) {
+        let printed = format!("{:width$}", e);
+        let parsed = Parser::parse(Scanner::scan(printed.clone()).unwrap()).unwrap();
+        prop_assert_eq!(parsed, e, "{}", printed);
+    }
+}
@@ -1,86 +1,1520 @@
-use std::collections::HashMap;
+use std::{
+    collections::{HashMap, HashSet},
+    fmt,
+};
 
-use anyhow::{anyhow, bail, Ok};
+use anyhow::{bail, Ok};
 
-use crate::parser::{Constant, Expr, Type};
+use crate::{
+    church,
+    derivation::{Derivation, Rule},
+    matching,
+    parser::{Constant, Expr, Pattern, Prim, Type},
+};
 
-#[derive(Default)]
+#[derive(Default, Clone)]
 pub struct Typer {
-    context: HashMap<String, Type>,
+    context: Context,
+    /// top-level definitions, which aren't part of the context shown in derivations
+    globals: HashMap<String, Type>,
+    /// type variables in scope, innermost last
+    type_vars: Vec<String>,
+    /// the types of the values in the store, indexed by location
+    pub store: Vec<Type>,
+    /// the constructors of each `data` type and the types of their arguments. A `data` type's
+    /// name is a type everywhere, like a type variable bound at the top level.
+    data: HashMap<String, Vec<(String, Vec<Type>)>>,
+    /// the context at the innermost binder a type error escaped from, if any
+    pub error_context: Option<Context>,
+    /// the holes in the term, in the order they were typed
+    pub holes: Vec<Hole>,
+    /// set on copies that try out variants of a term, which don't look for hints themselves
+    speculative: bool,
+}
+
+/// Why a term doesn't typecheck.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TypeError {
+    /// `x` isn't bound; `suggestion` is the closest name that is
+    UndeclaredVariable {
+        x: String,
+        suggestion: Option<String>,
+    },
+    UndeclaredConstructor {
+        c: String,
+        suggestion: Option<String>,
+    },
+    UnboundTypeVariable {
+        x: String,
+        t: Type,
+    },
+    UnknownLocation {
+        l: usize,
+    },
+    DataRedeclared {
+        name: String,
+    },
+    ConstructorRedeclared {
+        c: String,
+    },
+    /// `e` can only be checked: an unannotated abstraction, `[]` or a `raise`
+    CannotSynthesize {
+        e: Expr,
+    },
+    /// `e` has type `found` where `expected` was needed
+    Mismatch {
+        mode: Mode,
+        expected: Expected,
+        found: Type,
+        e: Expr,
+        hint: Option<Hint>,
+    },
+    /// the branches of the `if` `e` have different types
+    MismatchedBranches {
+        then: Type,
+        else_: Type,
+        e: Expr,
+    },
+    /// the abstraction or list `e` was checked against `expected`, a type of another form
+    MismatchedForm {
+        expected: Type,
+        e: Expr,
+    },
+    /// the abstraction `e` annotates its binder `x` differently from the `expected` type
+    MismatchedAnnotation {
+        x: String,
+        annotation: Type,
+        e: Expr,
+        expected: Type,
+    },
+    /// `p` matches values of the `data` type `name` but was matched against `expected`
+    MismatchedPattern {
+        p: Pattern,
+        name: String,
+        expected: Type,
+    },
+    TooManyPatterns {
+        c: String,
+        p: Pattern,
+    },
+    TooFewPatterns {
+        c: String,
+        p: Pattern,
+    },
+    BoundTwice {
+        x: String,
+    },
+    UnreachableArm {
+        p: Pattern,
+        e: Expr,
+    },
+    /// no arm of the `match` `e` matches the values `p` describes
+    MissingArm {
+        e: Expr,
+        p: Pattern,
+    },
+    NotRecursive {
+        t: Type,
+        e: Expr,
+    },
+}
+
+/// Which way the typer was going when a type didn't match.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Mode {
+    Checking,
+    Synthesis,
+}
+
+/// What a mismatched type should have been.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Expected {
+    Type(Type),
+    Function,
+    /// a function from `exn_type()`
+    Handler,
+    Polymorphic,
+    Reference,
+}
+
+/// A likely fix for a mismatch.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Hint {
+    /// `e1 e2` applied a non-function, but `e2 e1`, which is `e`, typechecks
+    Flipped { e: Expr },
+    /// `e1 e2` applied a non-function
+    NotAFunction { e1: Expr, e2: Expr },
+    /// swapping two arguments of an application gives `e`, which typechecks
+    Swapped { e: Expr },
+}
+
+impl TypeError {
+    /// The stable code that `--explain` looks up.
+    pub fn code(&self) -> &'static str {
+        match self {
+            TypeError::UndeclaredVariable { .. } => "E0201",
+            TypeError::UndeclaredConstructor { .. } => "E0202",
+            TypeError::MismatchedBranches { .. } => "E0203",
+            TypeError::UnboundTypeVariable { .. } => "E0204",
+            TypeError::Mismatch { expected, .. } => match expected {
+                Expected::Type(_) => "E0205",
+                Expected::Function => "E0206",
+                Expected::Polymorphic => "E0207",
+                Expected::Reference => "E0208",
+                Expected::Handler => "E0209",
+            },
+            TypeError::CannotSynthesize { .. } => "E0210",
+            TypeError::MismatchedForm { .. } => "E0211",
+            TypeError::MismatchedAnnotation { .. } => "E0212",
+            TypeError::MismatchedPattern { .. } => "E0213",
+            TypeError::TooManyPatterns { .. } | TypeError::TooFewPatterns { .. } => "E0214",
+            TypeError::BoundTwice { .. } => "E0215",
+            TypeError::UnreachableArm { .. } => "E0216",
+            TypeError::MissingArm { .. } => "E0217",
+            TypeError::NotRecursive { .. } => "E0218",
+            TypeError::DataRedeclared { .. } => "E0219",
+            TypeError::ConstructorRedeclared { .. } => "E0220",
+            TypeError::UnknownLocation { .. } => "E0221",
+        }
+    }
+}
+
+impl fmt::Display for TypeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let suggest = |f: &mut fmt::Formatter<'_>, suggestion: &Option<String>| match suggestion {
+            Some(y) => write!(f, "; did you mean `{}`?", y),
+            None => fmt::Result::Ok(()),
+        };
+        match self {
+            TypeError::UndeclaredVariable { x, suggestion } => {
+                write!(f, "undeclared variable `{}`", x)?;
+                suggest(f, suggestion)
+            }
+            TypeError::UndeclaredConstructor { c, suggestion } => {
+                write!(f, "undeclared constructor `{}`", c)?;
+                suggest(f, suggestion)
+            }
+            TypeError::UnboundTypeVariable { x, t } => {
+                write!(f, "unbound type variable `{}` in `{}`", x, t)
+            }
+            TypeError::UnknownLocation { l } => {
+                write!(f, "unknown location `{}`", Expr::Loc(*l))
+            }
+            TypeError::DataRedeclared { name } => write!(f, "`{}` is already declared", name),
+            TypeError::ConstructorRedeclared { c } => {
+                write!(f, "constructor `{}` is already declared", c)
+            }
+            TypeError::CannotSynthesize { e } => match e {
+                Expr::Abs { x, .. } => write!(
+                    f,
+                    "cannot synthesize the type of `{}` in `{}`; annotate it as `\\{}: T` or ascribe `(e : T)`",
+                    x, e, x
+                ),
+                Expr::List(_) => write!(
+                    f,
+                    "cannot synthesize the type of `[]`; ascribe it as `([] : List T)` or write `nil [T]`"
+                ),
+                e => write!(
+                    f,
+                    "cannot synthesize the type of `{}`, which has every type; ascribe it as `({} : T)`",
+                    e, e
+                ),
+            },
+            TypeError::Mismatch {
+                mode,
+                expected,
+                found,
+                e,
+                hint,
+            } => {
+                write!(
+                    f,
+                    "mismatch in {} mode: expected {}, found `{}` for `{}`",
+                    mode, expected, found, e
+                )?;
+                match hint {
+                    Some(hint) => write!(f, "\nhint: {}", hint),
+                    None => fmt::Result::Ok(()),
+                }
+            }
+            TypeError::MismatchedBranches { then, else_, e } => write!(
+                f,
+                "mismatched if branches: `then` has type `{}` but `else` has type `{}` in `{}`",
+                then, else_, e
+            ),
+            TypeError::MismatchedForm { expected, e } => write!(
+                f,
+                "mismatch in checking mode: expected `{}`, found {} `{}`",
+                expected,
+                match e {
+                    Expr::List(_) => "list",
+                    _ => "function",
+                },
+                e
+            ),
+            TypeError::MismatchedAnnotation {
+                x,
+                annotation,
+                e,
+                expected,
+            } => write!(
+                f,
+                "mismatch in checking mode: `{}` is annotated `{}` but `{}` is checked against `{}`",
+                x, annotation, e, expected
+            ),
+            TypeError::MismatchedPattern { p, name, expected } => write!(
+                f,
+                "mismatch in pattern: `{}` matches values of type `{}`, not `{}`",
+                p, name, expected
+            ),
+            TypeError::TooManyPatterns { c, p } => {
+                write!(f, "`{}` is applied to too many patterns in `{}`", c, p)
+            }
+            TypeError::TooFewPatterns { c, p } => {
+                write!(f, "`{}` is applied to too few patterns in `{}`", c, p)
+            }
+            TypeError::BoundTwice { x } => write!(f, "`{}` is bound twice in one pattern", x),
+            TypeError::UnreachableArm { p, e } => write!(
+                f,
+                "the arm `{}` of `{}` is unreachable; the arms above it match everything it does",
+                p, e
+            ),
+            TypeError::MissingArm { e, p } => write!(f, "no arm of `{}` matches `{}`", e, p),
+            TypeError::NotRecursive { t, e } => write!(
+                f,
+                "expected a recursive type `mu X. T`, found `{}` in `{}`",
+                t, e
+            ),
+        }
+    }
+}
+
+impl std::error::Error for TypeError {}
+
+impl fmt::Display for Mode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Mode::Checking => write!(f, "checking"),
+            Mode::Synthesis => write!(f, "synthesis"),
+        }
+    }
+}
+
+impl fmt::Display for Expected {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Expected::Type(t) => write!(f, "`{}`", t),
+            Expected::Function => write!(f, "a function"),
+            Expected::Handler => write!(f, "a handler `{} -> T`", exn_type()),
+            Expected::Polymorphic => write!(f, "a polymorphic type"),
+            Expected::Reference => write!(f, "a reference"),
+        }
+    }
+}
+
+impl fmt::Display for Hint {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Hint::Flipped { e } => {
+                write!(f, "only functions can be applied; did you mean `{}`?", e)
+            }
+            Hint::NotAFunction { e1, e2 } => write!(
+                f,
+                "`{}` isn't a function, so it can't be applied to `{}`; is an argument in the wrong place or a parenthesis missing?",
+                e1, e2
+            ),
+            Hint::Swapped { e } => {
+                write!(f, "the arguments look swapped; did you mean `{}`?", e)
+            }
+        }
+    }
+}
+
+/// A hole in a term, with the type it needs and the variables in scope where it is.
+#[derive(Clone, Debug)]
+pub struct Hole {
+    /// `None` for `_`
+    pub name: Option<String>,
+    /// the type the hole is checked against, or a placeholder `?name` if it's synthesized
+    pub ty: Type,
+    /// whether anything decided `ty`
+    pub checked: bool,
+    pub context: Context,
+}
+
+/// The variables bound by the abstractions and match arms around a term, as a stack with a
+/// frame for each binder. Leaving a binder pops its frame, bringing back what it shadowed.
+#[derive(Default, Clone, Debug)]
+pub struct Context {
+    frames: Vec<Vec<(String, Type)>>,
+}
+
+impl Context {
+    /// The frames, outermost first.
+    pub fn frames(&self) -> &[Vec<(String, Type)>] {
+        &self.frames
+    }
+
+    /// The type of the innermost binding of `x`.
+    fn lookup(&self, x: &str) -> Option<&Type> {
+        self.bindings().find(|(y, _)| y == x).map(|(_, t)| t)
+    }
+
+    /// The variables in scope, sorted by name.
+    fn visible(&self) -> Vec<(String, Type)> {
+        let mut visible: Vec<(String, Type)> = Vec::new();
+        for (x, t) in self.bindings() {
+            if !visible.iter().any(|(y, _)| y == x) {
+                visible.push((x.clone(), t.clone()));
+            }
+        }
+        visible.sort_by(|(x, _), (y, _)| x.cmp(y));
+        visible
+    }
+
+    /// Every binding, innermost first.
+    fn bindings(&self) -> impl Iterator<Item = &(String, Type)> {
+        self.frames
+            .iter()
+            .rev()
+            .flat_map(|frame| frame.iter().rev())
+    }
 }
 
 impl Typer {
+    /// Declares a top-level definition `x : t`.
+    pub fn define(&mut self, x: &str, t: Type) {
+        self.globals.insert(x.to_string(), t);
+    }
+
+    /// Declares `data name = C1 T1 T2 | C2`, giving the type of each constructor. The
+    /// arguments can mention `name` itself and any type declared before it.
+    pub fn declare(
+        &mut self,
+        name: &str,
+        constructors: &[(String, Vec<Type>)],
+    ) -> anyhow::Result<Vec<(String, Type)>> {
+        if self.data.contains_key(name) {
+            bail!(TypeError::DataRedeclared {
+                name: name.to_string()
+            });
+        }
+        for (i, (c, args)) in constructors.iter().enumerate() {
+            if constructors[..i].iter().any(|(c1, _)| c1 == c) || self.constructor(c).is_ok() {
+                bail!(TypeError::ConstructorRedeclared { c: c.clone() });
+            }
+            for t in args {
+                if let Some(x) = free_type_vars(t)
+                    .into_iter()
+                    .find(|x| x != name && !self.data.contains_key(x))
+                {
+                    bail!(TypeError::UnboundTypeVariable { x, t: t.clone() });
+                }
+            }
+        }
+        self.data.insert(name.to_string(), constructors.to_vec());
+        Ok(constructors
+            .iter()
+            .map(|(c, _)| (c.clone(), self.constructor(c).expect("just declared").1))
+            .collect())
+    }
+
     pub fn typecheck(&mut self, e: &Expr) -> anyhow::Result<Type> {
-        match e {
-            Expr::Var(x) => self.var(x),
-            Expr::Constant(c) => self.constant(c),
-            Expr::App { e1, e2 } => self.app(e1, e2),
-            Expr::Abs { x, t, e } => self.abs(x, t, e),
-            Expr::If { e1, e2, e3 } => self.r#if(e1, e2, e3),
+        Ok(self.derive(e)?.ty)
+    }
+
+    /// Typechecks `e`, recording the rule used at every step.
+    pub fn derive(&mut self, e: &Expr) -> anyhow::Result<Derivation> {
+        self.infer(e)
+    }
+
+    /// Synthesizes the type of `e`.
+    fn infer(&mut self, e: &Expr) -> anyhow::Result<Derivation> {
+        let context = self.context();
+        let (rule, premises, ty) = match e {
+            Expr::Var(x) => (Rule::Var, Vec::new(), self.var(x)?),
+            Expr::Constant(c) => {
+                let (rule, ty) = self.constant(c);
+                (rule, Vec::new(), ty)
+            }
+            Expr::App { e1, e2 } => {
+                let (premises, ty) = self.app(e1, e2).map_err(|err| self.swapped(e, err))?;
+                (Rule::App, premises, ty)
+            }
+            Expr::Abs { x, t: Some(t), e } => {
+                self.well_formed(t)?;
+                let (premise, ty) = self.abs(x, t, e)?;
+                (Rule::Abs, vec![premise], ty)
+            }
+            Expr::Abs { t: None, .. } => bail!(TypeError::CannotSynthesize { e: e.clone() }),
+            Expr::If { e1, e2, e3 } => {
+                let d1 = self.check(e1, &Type::Bool)?;
+                // `raise` has every type and a hole has whatever type it needs, so the other
+                // branch decides which
+                let (d2, d3) = if let Expr::Raise(_) | Expr::Hole(_) = **e2 {
+                    let d3 = self.infer(e3)?;
+                    (self.check(e2, &d3.ty)?, d3)
+                } else {
+                    let d2 = self.infer(e2)?;
+                    let d3 = self.check(e3, &d2.ty).map_err(|err| {
+                        match err.downcast_ref::<TypeError>() {
+                            // the `else` branch as a whole, not something inside it, is off
+                            Some(TypeError::Mismatch {
+                                found,
+                                e: e3_,
+                                hint: None,
+                                ..
+                            }) if e3_ == &**e3 => TypeError::MismatchedBranches {
+                                then: d2.ty.clone(),
+                                else_: found.clone(),
+                                e: e.clone(),
+                            }
+                            .into(),
+                            _ => err,
+                        }
+                    })?;
+                    (d2, d3)
+                };
+                let ty = d2.ty.clone();
+                (Rule::If, vec![d1, d2, d3], ty)
+            }
+            Expr::Hole(name) => {
+                let placeholder = name
+                    .clone()
+                    .unwrap_or_else(|| (self.holes.len() + 1).to_string());
+                let ty = Type::Var(format!("?{}", placeholder));
+                self.hole(name, &ty, false);
+                (Rule::Hole, Vec::new(), ty)
+            }
+            Expr::Prim(p) => (prim_rule(*p), Vec::new(), prim_type(*p)),
+            Expr::Con(c) => (Rule::Con, Vec::new(), self.constructor(c)?.1),
+            Expr::Match { e: e1, arms } => {
+                let d = self.infer(e1)?;
+                let (mut premises, ty) = self.arms(e, &d.ty, arms, None)?;
+                premises.insert(0, d);
+                (Rule::Match, premises, ty)
+            }
+            Expr::List(es) => {
+                let Some((first, rest)) = es.split_first() else {
+                    bail!(TypeError::CannotSynthesize { e: e.clone() });
+                };
+                let d = self.infer(first)?;
+                let t = d.ty.clone();
+                let mut premises = vec![d];
+                for e in rest {
+                    premises.push(self.check(e, &t)?);
+                }
+                (Rule::List, premises, Type::List(Box::new(t)))
+            }
+            Expr::Raise(_) => bail!(TypeError::CannotSynthesize { e: e.clone() }),
+            Expr::Try { e1, e2 } => {
+                let (d1, d2) = if let Expr::Raise(_) = **e1 {
+                    let d2 = self.infer(e2)?;
+                    let ty = match &d2.ty {
+                        Type::Fn(a, b) if alpha_eq(a, &exn_type()) => *b.clone(),
+                        _ => bail!(TypeError::Mismatch {
+                            mode: Mode::Synthesis,
+                            expected: Expected::Handler,
+                            found: d2.ty,
+                            e: *e2.clone(),
+                            hint: None
+                        }),
+                    };
+                    (self.check(e1, &ty)?, d2)
+                } else {
+                    let d1 = self.infer(e1)?;
+                    let handler = Type::Fn(Box::new(exn_type()), Box::new(d1.ty.clone()));
+                    let d2 = self.check(e2, &handler)?;
+                    (d1, d2)
+                };
+                let ty = d1.ty.clone();
+                (Rule::Try, vec![d1, d2], ty)
+            }
+            Expr::Ann { e, t } => {
+                self.well_formed(t)?;
+                (Rule::Ann, vec![self.check(e, t)?], t.clone())
+            }
+            Expr::TyAbs { x, e } => {
+                let (x, e) = self.bind_type_var(x, e);
+                let d = self.infer(&e)?;
+                self.type_vars.pop();
+                let ty = Type::Forall(x, Box::new(d.ty.clone()));
+                (Rule::TyAbs, vec![d], ty)
+            }
+            Expr::TyApp { e: e1, t } => {
+                self.well_formed(t)?;
+                let d = self.infer(e1)?;
+                let Type::Forall(x, body) = &d.ty else {
+                    bail!(TypeError::Mismatch {
+                        mode: Mode::Synthesis,
+                        expected: Expected::Polymorphic,
+                        found: d.ty,
+                        e: *e1.clone(),
+                        hint: None
+                    });
+                };
+                let ty = substitute(body, x, t);
+                (Rule::TyApp, vec![d], ty)
+            }
+            Expr::Ref(e) => {
+                let d = self.infer(e)?;
+                let ty = Type::Ref(Box::new(d.ty.clone()));
+                (Rule::Ref, vec![d], ty)
+            }
+            Expr::Deref(e) => {
+                let (d, ty) = self.reference(e)?;
+                (Rule::Deref, vec![d], ty)
+            }
+            Expr::Assign { e1, e2 } => {
+                let (d1, ty) = self.reference(e1)?;
+                let d2 = self.check(e2, &ty)?;
+                (Rule::Assign, vec![d1, d2], Type::Unit)
+            }
+            Expr::Loc(l) => {
+                let ty = self
+                    .store
+                    .get(*l)
+                    .ok_or(TypeError::UnknownLocation { l: *l })?;
+                (Rule::Loc, Vec::new(), Type::Ref(Box::new(ty.clone())))
+            }
+            Expr::Fold { t, e } => {
+                self.well_formed(t)?;
+                let d = self.check(e, &unroll(t, e)?)?;
+                (Rule::Fold, vec![d], t.clone())
+            }
+            Expr::Unfold { t, e } => {
+                self.well_formed(t)?;
+                let unrolled = unroll(t, e)?;
+                (Rule::Unfold, vec![self.check(e, t)?], unrolled)
+            }
+        };
+        Ok(Derivation {
+            rule,
+            context,
+            expr: e.clone(),
+            ty,
+            premises,
+        })
+    }
+
+    /// Checks `e` against `t`. Abstractions and conditionals push `t` inwards, so the binders
+    /// of checked abstractions don't need annotations; anything else is synthesized and
+    /// compared with `t`.
+    fn check(&mut self, e: &Expr, t: &Type) -> anyhow::Result<Derivation> {
+        let context = self.context();
+        let (rule, premises) = match (e, t) {
+            (Expr::Abs { x, t: a, e: body }, Type::Fn(dom, cod)) => {
+                if let Some(a) = a.as_ref().filter(|a| !alpha_eq(a, dom)) {
+                    bail!(TypeError::MismatchedAnnotation {
+                        x: x.clone(),
+                        annotation: a.clone(),
+                        e: e.clone(),
+                        expected: t.clone()
+                    });
+                }
+                let d = self.scoped(vec![(x.clone(), *dom.clone())], |this| {
+                    this.check(body, cod)
+                })?;
+                (Rule::Abs, vec![d])
+            }
+            (Expr::Abs { .. }, _) => bail!(TypeError::MismatchedForm {
+                expected: t.clone(),
+                e: e.clone()
+            }),
+            (Expr::If { e1, e2, e3 }, _) => {
+                let d1 = self.check(e1, &Type::Bool)?;
+                let d2 = self.check(e2, t)?;
+                let d3 = self.check(e3, t)?;
+                (Rule::If, vec![d1, d2, d3])
+            }
+            (Expr::Ref(e), Type::Ref(t)) => (Rule::Ref, vec![self.check(e, t)?]),
+            (Expr::List(es), Type::List(t)) => {
+                let premises = es
+                    .iter()
+                    .map(|e| self.check(e, t))
+                    .collect::<anyhow::Result<_>>()?;
+                (Rule::List, premises)
+            }
+            (Expr::List(_), _) => bail!(TypeError::MismatchedForm {
+                expected: t.clone(),
+                e: e.clone()
+            }),
+            (Expr::Raise(e), _) => (Rule::Raise, vec![self.check(e, &exn_type())?]),
+            (Expr::Match { e: e1, arms }, _) => {
+                let d = self.infer(e1)?;
+                let (mut premises, _) = self.arms(e, &d.ty, arms, Some(t))?;
+                premises.insert(0, d);
+                (Rule::Match, premises)
+            }
+            (Expr::Hole(name), _) => {
+                self.hole(name, t, true);
+                (Rule::Hole, Vec::new())
+            }
+            (Expr::Try { e1, e2 }, _) => {
+                let d1 = self.check(e1, t)?;
+                let handler = Type::Fn(Box::new(exn_type()), Box::new(t.clone()));
+                (Rule::Try, vec![d1, self.check(e2, &handler)?])
+            }
+            (Expr::TyAbs { x, e: body }, Type::Forall(y, t_body)) => {
+                let (x, body) = self.bind_type_var(x, body);
+                let d = self.check(&body, &substitute(t_body, y, &Type::Var(x)))?;
+                self.type_vars.pop();
+                (Rule::TyAbs, vec![d])
+            }
+            _ => {
+                let d = self.infer(e)?;
+                if !alpha_eq(&d.ty, t) {
+                    bail!(TypeError::Mismatch {
+                        mode: Mode::Checking,
+                        expected: Expected::Type(t.clone()),
+                        found: d.ty,
+                        e: e.clone(),
+                        hint: None
+                    });
+                }
+                return Ok(d);
+            }
+        };
+        Ok(Derivation {
+            rule,
+            context,
+            expr: e.clone(),
+            ty: t.clone(),
+            premises,
+        })
+    }
+
+    /// Brings the type variable bound by `/\x. e` into scope, renaming it if it would shadow
+    /// one that's already in scope or a `data` type, which patterns would mistake it for.
+    fn bind_type_var(&mut self, x: &String, e: &Expr) -> (String, Expr) {
+        let (x, e) = if self.type_vars.contains(x) || self.data.contains_key(x) {
+            let avoid = self
+                .type_vars
+                .iter()
+                .chain(self.data.keys())
+                .cloned()
+                .collect();
+            let x1 = fresh(x, &avoid);
+            let e = substitute_expr(e, x, &Type::Var(x1.clone()));
+            (x1, e)
+        } else {
+            (x.to_string(), e.clone())
+        };
+        self.type_vars.push(x.clone());
+        (x, e)
+    }
+
+    fn well_formed(&self, t: &Type) -> anyhow::Result<()> {
+        match free_type_vars(t)
+            .into_iter()
+            .find(|x| !self.type_vars.contains(x) && !self.data.contains_key(x))
+        {
+            Some(x) => bail!(TypeError::UnboundTypeVariable { x, t: t.clone() }),
+            None => Ok(()),
         }
     }
 
+    /// The variables in scope, sorted by name.
+    fn context(&self) -> Vec<(String, Type)> {
+        self.context.visible()
+    }
+
     fn var(&self, var: &String) -> anyhow::Result<Type> {
         Ok(self
             .context
-            .get(var)
-            .ok_or_else(|| anyhow!("undeclared variable `{}`", var))?
+            .lookup(var)
+            .or_else(|| self.globals.get(var))
+            .ok_or_else(|| TypeError::UndeclaredVariable {
+                x: var.clone(),
+                suggestion: suggest(
+                    var,
+                    self.context
+                        .bindings()
+                        .map(|(x, _)| x)
+                        .chain(self.globals.keys()),
+                ),
+            })?
             .clone())
     }
 
-    fn constant(&self, constant: &Constant) -> anyhow::Result<Type> {
+    fn constant(&self, constant: &Constant) -> (Rule, Type) {
         match constant {
-            Constant::True | Constant::False => Ok(Type::Bool),
+            Constant::True => (Rule::True, Type::Bool),
+            Constant::False => (Rule::False, Type::Bool),
+            Constant::Unit => (Rule::Unit, Type::Unit),
+            Constant::String(_) => (Rule::String, Type::String),
+            Constant::Char(_) => (Rule::Char, Type::Char),
         }
     }
 
-    fn app(&mut self, e1: &Expr, e2: &Expr) -> anyhow::Result<Type> {
-        if let Type::Fn(a, b) = self.typecheck(e1)? {
-            let t2 = self.typecheck(e2)?;
-            if a.as_ref() == &t2 {
-                Ok(*b)
-            } else {
-                bail!("invalid argument; expected type `{:?}`", a);
+    /// The `data` type that `c` constructs and the type of `c`, a function from its arguments
+    /// to that type.
+    fn constructor(&self, c: &str) -> anyhow::Result<(String, Type)> {
+        self.data
+            .iter()
+            .find_map(|(name, constructors)| {
+                let (_, args) = constructors.iter().find(|(c1, _)| c1 == c)?;
+                let ty = args.iter().rev().fold(Type::Var(name.clone()), |ty, arg| {
+                    Type::Fn(Box::new(arg.clone()), Box::new(ty))
+                });
+                Some((name.clone(), ty))
+            })
+            .ok_or_else(|| {
+                let constructors = self.data.values().flatten().map(|(c, _)| c);
+                TypeError::UndeclaredConstructor {
+                    c: c.to_string(),
+                    suggestion: suggest(c, constructors),
+                }
+                .into()
+            })
+    }
+
+    /// Types the arms of `e`, a `match` on a value of type `scrutinee`, checking them against
+    /// `t` if it's given and otherwise against the type of the first arm that isn't a `raise`.
+    /// Then checks that every value is matched by some arm, and that every arm matches some
+    /// value the arms above it don't.
+    fn arms(
+        &mut self,
+        e: &Expr,
+        scrutinee: &Type,
+        arms: &[(Pattern, Expr)],
+        t: Option<&Type>,
+    ) -> anyhow::Result<(Vec<Derivation>, Type)> {
+        let first = arms
+            .iter()
+            .position(|(_, body)| !matches!(body, Expr::Raise(_) | Expr::Hole(_)))
+            .unwrap_or(0);
+        let mut ty = t.cloned();
+        let mut premises = vec![None; arms.len()];
+        for i in std::iter::once(first).chain((0..arms.len()).filter(|&i| i != first)) {
+            let (p, body) = &arms[i];
+            let mut bindings = Vec::new();
+            self.pattern(p, scrutinee, &mut bindings)?;
+            let d = self.scoped(bindings, |this| match &ty {
+                Some(ty) => this.check(body, ty),
+                None => this.infer(body),
+            })?;
+            ty.get_or_insert_with(|| d.ty.clone());
+            premises[i] = Some(d);
+        }
+
+        let signature = |c: &str| {
+            self.data
+                .values()
+                .find(|constructors| constructors.iter().any(|(c1, _)| c1 == c))
+                .map_or_else(Vec::new, |constructors| {
+                    constructors
+                        .iter()
+                        .map(|(c, args)| (c.clone(), args.len()))
+                        .collect()
+                })
+        };
+        let patterns: Vec<_> = arms.iter().map(|(p, _)| p).collect();
+        if let Some(i) = matching::redundant(&patterns, &signature) {
+            bail!(TypeError::UnreachableArm {
+                p: patterns[i].clone(),
+                e: e.clone()
+            });
+        }
+        if let Some(p) = matching::missing(&patterns, &signature) {
+            bail!(TypeError::MissingArm { e: e.clone(), p });
+        }
+        let premises = premises.into_iter().map(|d| d.expect("typed")).collect();
+        Ok((premises, ty.expect("a match has arms")))
+    }
+
+    /// Checks that `p` matches values of type `t`, adding the variables it binds to `bindings`.
+    fn pattern(
+        &self,
+        p: &Pattern,
+        t: &Type,
+        bindings: &mut Vec<(String, Type)>,
+    ) -> anyhow::Result<()> {
+        match p {
+            Pattern::Var(x) => {
+                if bindings.iter().any(|(y, _)| y == x) {
+                    bail!(TypeError::BoundTwice { x: x.clone() });
+                }
+                bindings.push((x.clone(), t.clone()));
+            }
+            Pattern::Wildcard => {}
+            Pattern::Con(c, ps) => {
+                let (name, mut ty) = self.constructor(c)?;
+                if !alpha_eq(t, &Type::Var(name.clone())) {
+                    bail!(TypeError::MismatchedPattern {
+                        p: p.clone(),
+                        name,
+                        expected: t.clone()
+                    });
+                }
+                for q in ps {
+                    let Type::Fn(arg, rest) = ty else {
+                        bail!(TypeError::TooManyPatterns {
+                            c: c.clone(),
+                            p: p.clone()
+                        });
+                    };
+                    self.pattern(q, &arg, bindings)?;
+                    ty = *rest;
+                }
+                if let Type::Fn(..) = ty {
+                    bail!(TypeError::TooFewPatterns {
+                        c: c.clone(),
+                        p: p.clone()
+                    });
+                }
             }
+        }
+        Ok(())
+    }
+
+    /// Synthesizes the type `Ref T` of `e`, returning `T`.
+    fn reference(&mut self, e: &Expr) -> anyhow::Result<(Derivation, Type)> {
+        let d = self.infer(e)?;
+        match d.ty.clone() {
+            Type::Ref(t) => Ok((d, *t)),
+            _ => bail!(TypeError::Mismatch {
+                mode: Mode::Synthesis,
+                expected: Expected::Reference,
+                found: d.ty,
+                e: e.clone(),
+                hint: None
+            }),
+        }
+    }
+
+    fn app(&mut self, e1: &Expr, e2: &Expr) -> anyhow::Result<(Vec<Derivation>, Type)> {
+        let d1 = self.infer(e1)?;
+        if let Type::Fn(a, b) = d1.ty.clone() {
+            let d2 = self.check(e2, &a)?;
+            Ok((vec![d1, d2], *b))
         } else {
-            bail!("expected function type, got `{:?}`", e1);
+            let flipped = Expr::App {
+                e1: Box::new(e2.clone()),
+                e2: Box::new(e1.clone()),
+            };
+            let hint = match self.speculate(&flipped) {
+                true => Hint::Flipped { e: flipped },
+                false => Hint::NotAFunction {
+                    e1: e1.clone(),
+                    e2: e2.clone(),
+                },
+            };
+            bail!(TypeError::Mismatch {
+                mode: Mode::Synthesis,
+                expected: Expected::Function,
+                found: d1.ty,
+                e: e1.clone(),
+                hint: Some(hint)
+            });
+        }
+    }
+
+    /// Adds a hint to a mismatch in the application `e` if swapping two neighbouring
+    /// arguments makes it typecheck.
+    fn swapped(&self, e: &Expr, mut err: anyhow::Error) -> anyhow::Error {
+        let Some(TypeError::Mismatch {
+            hint: hint @ None, ..
+        }) = err.downcast_mut()
+        else {
+            return err;
+        };
+        let mut args = Vec::new();
+        let mut head = e;
+        while let Expr::App { e1, e2 } = head {
+            args.push(e2.as_ref());
+            head = e1;
+        }
+        args.reverse();
+        for i in 1..args.len() {
+            let mut swapped = args.clone();
+            swapped.swap(i - 1, i);
+            let e = swapped.into_iter().fold(head.clone(), |e1, e2| Expr::App {
+                e1: Box::new(e1),
+                e2: Box::new(e2.clone()),
+            });
+            if self.speculate(&e) {
+                *hint = Some(Hint::Swapped { e });
+                break;
+            }
+        }
+        err
+    }
+
+    /// Whether `e` typechecks in the current context. Always false on a copy that's already
+    /// speculating, so that looking for hints doesn't recurse.
+    fn speculate(&self, e: &Expr) -> bool {
+        if self.speculative {
+            return false;
+        }
+        let mut typer = self.clone();
+        typer.speculative = true;
+        typer.infer(e).is_ok()
+    }
+
+    fn abs(&mut self, x: &str, t: &Type, e: &Expr) -> anyhow::Result<(Derivation, Type)> {
+        let d = self.scoped(vec![(x.to_string(), t.clone())], |this| this.infer(e))?;
+        let ty = Type::Fn(Box::new(t.clone()), Box::new(d.ty.clone()));
+        Ok((d, ty))
+    }
+
+    fn hole(&mut self, name: &Option<String>, ty: &Type, checked: bool) {
+        self.holes.push(Hole {
+            name: name.clone(),
+            ty: ty.clone(),
+            checked,
+            context: self.context.clone(),
+        });
+    }
+
+    /// Runs `f` with `frame` bound on top of the context, popping it again even if `f` fails.
+    /// The first failure records the context it happened in.
+    fn scoped<T>(
+        &mut self,
+        frame: Vec<(String, Type)>,
+        f: impl FnOnce(&mut Self) -> anyhow::Result<T>,
+    ) -> anyhow::Result<T> {
+        self.context.frames.push(frame);
+        let result = f(self);
+        if result.is_err() && self.error_context.is_none() {
+            self.error_context = Some(self.context.clone());
+        }
+        self.context.frames.pop();
+        result
+    }
+}
+
+fn prim_rule(p: Prim) -> Rule {
+    match p {
+        Prim::Nil => Rule::Nil,
+        Prim::Cons => Rule::Cons,
+        Prim::IsNil => Rule::IsNil,
+        Prim::Head => Rule::Head,
+        Prim::Tail => Rule::Tail,
+        Prim::Foldr => Rule::Foldr,
+        Prim::Concat => Rule::Concat,
+        Prim::Length => Rule::Length,
+        Prim::Eq => Rule::Eq,
+        Prim::CharAt => Rule::CharAt,
+    }
+}
+
+/// `cons : forall X. X -> List X -> List X`, and so on. Lengths and indices of strings are
+/// Church numerals.
+pub fn prim_type(p: Prim) -> Type {
+    let var = |x: &str| Type::Var(x.to_string());
+    let list = |t: Type| Type::List(Box::new(t));
+    let func = |a: Type, b: Type| Type::Fn(Box::new(a), Box::new(b));
+    let forall = |x: &str, t: Type| Type::Forall(x.to_string(), Box::new(t));
+    let x = var("X");
+    match p {
+        Prim::Nil => forall("X", list(x)),
+        Prim::Cons => forall("X", func(x.clone(), func(list(x.clone()), list(x)))),
+        Prim::IsNil => forall("X", func(list(x), Type::Bool)),
+        Prim::Head => forall("X", func(list(x.clone()), x)),
+        Prim::Tail => forall("X", func(list(x.clone()), list(x))),
+        // foldr [X] [Y] f z [x1, x2] = f x1 (f x2 z)
+        Prim::Foldr => {
+            let y = var("Y");
+            forall(
+                "X",
+                forall(
+                    "Y",
+                    func(
+                        func(x.clone(), func(y.clone(), y.clone())),
+                        func(y.clone(), func(list(x), y)),
+                    ),
+                ),
+            )
         }
+        Prim::Concat => func(Type::String, func(Type::String, Type::String)),
+        Prim::Length => func(Type::String, church::numeral_type()),
+        Prim::Eq => func(Type::String, func(Type::String, Type::Bool)),
+        Prim::CharAt => func(Type::String, func(church::numeral_type(), Type::Char)),
     }
+}
+
+/// The type of the values exceptions carry: `raise e` needs `e : Bool`, and the handler of
+/// `try e1 with e2` is a function from it.
+pub fn exn_type() -> Type {
+    Type::Bool
+}
 
-    fn abs(&mut self, x: &String, t: &Type, e: &Expr) -> anyhow::Result<Type> {
-        self.context.insert(x.to_string(), t.clone());
-        let t2 = self.typecheck(e)?;
-        self.context.remove(x);
-        Ok(Type::Fn(Box::new(t.clone()), Box::new(t2.clone())))
+/// `S[X := mu X. S]` for `t = mu X. S`, the type of the terms that `fold [t]` takes and
+/// `unfold [t]` gives.
+fn unroll(t: &Type, e: &Expr) -> anyhow::Result<Type> {
+    match t {
+        Type::Mu(x, body) => Ok(substitute(body, x, t)),
+        _ => bail!(TypeError::NotRecursive {
+            t: t.clone(),
+            e: e.clone()
+        }),
     }
+}
+
+/// The name in `names` closest to `x`, if one is near enough to be what a typo of `x` meant.
+fn suggest<'a>(x: &str, names: impl Iterator<Item = &'a String>) -> Option<String> {
+    names
+        .map(|y| (edit_distance(x, y), y))
+        .filter(|(d, _)| *d <= (x.chars().count() / 3).max(1))
+        .min_by_key(|(d, y)| (*d, y.as_str()))
+        .map(|(_, y)| y.clone())
+}
 
-    fn r#if(&mut self, e1: &Expr, e2: &Expr, e3: &Expr) -> anyhow::Result<Type> {
-        if let Type::Bool = self.typecheck(e1)? {
-            let t2 = self.typecheck(e2)?;
-            let t3 = self.typecheck(e3)?;
-            if t2 == t3 {
-                Ok(t2)
+/// The number of single-character insertions, deletions, substitutions and swaps of
+/// neighbouring characters that turn `a` into `b`.
+fn edit_distance(a: &str, b: &str) -> usize {
+    let (a, b): (Vec<char>, Vec<char>) = (a.chars().collect(), b.chars().collect());
+    // d[i][j] is the distance between the first `i` chars of `a` and the first `j` of `b`
+    let mut d = vec![vec![0; b.len() + 1]; a.len() + 1];
+    for (i, row) in d.iter_mut().enumerate() {
+        row[0] = i;
+    }
+    d[0] = (0..=b.len()).collect();
+    for i in 1..=a.len() {
+        for j in 1..=b.len() {
+            let substituted = d[i - 1][j - 1] + usize::from(a[i - 1] != b[j - 1]);
+            d[i][j] = substituted.min(d[i - 1][j] + 1).min(d[i][j - 1] + 1);
+            if i > 1 && j > 1 && a[i - 1] == b[j - 2] && a[i - 2] == b[j - 1] {
+                d[i][j] = d[i][j].min(d[i - 2][j - 2] + 1);
+            }
+        }
+    }
+    d[a.len()][b.len()]
+}
+
+/// t[x := s], renaming `forall` and `mu` binders that would capture free variables of `s`
+pub fn substitute(t: &Type, x: &str, s: &Type) -> Type {
+    match t {
+        Type::Bool | Type::Unit | Type::String | Type::Char => t.clone(),
+        Type::Ref(t) => Type::Ref(Box::new(substitute(t, x, s))),
+        Type::List(t) => Type::List(Box::new(substitute(t, x, s))),
+        Type::Var(y) if y == x => s.clone(),
+        Type::Var(_) => t.clone(),
+        Type::Fn(a, b) => Type::Fn(Box::new(substitute(a, x, s)), Box::new(substitute(b, x, s))),
+        Type::Forall(y, _) | Type::Mu(y, _) if y == x => t.clone(),
+        Type::Forall(y, b) | Type::Mu(y, b) => {
+            let binder = |y: String, b: Type| match t {
+                Type::Forall(..) => Type::Forall(y, Box::new(b)),
+                _ => Type::Mu(y, Box::new(b)),
+            };
+            let fv_s = free_type_vars(s);
+            if fv_s.contains(y) {
+                let mut avoid = fv_s;
+                avoid.extend(free_type_vars(b));
+                let y1 = fresh(y, &avoid);
+                let b = substitute(b, y, &Type::Var(y1.clone()));
+                binder(y1, substitute(&b, x, s))
             } else {
-                bail!("mismatched `if` branches `{:?}` and `{:?}`", t2, t3);
+                binder(y.to_string(), substitute(b, x, s))
+            }
+        }
+    }
+}
+
+/// e[x := s] for a type variable `x`: substitutes into every annotation in `e`.
+pub fn substitute_expr(e: &Expr, x: &str, s: &Type) -> Expr {
+    let sub = |e: &Expr| Box::new(substitute_expr(e, x, s));
+    match e {
+        Expr::Var(_)
+        | Expr::Constant(_)
+        | Expr::Loc(_)
+        | Expr::Prim(_)
+        | Expr::Con(_)
+        | Expr::Hole(_) => e.clone(),
+        Expr::List(es) => Expr::List(es.iter().map(|e| substitute_expr(e, x, s)).collect()),
+        Expr::Match { e, arms } => Expr::Match {
+            e: sub(e),
+            arms: arms
+                .iter()
+                .map(|(p, e)| (p.clone(), substitute_expr(e, x, s)))
+                .collect(),
+        },
+        Expr::App { e1, e2 } => Expr::App {
+            e1: sub(e1),
+            e2: sub(e2),
+        },
+        Expr::Ref(e) => Expr::Ref(sub(e)),
+        Expr::Raise(e) => Expr::Raise(sub(e)),
+        Expr::Try { e1, e2 } => Expr::Try {
+            e1: sub(e1),
+            e2: sub(e2),
+        },
+        Expr::Deref(e) => Expr::Deref(sub(e)),
+        Expr::Assign { e1, e2 } => Expr::Assign {
+            e1: sub(e1),
+            e2: sub(e2),
+        },
+        Expr::Abs { x: y, t, e } => Expr::Abs {
+            x: y.to_string(),
+            t: t.as_ref().map(|t| substitute(t, x, s)),
+            e: sub(e),
+        },
+        Expr::Ann { e, t } => Expr::Ann {
+            e: sub(e),
+            t: substitute(t, x, s),
+        },
+        Expr::If { e1, e2, e3 } => Expr::If {
+            e1: sub(e1),
+            e2: sub(e2),
+            e3: sub(e3),
+        },
+        Expr::TyAbs { x: y, .. } if y == x => e.clone(),
+        Expr::TyAbs { x: y, e: body } => {
+            let fv_s = free_type_vars(s);
+            if fv_s.contains(y) {
+                let mut avoid = fv_s;
+                avoid.extend(annotation_type_vars(body));
+                avoid.insert(x.to_string());
+                let y1 = fresh(y, &avoid);
+                let body = substitute_expr(body, y, &Type::Var(y1.clone()));
+                Expr::TyAbs {
+                    x: y1,
+                    e: Box::new(substitute_expr(&body, x, s)),
+                }
+            } else {
+                Expr::TyAbs {
+                    x: y.to_string(),
+                    e: sub(body),
+                }
             }
-        } else {
-            bail!("expected boolean");
         }
+        Expr::TyApp { e, t } => Expr::TyApp {
+            e: sub(e),
+            t: substitute(t, x, s),
+        },
+        Expr::Fold { t, e } => Expr::Fold {
+            t: substitute(t, x, s),
+            e: sub(e),
+        },
+        Expr::Unfold { t, e } => Expr::Unfold {
+            t: substitute(t, x, s),
+            e: sub(e),
+        },
     }
 }
 
+/// The type variables free in the annotations of `e`, outside any `/\` that binds them.
+fn annotation_type_vars(e: &Expr) -> HashSet<String> {
+    let mut vars = HashSet::new();
+    let mut add = |es: &[&Expr], ts: &[&Type]| {
+        for e in es {
+            vars.extend(annotation_type_vars(e));
+        }
+        for t in ts {
+            vars.extend(free_type_vars(t));
+        }
+    };
+    match e {
+        Expr::Var(_)
+        | Expr::Constant(_)
+        | Expr::Loc(_)
+        | Expr::Prim(_)
+        | Expr::Con(_)
+        | Expr::Hole(_) => {}
+        Expr::List(es) => add(&es.iter().collect::<Vec<_>>(), &[]),
+        Expr::Match { e, arms } => {
+            add(&[e], &[]);
+            add(&arms.iter().map(|(_, e)| e).collect::<Vec<_>>(), &[]);
+        }
+        Expr::App { e1, e2 } | Expr::Try { e1, e2 } | Expr::Assign { e1, e2 } => {
+            add(&[e1, e2], &[])
+        }
+        Expr::Ref(e) | Expr::Raise(e) | Expr::Deref(e) => add(&[e], &[]),
+        Expr::Abs { t, e, .. } => add(&[e], &t.iter().collect::<Vec<_>>()),
+        Expr::Ann { e, t } | Expr::TyApp { e, t } | Expr::Fold { t, e } | Expr::Unfold { t, e } => {
+            add(&[e], &[t])
+        }
+        Expr::If { e1, e2, e3 } => add(&[e1, e2, e3], &[]),
+        Expr::TyAbs { x, e } => {
+            let mut body = annotation_type_vars(e);
+            body.remove(x);
+            vars.extend(body);
+        }
+    }
+    vars
+}
+
+pub fn free_type_vars(t: &Type) -> HashSet<String> {
+    match t {
+        Type::Bool | Type::Unit | Type::String | Type::Char => HashSet::new(),
+        Type::Ref(t) | Type::List(t) => free_type_vars(t),
+        Type::Var(x) => HashSet::from([x.to_string()]),
+        Type::Fn(a, b) => {
+            let mut vars = free_type_vars(a);
+            vars.extend(free_type_vars(b));
+            vars
+        }
+        Type::Forall(x, b) | Type::Mu(x, b) => {
+            let mut vars = free_type_vars(b);
+            vars.remove(x);
+            vars
+        }
+    }
+}
+
+/// Equality up to renaming of `forall` binders.
+pub fn alpha_eq(t1: &Type, t2: &Type) -> bool {
+    fn eq<'a>(t1: &'a Type, t2: &'a Type, env: &mut Vec<(&'a String, &'a String)>) -> bool {
+        match (t1, t2) {
+            (Type::Bool, Type::Bool)
+            | (Type::Unit, Type::Unit)
+            | (Type::String, Type::String)
+            | (Type::Char, Type::Char) => true,
+            (Type::Ref(t1), Type::Ref(t2)) | (Type::List(t1), Type::List(t2)) => eq(t1, t2, env),
+            (Type::Var(x), Type::Var(y)) => {
+                match env.iter().rev().find(|(a, b)| *a == x || *b == y) {
+                    Some((a, b)) => *a == x && *b == y,
+                    None => x == y,
+                }
+            }
+            (Type::Fn(a1, b1), Type::Fn(a2, b2)) => eq(a1, a2, env) && eq(b1, b2, env),
+            (Type::Forall(x, b1), Type::Forall(y, b2)) | (Type::Mu(x, b1), Type::Mu(y, b2)) => {
+                env.push((x, y));
+                let result = eq(b1, b2, env);
+                env.pop();
+                result
+            }
+            _ => false,
+        }
+    }
+    eq(t1, t2, &mut Vec::new())
+}
+
+/// Just add 1s until we have a new variable name!
+fn fresh(x: &str, avoid: &HashSet<String>) -> String {
+    let mut name = format!("{x}1");
+    while avoid.contains(&name) {
+        name = format!("{name}1");
+    }
+    name
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::{
+        interpreter::EvalError,
+        parser::{Parser, Statement},
+        scanner::Scanner,
+        session::Session,
+    };
 
     #[test]
     fn works() {
         let e = Expr::Abs {
             x: "x".into(),
-            t: Type::Bool,
+            t: Some(Type::Bool),
             e: Box::new(Expr::Var("x".into())),
         };
         let ty = Typer::default().typecheck(&e).unwrap();
         assert_eq!(ty, Type::Fn(Box::new(Type::Bool), Box::new(Type::Bool)));
     }
+
+    #[test]
+    fn bidirectional() {
+        let typecheck = |s: &str| {
+            let e = Parser::parse(Scanner::scan(s.to_string()).unwrap()).unwrap();
+            Typer::default().typecheck(&e)
+        };
+        let bool_to_bool = Type::Fn(Box::new(Type::Bool), Box::new(Type::Bool));
+        // checked against the parameter type of `f`
+        assert_eq!(
+            typecheck("(\\f: Bool -> Bool. f true) (\\x. x)").unwrap(),
+            Type::Bool
+        );
+        assert_eq!(typecheck("(\\x. x : Bool -> Bool)").unwrap(), bool_to_bool);
+        assert_eq!(
+            typecheck("(\\x. if x then \\y. y else \\y. x : Bool -> Bool -> Bool)").unwrap(),
+            Type::Fn(Box::new(Type::Bool), Box::new(bool_to_bool))
+        );
+
+        let err = typecheck("\\x. x").unwrap_err().to_string();
+        assert!(err.starts_with("cannot synthesize"), "{err}");
+        let err = typecheck("(\\x: Bool -> Bool. x : Bool -> Bool)")
+            .unwrap_err()
+            .to_string();
+        assert!(err.contains("checking mode"), "{err}");
+        let err = typecheck("(\\x: Bool. x) true true")
+            .unwrap_err()
+            .to_string();
+        assert!(err.contains("synthesis mode"), "{err}");
+    }
+
+    #[test]
+    fn shadowing() {
+        let parse = |s: &str| Parser::parse(Scanner::scan(s.to_string()).unwrap()).unwrap();
+        let bool_to_bool = Type::Fn(Box::new(Type::Bool), Box::new(Type::Bool));
+        // the outer `x` is back in scope after the inner abstraction
+        assert_eq!(
+            Typer::default()
+                .typecheck(&parse("\\x: Bool. (\\x: Bool -> Bool. x) (\\y: Bool. x)"))
+                .unwrap(),
+            Type::Fn(Box::new(Type::Bool), Box::new(bool_to_bool.clone()))
+        );
+
+        let mut typer = Typer::default();
+        let e = parse("\\x: Bool. \\f: Bool -> Bool. match unit with | y -> \\x: Unit. f x");
+        assert!(typer.typecheck(&e).is_err());
+        let context = typer.error_context.unwrap();
+        assert_eq!(
+            context.visible(),
+            vec![
+                ("f".into(), bool_to_bool),
+                ("x".into(), Type::Unit),
+                ("y".into(), Type::Unit)
+            ]
+        );
+        assert_eq!(
+            context.to_string(),
+            "x : Bool  (shadowed)\nf : Bool -> Bool\ny : Unit\nx : Unit"
+        );
+        // the frames are popped on the way out
+        assert!(typer.context.frames().is_empty());
+    }
+
+    #[test]
+    fn type_substitution_avoids_annotations() {
+        let parse = |s: &str| Parser::parse(Scanner::scan(s.to_string()).unwrap()).unwrap();
+        // `X` has to be renamed past `X1`, which is already free in the body
+        let e = parse("/\\X. \\z: X1. \\w: Y. z");
+        assert_eq!(
+            substitute_expr(&e, "Y", &Type::Var("X".into())).to_string(),
+            "/\\X11. \\z: X1. \\w: X. z"
+        );
+    }
+
+    #[test]
+    fn type_variables_shadowing_data() {
+        let parse = |s: &str| Scanner::scan(s.to_string()).unwrap();
+        let mut typer = Typer::default();
+        let Statement::Data { name, constructors } =
+            Parser::parse_statement(parse("data Tree = Leaf | Node Tree Bool Tree")).unwrap()
+        else {
+            panic!("expected a data declaration");
+        };
+        typer.declare(&name, &constructors).unwrap();
+        // the bound `Tree` is renamed, so it's `Bool` rather than the data type inside
+        let e = Parser::parse(parse(
+            "(/\\Tree. \\x: Tree. match x with | Leaf -> false | Node l b r -> b) [Bool] true",
+        ))
+        .unwrap();
+        let err = typer.typecheck(&e).unwrap_err();
+        assert!(
+            matches!(
+                err.downcast_ref::<TypeError>(),
+                Some(TypeError::MismatchedPattern { .. })
+            ),
+            "{err}"
+        );
+        let e = Parser::parse(parse("(/\\Tree. \\x: Tree. x) [Tree] Leaf")).unwrap();
+        assert_eq!(typer.typecheck(&e).unwrap(), Type::Var("Tree".into()));
+    }
+
+    #[test]
+    fn hints() {
+        let typecheck = |s: &str| {
+            let e = Parser::parse(Scanner::scan(s.to_string()).unwrap()).unwrap();
+            let err = Typer::default().typecheck(&e).unwrap_err();
+            err.downcast::<TypeError>().unwrap()
+        };
+        assert_eq!(edit_distance("flag", "flga"), 1);
+        assert_eq!(edit_distance("kitten", "sitting"), 3);
+        assert_eq!(
+            typecheck("\\flag: Bool. \\x: Bool. flga"),
+            TypeError::UndeclaredVariable {
+                x: "flga".into(),
+                suggestion: Some("flag".into())
+            }
+        );
+        assert_eq!(
+            typecheck("\\flag: Bool. zzz"),
+            TypeError::UndeclaredVariable {
+                x: "zzz".into(),
+                suggestion: None
+            }
+        );
+
+        let hint = |s: &str| match typecheck(s) {
+            TypeError::Mismatch { hint, .. } => hint.map(|hint| hint.to_string()),
+            err => panic!("not a mismatch: {err}"),
+        };
+        assert_eq!(
+            hint("\\x: Bool. \\f: Bool -> Bool. x f").unwrap(),
+            "only functions can be applied; did you mean `f x`?"
+        );
+        assert!(hint("\\x: Bool. x x")
+            .unwrap()
+            .starts_with("`x` isn't a function"));
+        assert_eq!(
+            hint("\\f: Bool -> Unit -> Bool. f unit true").unwrap(),
+            "the arguments look swapped; did you mean `f true unit`?"
+        );
+        assert_eq!(hint("\\f: Bool -> Bool -> Bool. f unit true"), None);
+    }
+
+    #[test]
+    fn recursive_types() {
+        let mut session = Session::default();
+        assert_eq!(
+            session.eval("\\x: mu L. Bool -> L. unfold [mu L. Bool -> L] x"),
+            "\\x: mu L. Bool -> L. unfold [mu L. Bool -> L] x : \
+             (mu L. Bool -> L) -> Bool -> mu L. Bool -> L"
+        );
+        // a fixed point combinator, which the simply typed calculus can't type without `mu`
+        session
+            .exec(
+                "fix = \\f: (Bool -> Bool) -> Bool -> Bool. \
+                 (\\x: mu X. X -> Bool -> Bool. f (unfold [mu X. X -> Bool -> Bool] x x)) \
+                 (fold [mu X. X -> Bool -> Bool] \
+                 (\\x: mu X. X -> Bool -> Bool. f (unfold [mu X. X -> Bool -> Bool] x x)))",
+            )
+            .unwrap();
+        assert_eq!(
+            session.eval("fix (\\f: Bool -> Bool. \\b: Bool. if b then f false else true) true"),
+            "true : Bool"
+        );
+        assert!(session.exec("fold [Bool] true").is_err());
+        assert!(session.exec("unfold [mu X. X -> Bool] true").is_err());
+    }
+
+    #[test]
+    fn holes() {
+        let mut session = Session::with_prelude();
+        let holes = |session: &Session| {
+            session
+                .holes()
+                .iter()
+                .map(|hole| hole.to_string())
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(
+            session.eval("\\x: Bool. \\f: Bool -> Bool. f (if x then ?left else _)"),
+            "\\x: Bool. \\f: Bool -> Bool. f if x then ?left else _ : \
+             Bool -> (Bool -> Bool) -> Bool"
+        );
+        assert_eq!(
+            holes(&session),
+            [
+                "?left : Bool\n  x : Bool\n  f : Bool -> Bool",
+                "_ : Bool\n  x : Bool\n  f : Bool -> Bool"
+            ]
+        );
+        assert_eq!(session.eval("\\x: Bool. _"), "\\x: Bool. _ : Bool -> ?1");
+        assert_eq!(
+            holes(&session),
+            ["_ : ?1, which nothing constrains; ascribe it as `(_ : T)`\n  x : Bool"]
+        );
+        // call-by-name never reaches the hole
+        assert_eq!(session.eval("(\\x: Bool. true) ?unused"), "true : Bool");
+        assert_eq!(
+            session.exec("iszero ?n").unwrap_err().downcast_ref(),
+            Some(&EvalError::Hole {
+                name: Some("n".into())
+            })
+        );
+        assert_eq!(holes(&session), ["?n : forall X. (X -> X) -> X -> X"]);
+        assert!(session.exec("g = \\x: Bool. _").is_err());
+        assert!(session.exec("?").is_err());
+    }
 }